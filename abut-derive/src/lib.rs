@@ -0,0 +1,294 @@
+//! Macros for declaring `abut` message protocols.
+//!
+//! Use them through the `derive` feature of `abut`, which re-exports them from
+//! `abut::router` and `abut::schema`. Generated code names `::abut` paths, so the `abut` crate
+//! must be a direct dependency under that name.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{ToTokens, format_ident, quote};
+use syn::{
+    Data, DeriveInput, Fields, FnArg, GenericArgument, GenericParam, Ident, ItemTrait, LitInt, PathArguments,
+    ReturnType, TraitItem, Type, parse_macro_input, parse_quote, spanned::Spanned,
+};
+
+/// Implements `abut::router::Message` from `#[message(id = N)]` or
+/// `#[message(id = N, version = V)]`.
+#[proc_macro_derive(Message, attributes(message))]
+pub fn derive_message(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_message(input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+fn expand_message(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let mut id: Option<u16> = None;
+    let mut version: u16 = 1;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("message")) {
+        attr.parse_nested_meta(|meta| {
+            let value: LitInt = meta.value()?.parse()?;
+            if meta.path.is_ident("id") {
+                let parsed = value.base10_parse::<u16>()?;
+                if parsed == u16::MAX {
+                    return Err(syn::Error::new(value.span(), "message ID 0xffff is reserved"));
+                }
+                id = Some(parsed);
+            } else if meta.path.is_ident("version") {
+                version = value.base10_parse()?;
+            } else {
+                return Err(meta.error("expected `id` or `version`"));
+            }
+            Ok(())
+        })?;
+    }
+    let id = id.ok_or_else(|| syn::Error::new(input.ident.span(), "missing #[message(id = ..)]"))?;
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::abut::router::Message for #name #ty_generics #where_clause {
+            const ID: u16 = #id;
+            const VERSION: u16 = #version;
+        }
+    })
+}
+
+/// Implements `abut::schema::Schema` from the names and types of the fields
+/// and variants. Every field type must implement `Schema` too.
+#[proc_macro_derive(Schema)]
+pub fn derive_schema(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_schema(input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+/// The fingerprint expression of one set of fields.
+fn fields_shape(fields: &Fields) -> proc_macro2::TokenStream {
+    let types = fields.iter().map(|f| &f.ty);
+    let (kind, parts) = match fields {
+        Fields::Named(named) => {
+            let names = named.named.iter().map(|f| f.ident.as_ref().expect("named field").to_string());
+            ("struct", quote! { #(::abut::schema::name(#names), <#types as ::abut::schema::Schema>::FINGERPRINT),* })
+        }
+        Fields::Unnamed(_) => ("tuple_struct", quote! { #(<#types as ::abut::schema::Schema>::FINGERPRINT),* }),
+        Fields::Unit => ("unit", quote! {}),
+    };
+    quote! { ::abut::schema::shape(#kind, &[#parts]) }
+}
+
+fn expand_schema(mut input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let fingerprint = match &input.data {
+        Data::Struct(data) => fields_shape(&data.fields),
+        Data::Enum(data) => {
+            let variants = data.variants.iter().map(|v| {
+                let name = v.ident.to_string();
+                let fields = fields_shape(&v.fields);
+                quote! { ::abut::schema::name(#name), #fields }
+            });
+            quote! { ::abut::schema::shape("enum", &[#(#variants),*]) }
+        }
+        Data::Union(u) => return Err(syn::Error::new(u.union_token.span(), "unions have no schema")),
+    };
+
+    let params: Vec<Ident> = input
+        .generics
+        .params
+        .iter()
+        .filter_map(|p| match p {
+            GenericParam::Type(t) => Some(t.ident.clone()),
+            _ => None,
+        })
+        .collect();
+    let where_clause = input.generics.make_where_clause();
+    for param in params {
+        where_clause.predicates.push(parse_quote! { #param: ::abut::schema::Schema });
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::abut::schema::Schema for #name #ty_generics #where_clause {
+            const FINGERPRINT: u64 = #fingerprint;
+        }
+    })
+}
+
+/// Turns a trait of message handlers into a protocol.
+///
+/// Every method takes `&mut self` and one message, and returns
+/// `Result<Reply, E>` with `E: Into<AbutError>`; a `()` reply makes the
+/// message a one-way event. The trait gains:
+///
+/// * `MESSAGES`, the `(id, version)` of every message it mentions;
+/// * `router::<C>()`, a `Router<C, Self>` dispatching to the methods;
+///
+/// and a `<Trait>Client<C, R, W>` stub is generated next to it with one
+/// method per message, taking it by reference. A request type handled by two
+/// methods is an error, and so are two requests, or two replies, with the same
+/// ID.
+#[proc_macro_attribute]
+pub fn protocol(args: TokenStream, input: TokenStream) -> TokenStream {
+    if !args.is_empty() {
+        let err = syn::Error::new(Span::call_site(), "#[protocol] takes no arguments");
+        return err.into_compile_error().into();
+    }
+    let input = parse_macro_input!(input as ItemTrait);
+    expand_protocol(input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+struct Method {
+    name: Ident,
+    message: Type,
+    /// `None` for events.
+    reply: Option<Type>,
+}
+
+fn parse_method(item: &TraitItem) -> syn::Result<Option<Method>> {
+    let TraitItem::Fn(f) = item else { return Ok(None) };
+    let sig = &f.sig;
+    let mut inputs = sig.inputs.iter();
+    match inputs.next() {
+        Some(FnArg::Receiver(r)) if r.reference.is_some() && r.mutability.is_some() => {}
+        _ => return Err(syn::Error::new(sig.span(), "protocol methods take `&mut self`")),
+    }
+    let message = match (inputs.next(), inputs.next()) {
+        (Some(FnArg::Typed(arg)), None) => (*arg.ty).clone(),
+        _ => return Err(syn::Error::new(sig.inputs.span(), "protocol methods take exactly one message")),
+    };
+    if !sig.generics.params.is_empty() {
+        return Err(syn::Error::new(sig.generics.span(), "protocol methods cannot be generic"));
+    }
+
+    let reply = result_ok_type(&sig.output)
+        .ok_or_else(|| syn::Error::new(sig.output.span(), "protocol methods return `Result<Reply, E>`"))?;
+    let reply = match reply {
+        Type::Tuple(t) if t.elems.is_empty() => None,
+        other => Some(other.clone()),
+    };
+    Ok(Some(Method { name: sig.ident.clone(), message, reply }))
+}
+
+fn result_ok_type(output: &ReturnType) -> Option<&Type> {
+    let ReturnType::Type(_, ty) = output else { return None };
+    let Type::Path(path) = &**ty else { return None };
+    let last = path.path.segments.last()?;
+    if last.ident != "Result" {
+        return None;
+    }
+    let PathArguments::AngleBracketed(args) = &last.arguments else { return None };
+    match args.args.first()? {
+        GenericArgument::Type(ty) => Some(ty),
+        _ => None,
+    }
+}
+
+fn expand_protocol(mut input: ItemTrait) -> syn::Result<proc_macro2::TokenStream> {
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new(input.generics.span(), "protocol traits cannot be generic"));
+    }
+    let mut methods: Vec<Method> = Vec::new();
+    for item in &input.items {
+        let Some(method) = parse_method(item)? else { continue };
+        let key = method.message.to_token_stream().to_string();
+        if let Some(other) = methods.iter().find(|m| m.message.to_token_stream().to_string() == key) {
+            let msg = format!("`{key}` is already handled by `{}`", other.name);
+            return Err(syn::Error::new(method.message.span(), msg));
+        }
+        methods.push(method);
+    }
+
+    // Requests are distinct by now; a reply type may answer several of them.
+    let requests: Vec<&Type> = methods.iter().map(|m| &m.message).collect();
+    let mut replies: Vec<&Type> = Vec::new();
+    for ty in methods.iter().filter_map(|m| m.reply.as_ref()) {
+        let key = ty.to_token_stream().to_string();
+        if !replies.iter().any(|seen| seen.to_token_stream().to_string() == key) {
+            replies.push(ty);
+        }
+    }
+    // Every distinct message type, requests and replies alike.
+    let mut messages: Vec<&Type> = Vec::new();
+    for ty in methods.iter().flat_map(|m| std::iter::once(&m.message).chain(&m.reply)) {
+        let key = ty.to_token_stream().to_string();
+        if !messages.iter().any(|seen| seen.to_token_stream().to_string() == key) {
+            messages.push(ty);
+        }
+    }
+
+    let routes = methods.iter().map(|Method { name, message, reply }| match reply {
+        Some(_) => quote! {
+            .on_request(|s: &mut Self, m: #message| s.#name(m).map(::core::option::Option::Some).map_err(::core::convert::Into::into))
+        },
+        None => quote! {
+            .on(|s: &mut Self, m: #message| s.#name(m).map_err(::core::convert::Into::into))
+        },
+    });
+    input.items.push(parse_quote! {
+        /// `(id, version)` of every message in this protocol.
+        const MESSAGES: &'static [(u16, u16)] = &[
+            #((<#messages as ::abut::router::Message>::ID, <#messages as ::abut::router::Message>::VERSION)),*
+        ];
+    });
+    input.items.push(parse_quote! {
+        /// A router dispatching each message to its method.
+        fn router<C: ::abut::Codec + 'static>() -> ::abut::router::Router<C, Self>
+        where
+            Self: Sized + 'static,
+        {
+            ::abut::router::Router::new() #(#routes)*
+        }
+    });
+
+    let vis = &input.vis;
+    let client = format_ident!("{}Client", input.ident);
+    let calls = methods.iter().map(|Method { name, message, reply }| match reply {
+        Some(reply) => quote! {
+            pub fn #name(&mut self, msg: &#message) -> ::core::result::Result<#reply, ::abut::AbutError> {
+                self.0.call(msg)
+            }
+        },
+        None => quote! {
+            pub fn #name(&mut self, msg: &#message) -> ::core::result::Result<(), ::abut::AbutError> {
+                self.0.send(msg)
+            }
+        },
+    });
+    let doc = format!("Calling side of the [`{}`] protocol.", input.ident);
+
+    Ok(quote! {
+        #input
+
+        const _: () = ::abut::router::assert_unique_ids(&[
+            #(<#requests as ::abut::router::Message>::ID),*
+        ]);
+        const _: () = ::abut::router::assert_unique_ids(&[
+            #(<#replies as ::abut::router::Message>::ID),*
+        ]);
+
+        #[doc = #doc]
+        #vis struct #client<C, R: ::std::io::Read, W: ::std::io::Write>(pub ::abut::router::Caller<C, R, W>);
+
+        impl<C: ::abut::Codec, R: ::std::io::Read, W: ::std::io::Write> #client<C, R, W> {
+            pub fn new(reader: ::abut::frame::FramedReader<R>, writer: ::abut::frame::FramedWriter<W>) -> Self {
+                Self(::abut::router::Caller::new(reader, writer))
+            }
+
+            #(#calls)*
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_type_handled_twice_is_rejected() {
+        let input: ItemTrait = parse_quote! {
+            trait Twice {
+                fn first(&mut self, req: Add) -> Result<Sum, E>;
+                fn second(&mut self, req: Add) -> Result<(), E>;
+            }
+        };
+        let err = expand_protocol(input).unwrap_err();
+        assert_eq!(err.to_string(), "`Add` is already handled by `first`");
+    }
+}
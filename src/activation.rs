@@ -0,0 +1,252 @@
+//! systemd socket activation (`sd_listen_fds`).
+//!
+//! A socket unit passes already-bound listeners to the service starting at
+//! fd 3, described by `LISTEN_PID`, `LISTEN_FDS` and `LISTEN_FDNAMES`. The
+//! listeners picked up here are plain `UnixListener`s, the same type
+//! [`Server::bind`](crate::server::Server::bind) produces.
+
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::AbutError;
+
+/// First fd passed by the service manager.
+pub const LISTEN_FDS_START: RawFd = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketKind {
+    Stream,
+    SeqPacket,
+}
+
+/// One validated listening Unix socket handed over by systemd.
+#[derive(Debug)]
+pub struct ActivatedSocket {
+    fd: OwnedFd,
+    name: String,
+    kind: SocketKind,
+}
+
+impl ActivatedSocket {
+    /// The `FileDescriptorName=` of the socket unit (`unknown` if unset).
+    pub fn name(&self) -> &str { &self.name }
+    pub fn kind(&self) -> SocketKind { self.kind }
+    pub fn into_fd(self) -> OwnedFd { self.fd }
+
+    /// Converts a stream socket into a listener; seqpacket sockets are refused
+    /// because the framed layers assume byte-stream semantics.
+    pub fn into_listener(self) -> Result<UnixListener, AbutError> {
+        match self.kind {
+            SocketKind::Stream => Ok(UnixListener::from(self.fd)),
+            SocketKind::SeqPacket => Err(invalid(format!("socket {:?} is SOCK_SEQPACKET, not SOCK_STREAM", self.name))),
+        }
+    }
+}
+
+fn invalid(msg: String) -> AbutError {
+    io::Error::new(io::ErrorKind::InvalidInput, msg).into()
+}
+
+fn sockopt(fd: RawFd, opt: libc::c_int) -> io::Result<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: `value` and `len` are valid for writes and sized for an int option.
+    let rc = unsafe { libc::getsockopt(fd, libc::SOL_SOCKET, opt, (&mut value as *mut libc::c_int).cast(), &mut len) };
+    if rc != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value)
+}
+
+fn validate(fd: RawFd, name: &str) -> Result<SocketKind, AbutError> {
+    let domain = sockopt(fd, libc::SO_DOMAIN).map_err(|e| invalid(format!("fd {fd} ({name}) is not a socket: {e}")))?;
+    if domain != libc::AF_UNIX {
+        return Err(invalid(format!("fd {fd} ({name}) is not an AF_UNIX socket")));
+    }
+    let kind = match sockopt(fd, libc::SO_TYPE)? {
+        libc::SOCK_STREAM => SocketKind::Stream,
+        libc::SOCK_SEQPACKET => SocketKind::SeqPacket,
+        other => return Err(invalid(format!("fd {fd} ({name}) has unsupported socket type {other}"))),
+    };
+    if sockopt(fd, libc::SO_ACCEPTCONN)? == 0 {
+        return Err(invalid(format!("fd {fd} ({name}) is not listening")));
+    }
+    Ok(kind)
+}
+
+/// Validates `count` fds starting at `start` and takes ownership of them.
+fn collect(start: RawFd, count: usize, names: Option<&str>) -> Result<Vec<ActivatedSocket>, AbutError> {
+    let mut names = names.map(|n| n.split(':').map(str::to_owned).collect::<Vec<_>>()).unwrap_or_default();
+    names.resize(count, "unknown".to_owned());
+
+    let fds: Vec<RawFd> = (0..count).map(|i| start + i as RawFd).collect();
+    let kinds = fds.iter().zip(&names).map(|(&fd, name)| validate(fd, name)).collect::<Result<Vec<_>, _>>()?;
+
+    Ok(fds
+        .into_iter()
+        .zip(names)
+        .zip(kinds)
+        .map(|((fd, name), kind)| {
+            // SAFETY: the service manager passed these fds to this process and
+            // `validate` confirmed each is an open socket.
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+            // SAFETY: F_SETFD on an fd we own.
+            unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) };
+            ActivatedSocket { fd, name, kind }
+        })
+        .collect())
+}
+
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Sockets collected but not yet handed out.
+static SOCKETS: Mutex<Vec<ActivatedSocket>> = Mutex::new(Vec::new());
+
+/// Locks the cache, collecting the passed fds into it on first use.
+fn load() -> Result<MutexGuard<'static, Vec<ActivatedSocket>>, AbutError> {
+    let mut cache = SOCKETS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if TAKEN.load(Ordering::SeqCst) {
+        return Ok(cache);
+    }
+    let Ok(pid) = std::env::var("LISTEN_PID") else { return Ok(cache) };
+    if pid.parse::<u32>().ok() != Some(std::process::id()) {
+        return Ok(cache);
+    }
+    let fds = std::env::var("LISTEN_FDS").unwrap_or_default();
+    let count: usize = fds.parse().map_err(|_| invalid(format!("LISTEN_FDS={fds:?} is not a count")))?;
+    if count > 0 {
+        cache.extend(collect(LISTEN_FDS_START, count, std::env::var("LISTEN_FDNAMES").ok().as_deref())?);
+        TAKEN.store(true, Ordering::SeqCst);
+    }
+    Ok(cache)
+}
+
+/// Picks up the sockets passed by systemd.
+///
+/// Returns an empty list when the process was not socket-activated or the
+/// variables are addressed to another process (`LISTEN_PID` mismatch). The
+/// fds can be taken only once per process: later calls, and calls after
+/// [`listener`], return only the sockets not yet handed out.
+pub fn listen_fds() -> Result<Vec<ActivatedSocket>, AbutError> {
+    Ok(std::mem::take(&mut *load()?))
+}
+
+/// Removes the socket named `name` from `sockets`, or the only one if `name` is `None`.
+fn take(sockets: &mut Vec<ActivatedSocket>, name: Option<&str>) -> Result<Option<ActivatedSocket>, AbutError> {
+    match name {
+        Some(name) => Ok(sockets.iter().position(|s| s.name() == name).map(|i| sockets.remove(i))),
+        None if sockets.len() > 1 => Err(invalid(format!("{} sockets passed, a name is required", sockets.len()))),
+        None => Ok(sockets.pop()),
+    }
+}
+
+/// The activated stream listener named `name`, or the only one if `name` is `None`.
+///
+/// The other sockets stay open for later calls.
+pub fn listener(name: Option<&str>) -> Result<Option<UnixListener>, AbutError> {
+    take(&mut *load()?, name)?.map(ActivatedSocket::into_listener).transpose()
+}
+
+/// Uses the activated listener if there is one, otherwise binds `path` as usual.
+pub fn listener_or_bind(name: Option<&str>, path: impl AsRef<Path>) -> Result<UnixListener, AbutError> {
+    match listener(name)? {
+        Some(listener) => Ok(listener),
+        None => Ok(UnixListener::bind(path)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::fd::IntoRawFd;
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn test_collect_validates_and_names() {
+        let path = std::env::temp_dir().join(format!("abut-activation-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let fd = UnixListener::bind(&path).unwrap().into_raw_fd();
+
+        let mut sockets = collect(fd, 1, Some("control")).unwrap();
+        let socket = sockets.pop().unwrap();
+        assert_eq!(socket.name(), "control");
+        assert_eq!(socket.kind(), SocketKind::Stream);
+
+        let listener = socket.into_listener().unwrap();
+        let _client = UnixStream::connect(&path).unwrap();
+        listener.accept().unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_collect_rejects_non_listening() {
+        let (a, _b) = UnixStream::pair().unwrap();
+        assert!(collect(a.as_raw_fd(), 1, None).is_err());
+    }
+
+    /// Removes the socket files when the test ends, however it ends.
+    struct Paths(Vec<std::path::PathBuf>);
+
+    impl Drop for Paths {
+        fn drop(&mut self) {
+            for path in &self.0 {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    /// Duplicates `fds` onto free consecutive fds, as the service manager
+    /// lays them out, returning the first.
+    fn consecutive(fds: &[OwnedFd]) -> RawFd {
+        let mut start = 1000;
+        'retry: loop {
+            let mut placed = Vec::new();
+            for (i, fd) in fds.iter().enumerate() {
+                // SAFETY: F_DUPFD_CLOEXEC on an fd we own; the result is owned by `placed`.
+                let dup = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_DUPFD_CLOEXEC, start + i as RawFd) };
+                assert!(dup >= 0, "{}", io::Error::last_os_error());
+                // SAFETY: `dup` was just created and nothing else owns it.
+                placed.push(unsafe { OwnedFd::from_raw_fd(dup) });
+                if dup != start + i as RawFd {
+                    // Something holds that fd: start again above it.
+                    start = dup + 1;
+                    continue 'retry;
+                }
+            }
+            let raw: Vec<RawFd> = placed.into_iter().map(IntoRawFd::into_raw_fd).collect();
+            return raw[0];
+        }
+    }
+
+    #[test]
+    fn test_take_by_name_keeps_the_rest_open() {
+        let paths = Paths(
+            ["a", "b"]
+                .iter()
+                .map(|n| std::env::temp_dir().join(format!("abut-activation-{}-{n}.sock", std::process::id())))
+                .collect(),
+        );
+        let listeners: Vec<OwnedFd> = paths
+            .0
+            .iter()
+            .map(|path| {
+                let _ = std::fs::remove_file(path);
+                UnixListener::bind(path).unwrap().into()
+            })
+            .collect();
+
+        let mut sockets = collect(consecutive(&listeners), 2, Some("a:b")).unwrap();
+        drop(listeners);
+        assert!(take(&mut sockets, None).is_err());
+        assert_eq!(take(&mut sockets, Some("b")).unwrap().unwrap().name(), "b");
+        assert!(take(&mut sockets, Some("b")).unwrap().is_none());
+
+        let listener = take(&mut sockets, None).unwrap().unwrap().into_listener().unwrap();
+        let _client = UnixStream::connect(&paths.0[0]).unwrap();
+        listener.accept().unwrap();
+    }
+}
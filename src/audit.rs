@@ -0,0 +1,443 @@
+#![cfg(feature = "audit")]
+
+//! Tamper-evident log of the frames crossing a boundary (`audit` feature).
+//!
+//! [`AuditLog`] appends one line per frame: sequence number, timestamp,
+//! direction, peer credentials, message ID, size and the SHA-256 of the frame
+//! (never its content). Every line ends with the SHA-256 of the previous
+//! line's hash followed by the rest of this line, so changing, removing or
+//! reordering a line breaks the chain from there on, which [`verify`] reports.
+//!
+//! An inbound frame the reader consumed but did not deliver (too large, or
+//! refused while decompressing) gets a `drop` line instead of `in`, with no
+//! message ID, its length prefix, and the hash of its bytes as received, or
+//! `-` if they were drained without being kept.
+//!
+//! Cutting lines off the end leaves a valid, shorter chain. To catch that,
+//! keep [`AuditLog::head`] somewhere the log's writer cannot change and check
+//! the file against it with [`verify_against`]. The `abut-audit` binary does
+//! both from the command line.
+//!
+//! ```text
+//! <seq> <unix_ns> <in|out|drop> <pid> <uid> <gid> <msg_id|-> <len> <frame_sha256|-> <chain_sha256>
+//! ```
+
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use sha2::{Digest, Sha256};
+
+use crate::frame::{Dropped, FrameObserver};
+use crate::router::message_id;
+use crate::{AbutCode, AbutError, Direction, PeerCred};
+
+/// Chain hash that the first entry builds on.
+pub const GENESIS: [u8; 32] = [0; 32];
+
+/// How far a log extends: its entry count and the chain hash of its last entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub entries: u64,
+    pub head: [u8; 32],
+}
+
+impl Default for Checkpoint {
+    fn default() -> Self {
+        Self { entries: 0, head: GENESIS }
+    }
+}
+
+/// Formats as `<entries>:<head hex>`, the form [`FromStr`] accepts.
+impl fmt::Display for Checkpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.entries, hex(&self.head))
+    }
+}
+
+impl FromStr for Checkpoint {
+    type Err = AbutError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || AbutError::protocol(format_args!("bad checkpoint {s:?}, expected <entries>:<sha256 hex>"));
+        let (entries, head) = s.split_once(':').ok_or_else(bad)?;
+        Ok(Self { entries: entries.parse().map_err(|_| bad())?, head: unhex(head).ok_or_else(bad)? })
+    }
+}
+
+fn hex(bytes: &[u8; 32]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unhex(s: &str) -> Option<[u8; 32]> {
+    if s.len() != 64 || !s.is_ascii() {
+        return None;
+    }
+    let mut out = [0u8; 32];
+    for (byte, pair) in out.iter_mut().zip(s.as_bytes().chunks_exact(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(out)
+}
+
+fn chain(prev: &[u8; 32], body: &str) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(prev);
+    hasher.update(body.as_bytes());
+    hasher.finalize().into()
+}
+
+fn corrupt(line: u64, msg: impl fmt::Display) -> AbutError {
+    AbutError::new(AbutCode::AuditCorrupt).ctx(format_args!("line {line}: {msg}"))
+}
+
+struct Chain {
+    /// Holds the lock taken by [`AuditLog::open`] until the last clone is dropped.
+    file: File,
+    head: Checkpoint,
+    torn: bool,
+}
+
+/// An append-only audit log file, shared by every connection that records to it.
+#[derive(Clone)]
+pub struct AuditLog {
+    chain: Arc<Mutex<Chain>>,
+}
+
+impl AuditLog {
+    /// Opens the log at `path`, creating it if needed.
+    ///
+    /// An existing log is verified first and extended from its last entry;
+    /// a broken one is refused with `AbutCode::AuditCorrupt`. A last line
+    /// cut short (the writing process died mid-write) carries no chain hash
+    /// yet: if it reads as the start of the next entry it is cut off the file
+    /// and reported by [`torn`](Self::torn). Anything else after the last
+    /// entry, or a file without a single complete entry, is refused rather
+    /// than cut.
+    ///
+    /// The file stays locked until every clone of the log is dropped; opening
+    /// it again meanwhile, from this process or another, fails with
+    /// `AbutCode::Io`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, AbutError> {
+        let mut file = OpenOptions::new().create(true).read(true).append(true).open(path)?;
+        lock(&file)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let (head, valid) = scan(&bytes, |_| {})?;
+        let torn = valid < bytes.len();
+        if torn {
+            let next = head.entries + 1;
+            if valid == 0 {
+                return Err(corrupt(next, "no complete entry; not an audit log?"));
+            }
+            if !torn_entry(&bytes[valid..], next) {
+                return Err(corrupt(next, "unrecognised data after the last entry"));
+            }
+            file.set_len(valid as u64)?;
+        }
+        Ok(Self { chain: Arc::new(Mutex::new(Chain { file, head, torn })) })
+    }
+
+    /// Whether [`open`](Self::open) found and removed a torn last line.
+    pub fn torn(&self) -> bool {
+        self.lock().torn
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Chain> {
+        self.chain.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// The checkpoint after the last entry written.
+    pub fn head(&self) -> Checkpoint {
+        self.lock().head
+    }
+
+    /// Appends an entry for `frame`.
+    pub fn record(&self, direction: Direction, peer: PeerCred, frame: &[u8]) -> Result<(), AbutError> {
+        let dir = match direction {
+            Direction::Inbound => "in",
+            Direction::Outbound => "out",
+        };
+        let msg = message_id(frame).map_or_else(|| "-".to_owned(), |id| id.to_string());
+        self.append(dir, peer, &msg, frame.len(), Some(frame))
+    }
+
+    /// Appends a `drop` entry for an inbound frame that was not delivered.
+    pub fn record_dropped(&self, peer: PeerCred, frame: Dropped<'_>) -> Result<(), AbutError> {
+        self.append("drop", peer, "-", frame.len, frame.bytes)
+    }
+
+    fn append(&self, dir: &str, peer: PeerCred, msg: &str, len: usize, bytes: Option<&[u8]>) -> Result<(), AbutError> {
+        let ts = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+        let digest = bytes.map_or_else(|| "-".to_owned(), |b| hex(&Sha256::digest(b).into()));
+
+        let mut chain = self.lock();
+        let seq = chain.head.entries + 1;
+        let body = format!("{seq} {ts} {dir} {} {} {} {msg} {len} {digest}", peer.pid, peer.uid, peer.gid);
+        let head = self::chain(&chain.head.head, &body);
+        // One write per entry, so a crash can only tear the last line.
+        chain.file.write_all(format!("{body} {}\n", hex(&head)).as_bytes())?;
+        chain.head = Checkpoint { entries: seq, head };
+        Ok(())
+    }
+
+    /// An observer for a [`FramedReader`](crate::frame::FramedReader) or
+    /// [`FramedWriter`](crate::frame::FramedWriter) talking to `peer`.
+    pub fn observer(&self, peer: PeerCred) -> AuditObserver {
+        AuditObserver { log: self.clone(), peer }
+    }
+}
+
+/// Records every observed frame, delivered or dropped, in an [`AuditLog`];
+/// a frame that cannot be recorded fails the read or write that carried it.
+pub struct AuditObserver {
+    log: AuditLog,
+    peer: PeerCred,
+}
+
+impl FrameObserver for AuditObserver {
+    fn observe(&mut self, direction: Direction, frame: &[u8]) -> Result<(), AbutError> {
+        self.log.record(direction, self.peer, frame)
+    }
+
+    fn dropped(&mut self, frame: Dropped<'_>) -> Result<(), AbutError> {
+        self.log.record_dropped(self.peer, frame)
+    }
+}
+
+/// Takes an exclusive lock on `file`, so two writers cannot interleave their
+/// chains. It is released when the file is closed.
+fn lock(file: &File) -> Result<(), AbutError> {
+    // SAFETY: `file` is an open descriptor for the duration of the call.
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(());
+    }
+    let err = io::Error::last_os_error();
+    if err.kind() == io::ErrorKind::WouldBlock {
+        return Err(AbutError::from(err).ctx("audit log is already open for writing"));
+    }
+    Err(err.into())
+}
+
+/// Whether `tail`, the bytes after the last newline, could be the start of
+/// entry `seq` cut short by a crash.
+fn torn_entry(tail: &[u8], seq: u64) -> bool {
+    let Ok(tail) = std::str::from_utf8(tail) else { return false };
+    let fields: Vec<&str> = tail.split(' ').collect();
+    if fields.len() > 10 {
+        return false;
+    }
+    let last = fields.len() - 1;
+    let seq = seq.to_string();
+    fields.iter().enumerate().all(|(i, &field)| {
+        // Every field but the last is complete; the last may stop anywhere.
+        let whole = i < last;
+        let word = |w: &str| if whole { field == w } else { w.starts_with(field) };
+        let digits = field.bytes().all(|b| b.is_ascii_digit()) && !(whole && field.is_empty());
+        let hash = field.len() <= 64 && field.bytes().all(|b| b.is_ascii_hexdigit()) && !(whole && field.len() != 64);
+        match i {
+            0 => word(&seq),
+            2 => word("in") || word("out") || word("drop"),
+            6 => word("-") || digits,
+            8 => word("-") || hash,
+            9 => hash,
+            _ => digits,
+        }
+    })
+}
+
+/// Checks the whole chain of the log at `path`, returning where it ends.
+pub fn verify(path: impl AsRef<Path>) -> Result<Checkpoint, AbutError> {
+    verify_bytes(&std::fs::read(path)?, |_| {})
+}
+
+/// Like [`verify`], and also checks that the log still passes through
+/// `expected`: it has at least `expected.entries` entries, and the chain
+/// hash of entry number `expected.entries` is `expected.head`. Entries
+/// appended since the checkpoint was taken are fine.
+pub fn verify_against(path: impl AsRef<Path>, expected: Checkpoint) -> Result<Checkpoint, AbutError> {
+    let mut at_expected = (expected.entries == 0).then_some(GENESIS);
+    let found = verify_bytes(&std::fs::read(path)?, |at| {
+        if at.entries == expected.entries {
+            at_expected = Some(at.head);
+        }
+    })?;
+    match at_expected {
+        None => Err(AbutError::new(AbutCode::AuditCorrupt)
+            .ctx(format_args!("log ends at {found}, before {expected}; entries were removed"))),
+        Some(head) if head != expected.head => Err(AbutError::new(AbutCode::AuditCorrupt)
+            .ctx(format_args!("entry {} does not match {expected}; entries were replaced", expected.entries))),
+        Some(_) => Ok(found),
+    }
+}
+
+/// Checks the chain, calling `each` with the checkpoint after every entry.
+fn verify_bytes(bytes: &[u8], each: impl FnMut(Checkpoint)) -> Result<Checkpoint, AbutError> {
+    let (at, valid) = scan(bytes, each)?;
+    if valid < bytes.len() {
+        return Err(corrupt(at.entries + 1, "incomplete last entry"));
+    }
+    Ok(at)
+}
+
+/// Checks every complete line, returning where the chain ends and how many
+/// bytes it covers; anything after the last newline is left unchecked.
+fn scan(bytes: &[u8], mut each: impl FnMut(Checkpoint)) -> Result<(Checkpoint, usize), AbutError> {
+    let valid = bytes.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+    let text = std::str::from_utf8(&bytes[..valid]).map_err(|e| corrupt(0, e))?;
+    let mut at = Checkpoint::default();
+    for line in text.split_terminator('\n') {
+        let n = at.entries + 1;
+        let (body, hash) = line.rsplit_once(' ').ok_or_else(|| corrupt(n, "missing chain hash"))?;
+        let fields: Vec<&str> = body.split(' ').collect();
+        if fields.len() != 9 {
+            return Err(corrupt(n, format_args!("{} fields, expected 10", fields.len() + 1)));
+        }
+        if fields[0] != n.to_string() {
+            return Err(corrupt(n, format_args!("sequence number {}", fields[0])));
+        }
+        let hash = unhex(hash).ok_or_else(|| corrupt(n, "malformed chain hash"))?;
+        if chain(&at.head, body) != hash {
+            return Err(corrupt(n, "chain hash mismatch"));
+        }
+        at = Checkpoint { entries: n, head: hash };
+        each(at);
+    }
+    Ok((at, valid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::{FramedReader, FramedWriter};
+    use std::path::PathBuf;
+
+    fn log_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("abut-audit-{}-{name}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn write_entries(path: &Path, n: usize) -> Checkpoint {
+        let log = AuditLog::open(path).unwrap();
+        let mut buf = Vec::new();
+        let mut w = FramedWriter::new(&mut buf);
+        w.set_observer(Some(Box::new(log.observer(PeerCred::current()))));
+        for i in 0..n {
+            w.write_frame(&[i as u8, 0, b'x']).unwrap();
+        }
+        drop(w);
+
+        let mut r = FramedReader::new(&buf[..]);
+        r.set_observer(Some(Box::new(log.observer(PeerCred::current()))));
+        assert_eq!(r.frames().count(), n);
+        log.head()
+    }
+
+    #[test]
+    fn test_chain_verifies_and_resumes() {
+        let path = log_path("resume");
+        let first = write_entries(&path, 2);
+        assert_eq!(first.entries, 4);
+        assert_eq!(verify(&path).unwrap(), first);
+
+        let second = write_entries(&path, 1);
+        assert_eq!(second.entries, 6);
+        assert_eq!(verify_against(&path, second).unwrap(), second);
+        // An older checkpoint still holds after more entries were appended.
+        assert_eq!(verify_against(&path, first).unwrap(), second);
+        assert_eq!(verify_against(&path, Checkpoint::default()).unwrap(), second);
+        let wrong = Checkpoint { head: second.head, ..first };
+        assert_eq!(verify_against(&path, wrong).unwrap_err().code, AbutCode::AuditCorrupt);
+        assert_eq!(second.to_string().parse::<Checkpoint>().unwrap(), second);
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let fields: Vec<_> = text.lines().next().unwrap().split(' ').collect();
+        assert_eq!((fields[2], fields[6], fields[7]), ("out", "0", "3"));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_tampering_is_detected() {
+        let path = log_path("tamper");
+        let head = write_entries(&path, 2);
+        let text = std::fs::read_to_string(&path).unwrap();
+
+        // An edited size.
+        std::fs::write(&path, text.replacen(" 3 ", " 4 ", 1)).unwrap();
+        assert_eq!(verify(&path).unwrap_err().code, AbutCode::AuditCorrupt);
+
+        // A torn last entry fails verification, but opening cuts it off and carries on.
+        std::fs::write(&path, &text[..text.len() - 10]).unwrap();
+        assert_eq!(verify(&path).unwrap_err().code, AbutCode::AuditCorrupt);
+        let log = AuditLog::open(&path).unwrap();
+        assert!(log.torn());
+        assert_eq!(log.head().entries, 3);
+        drop(log);
+        assert_eq!(verify(&path).unwrap().entries, 3);
+        let resumed = write_entries(&path, 1);
+        assert_eq!(verify(&path).unwrap(), resumed);
+
+        // Damage before the last line is still refused.
+        std::fs::write(&path, text.replacen(" 3 ", " 4 ", 1) + "torn").unwrap();
+        assert_eq!(AuditLog::open(&path).err().unwrap().code, AbutCode::AuditCorrupt);
+
+        // So is a tail that is not the start of the next entry, and a file
+        // with no entry at all; neither is touched.
+        for bytes in [text.clone() + "5 17 sideways", "not an audit log".to_owned()] {
+            std::fs::write(&path, &bytes).unwrap();
+            assert_eq!(AuditLog::open(&path).err().unwrap().code, AbutCode::AuditCorrupt, "{bytes}");
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), bytes);
+        }
+
+        // Whole entries cut off the end only show against a checkpoint.
+        let kept: String = text.split_inclusive('\n').take(3).collect();
+        std::fs::write(&path, kept).unwrap();
+        assert_eq!(verify(&path).unwrap().entries, 3);
+        assert_eq!(verify_against(&path, head).unwrap_err().code, AbutCode::AuditCorrupt);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_dropped_frames_are_recorded() {
+        let path = log_path("dropped");
+        let log = AuditLog::open(&path).unwrap();
+        let mut buf = Vec::new();
+        let mut w = FramedWriter::new(&mut buf);
+        w.write_frame(&[0u8; 32]).unwrap();
+        w.write_frame(b"ok").unwrap();
+        drop(w);
+
+        let cfg = crate::ReaderConfig { max_frame_len: 16, drain_oversize_up_to: 64, ..Default::default() };
+        let mut r = FramedReader::with_config(&buf[..], cfg);
+        r.set_observer(Some(Box::new(log.observer(PeerCred::current()))));
+        assert_eq!(r.recv_into(&mut Vec::new()).unwrap_err().code, AbutCode::FrameTooLarge);
+        r.recv_into(&mut Vec::new()).unwrap();
+        assert_eq!(verify(&path).unwrap(), log.head());
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let fields: Vec<_> = text.lines().next().unwrap().split(' ').collect();
+        assert_eq!((fields[2], fields[6], fields[7], fields[8]), ("drop", "-", "32", "-"));
+        // A `drop` line cut short is still recognised as a torn entry.
+        assert!(torn_entry(b"3 1700000000 drop 1 2 3 - 32 -", 3));
+        drop(log);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_one_writer_at_a_time() {
+        let path = log_path("lock");
+        let log = AuditLog::open(&path).unwrap();
+        let clone = log.clone();
+        drop(log);
+        assert_eq!(AuditLog::open(&path).err().unwrap().code, AbutCode::Io);
+        drop(clone);
+        assert!(AuditLog::open(&path).is_ok());
+        let _ = std::fs::remove_file(&path);
+    }
+}
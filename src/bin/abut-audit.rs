@@ -0,0 +1,32 @@
+//! Verifies an `abut` audit log.
+//!
+//! Usage: `abut-audit <log> [<entries>:<head>]`
+//!
+//! Prints the checkpoint the log ends at. With an expected checkpoint, also
+//! fails if the log no longer passes through it. Exits 1 on any failure.
+
+use std::process::ExitCode;
+
+use abut::audit::{self, Checkpoint};
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.as_slice() {
+        [log] => audit::verify(log),
+        [log, expected] => expected.parse::<Checkpoint>().and_then(|cp| audit::verify_against(log, cp)),
+        _ => {
+            eprintln!("usage: abut-audit <log> [<entries>:<head>]");
+            return ExitCode::from(2);
+        }
+    };
+    match result {
+        Ok(checkpoint) => {
+            println!("ok {checkpoint}");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("abut-audit: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
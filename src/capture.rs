@@ -0,0 +1,342 @@
+//! Recording and replaying framed sessions.
+//!
+//! [`Capture`] wraps a [`FrameSink`] or [`FrameSource`] and appends every
+//! frame that crosses it to a session file through a shared [`Recorder`], so
+//! both halves of a connection land in one file, in order. A recorded
+//! [`Session`] can be fed back as a [`Replay`]: a `Read` stream of one
+//! direction's frames in the length-prefixed wire format, paced like the
+//! original or faster, for a [`FramedReader`](crate::frame::FramedReader) or
+//! any of the typed readers built on it.
+//!
+//! File format, all integers little-endian:
+//!
+//! ```text
+//! header: b"ABUTCAP1" <unix_ns: u64>
+//! record: <direction: u8, 0 in / 1 out> <offset_ns: u64> <len: u32> <frame>
+//! ```
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::{AbutCode, AbutError, Direction, FrameSink, FrameSource};
+
+/// Magic at the start of every session file.
+pub const MAGIC: &[u8; 8] = b"ABUTCAP1";
+
+const HEADER_LEN: usize = MAGIC.len() + 8;
+const RECORD_HEADER_LEN: usize = 1 + 8 + 4;
+
+struct Output {
+    file: File,
+    started: Instant,
+}
+
+/// A session file being written. Cheap to clone; all clones append to the same file.
+#[derive(Clone)]
+pub struct Recorder {
+    out: Arc<Mutex<Output>>,
+}
+
+impl Recorder {
+    /// Creates (or truncates) the session file at `path`.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, AbutError> {
+        let mut file = File::create(path)?;
+        let unix_ns = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&unix_ns.to_le_bytes());
+        file.write_all(&header)?;
+        Ok(Self { out: Arc::new(Mutex::new(Output { file, started: Instant::now() })) })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Output> {
+        self.out.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Appends one frame, stamped with the time since the file was created.
+    pub fn record(&self, direction: Direction, frame: &[u8]) -> Result<(), AbutError> {
+        let len: u32 = frame.len().try_into().map_err(|_| AbutError::frame_too_large(frame.len(), u32::MAX as usize))?;
+        let mut out = self.lock();
+        let offset = out.started.elapsed().as_nanos() as u64;
+        let mut record = Vec::with_capacity(RECORD_HEADER_LEN + frame.len());
+        record.push(match direction {
+            Direction::Inbound => 0,
+            Direction::Outbound => 1,
+        });
+        record.extend_from_slice(&offset.to_le_bytes());
+        record.extend_from_slice(&len.to_le_bytes());
+        record.extend_from_slice(frame);
+        out.file.write_all(&record)?;
+        Ok(())
+    }
+
+    /// Wraps `inner` so that the frames it sends or receives are recorded.
+    pub fn wrap<T>(&self, inner: T) -> Capture<T> {
+        Capture { inner, recorder: self.clone() }
+    }
+}
+
+/// A sink or source whose frames are copied to a [`Recorder`].
+///
+/// Only frames that were sent or received successfully are recorded.
+pub struct Capture<T> {
+    inner: T,
+    recorder: Recorder,
+}
+
+impl<T> Capture<T> {
+    pub fn inner_mut(&mut self) -> &mut T { &mut self.inner }
+    pub fn into_inner(self) -> T { self.inner }
+}
+
+impl<T: FrameSink<Error = AbutError>> FrameSink for Capture<T> {
+    type Error = AbutError;
+
+    fn send_frame(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.inner.send_frame(bytes)?;
+        self.recorder.record(Direction::Outbound, bytes)
+    }
+}
+
+impl<T: FrameSource<Error = AbutError>> FrameSource for Capture<T> {
+    type Error = AbutError;
+
+    fn recv_frame(&mut self, dst: &mut [u8]) -> Result<usize, Self::Error> {
+        let n = self.inner.recv_frame(dst)?;
+        self.recorder.record(Direction::Inbound, &dst[..n])?;
+        Ok(n)
+    }
+}
+
+/// One recorded frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub direction: Direction,
+    /// Time since recording started.
+    pub at: Duration,
+    pub frame: Vec<u8>,
+}
+
+/// A session file read back into memory.
+#[derive(Debug, Clone)]
+pub struct Session {
+    started: SystemTime,
+    records: Vec<Record>,
+    torn: bool,
+}
+
+impl Session {
+    /// Reads a session file.
+    ///
+    /// A record cut short at the end of the file (the recording process died
+    /// mid-write) is dropped and reported by [`torn`](Self::torn).
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, AbutError> {
+        Self::parse(&std::fs::read(path)?)
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, AbutError> {
+        let header = bytes.get(..HEADER_LEN).filter(|h| h.starts_with(MAGIC));
+        let header = header.ok_or_else(|| AbutError::protocol("not a capture session file"))?;
+        let unix_ns = u64::from_le_bytes(header[MAGIC.len()..].try_into().expect("8 bytes"));
+        let started = SystemTime::UNIX_EPOCH + Duration::from_nanos(unix_ns);
+
+        let mut rest = &bytes[HEADER_LEN..];
+        let mut records = Vec::new();
+        while let Some((head, body)) = rest.split_first_chunk::<RECORD_HEADER_LEN>() {
+            let direction = match head[0] {
+                0 => Direction::Inbound,
+                1 => Direction::Outbound,
+                other => return Err(AbutError::protocol(format_args!("bad direction {other} in record {}", records.len()))),
+            };
+            let at = Duration::from_nanos(u64::from_le_bytes(head[1..9].try_into().expect("8 bytes")));
+            let len = u32::from_le_bytes(head[9..].try_into().expect("4 bytes")) as usize;
+            let Some(frame) = body.get(..len) else { break };
+            records.push(Record { direction, at, frame: frame.to_vec() });
+            rest = &body[len..];
+        }
+        Ok(Self { started, records, torn: !rest.is_empty() })
+    }
+
+    /// Wall-clock time recording started.
+    pub fn started(&self) -> SystemTime { self.started }
+    pub fn records(&self) -> &[Record] { &self.records }
+
+    /// Whether the file ended part-way through a record.
+    pub fn torn(&self) -> bool { self.torn }
+
+    /// Replays the frames that travelled in `direction`.
+    ///
+    /// A [`Speed::Scaled`] factor that is not above zero (or is NaN), or so
+    /// small that the recording's span no longer fits a `Duration`, is
+    /// refused with `AbutCode::BadSpeed`.
+    pub fn replay(&self, direction: Direction, speed: Speed) -> Result<Replay, AbutError> {
+        let frames: VecDeque<_> =
+            self.records.iter().filter(|r| r.direction == direction).map(|r| (r.at, r.frame.clone())).collect();
+        if let Speed::Scaled(factor) = speed {
+            let bad = |why: &str| AbutError::new(AbutCode::BadSpeed).ctx(format_args!("replay speed factor {factor} {why}"));
+            if factor.is_nan() || factor <= 0.0 {
+                return Err(bad("is not positive"));
+            }
+            // Every gap `wait_for` scales is at most the span from the first frame.
+            let first = frames.front().map_or(Duration::ZERO, |(at, _)| *at);
+            let span = frames.iter().map(|(at, _)| at.saturating_sub(first)).max().unwrap_or_default();
+            if Duration::try_from_secs_f64(span.as_secs_f64() / factor).is_err() {
+                return Err(bad(&format!("stretches the {span:?} recording past what a Duration holds")));
+            }
+        }
+        Ok(Replay { frames, speed, origin: None, pending: Vec::new(), pos: 0 })
+    }
+}
+
+/// How fast a [`Replay`] hands out frames.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Speed {
+    /// With the gaps between frames as recorded.
+    #[default]
+    Original,
+    /// With the gaps divided by the factor (`2.0` is twice as fast).
+    Scaled(f64),
+    /// As fast as they are read.
+    Immediate,
+}
+
+/// A recorded direction of a session, re-encoded as a framed byte stream.
+///
+/// Time starts at the first read; the stream ends (EOF at a frame boundary)
+/// after the last frame.
+pub struct Replay {
+    frames: VecDeque<(Duration, Vec<u8>)>,
+    speed: Speed,
+    /// Replay start, and the recording offset of the first frame.
+    origin: Option<(Instant, Duration)>,
+    /// The frame being read out, length prefix included.
+    pending: Vec<u8>,
+    pos: usize,
+}
+
+impl Replay {
+    pub fn open(path: impl AsRef<Path>, direction: Direction, speed: Speed) -> Result<Self, AbutError> {
+        Session::open(path)?.replay(direction, speed)
+    }
+
+    /// Frames not yet started.
+    pub fn remaining(&self) -> usize { self.frames.len() }
+
+    /// Blocks until `at` is due under the configured speed.
+    fn wait_for(&mut self, at: Duration) {
+        let (start, first) = *self.origin.get_or_insert((Instant::now(), at));
+        let gap = at.saturating_sub(first);
+        let due = match self.speed {
+            Speed::Original => gap,
+            Speed::Scaled(factor) => {
+                Duration::try_from_secs_f64(gap.as_secs_f64() / factor).expect("factor checked by Session::replay")
+            }
+            Speed::Immediate => return,
+        };
+        let remaining = due.saturating_sub(start.elapsed());
+        if !remaining.is_zero() {
+            std::thread::sleep(remaining);
+        }
+    }
+}
+
+impl Read for Replay {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.pending.len() {
+            let Some((at, frame)) = self.frames.pop_front() else { return Ok(0) };
+            self.wait_for(at);
+            self.pending.clear();
+            self.pending.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            self.pending.extend_from_slice(&frame);
+            self.pos = 0;
+        }
+        let n = buf.len().min(self.pending.len() - self.pos);
+        buf[..n].copy_from_slice(&self.pending[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AbutCode;
+    use crate::frame::{FramedReader, FramedWriter};
+    use std::os::unix::net::UnixStream;
+    use std::path::PathBuf;
+
+    fn session_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("abut-capture-{}-{name}.cap", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn record_echo(path: &Path, gap: Duration) {
+        let (a, b) = UnixStream::pair().unwrap();
+        let echo = std::thread::spawn(move || {
+            let (mut r, mut w) = (FramedReader::new(&b), FramedWriter::new(&b));
+            let mut buf = Vec::new();
+            while r.try_recv_into(&mut buf).unwrap() {
+                w.write_frame(&buf).unwrap();
+            }
+        });
+
+        let recorder = Recorder::create(path).unwrap();
+        let mut tx = recorder.wrap(FramedWriter::new(&a));
+        let mut rx = recorder.wrap(FramedReader::new(&a));
+        let mut buf = [0u8; 16];
+        for frame in [&b"one"[..], b"two"] {
+            tx.send_frame(frame).unwrap();
+            let n = rx.recv_frame(&mut buf).unwrap();
+            assert_eq!(&buf[..n], frame);
+            std::thread::sleep(gap);
+        }
+        a.shutdown(std::net::Shutdown::Both).unwrap();
+        echo.join().unwrap();
+    }
+
+    #[test]
+    fn test_capture_and_replay_through_framed_reader() {
+        let path = session_path("echo");
+        record_echo(&path, Duration::ZERO);
+
+        let session = Session::open(&path).unwrap();
+        let dirs: Vec<_> = session.records().iter().map(|r| r.direction).collect();
+        assert_eq!(dirs, [Direction::Outbound, Direction::Inbound, Direction::Outbound, Direction::Inbound]);
+        assert!(!session.torn());
+
+        let mut r = FramedReader::new(session.replay(Direction::Inbound, Speed::Immediate).unwrap());
+        let frames: Vec<_> = r.frames().map(Result::unwrap).collect();
+        assert_eq!(frames, [b"one".to_vec(), b"two".to_vec()]);
+        assert_eq!(r.recv_into(&mut Vec::new()).unwrap_err().code, AbutCode::Closed);
+
+        // A torn tail keeps the complete records.
+        let bytes = std::fs::read(&path).unwrap();
+        let torn = Session::parse(&bytes[..bytes.len() - 1]).unwrap();
+        assert!(torn.torn());
+        assert_eq!(torn.records().len(), 3);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_replay_pacing() {
+        let path = session_path("pacing");
+        record_echo(&path, Duration::from_millis(40));
+        let session = Session::open(&path).unwrap();
+
+        let timed = |speed| {
+            let start = Instant::now();
+            assert_eq!(FramedReader::new(session.replay(Direction::Outbound, speed).unwrap()).frames().count(), 2);
+            start.elapsed()
+        };
+        assert!(timed(Speed::Original) >= Duration::from_millis(40));
+        assert!(timed(Speed::Scaled(100.0)) < Duration::from_millis(40));
+        for bad in [0.0, -1.0, f64::NAN, 1e-300] {
+            assert_eq!(session.replay(Direction::Outbound, Speed::Scaled(bad)).err().unwrap().code, AbutCode::BadSpeed);
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+}
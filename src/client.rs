@@ -0,0 +1,477 @@
+//! A client that survives the server restarting.
+//!
+//! [`ReconnectingClient`] owns the connection to a Unix socket path. When a
+//! send or receive fails it reconnects with exponential backoff and jitter,
+//! re-runs the caller's handshake, and (optionally) replays frames that
+//! were queued while the server was away.
+
+use std::collections::VecDeque;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::frame::{FramedReader, FramedWriter};
+use crate::{AbutCode, AbutError, FrameSink, FrameSource, OverflowPolicy, QueueLimits, ReaderConfig};
+
+/// Delay schedule between reconnect attempts.
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub multiplier: f64,
+
+    /// Each delay is randomised by up to this fraction in either direction (0.0 ..= 1.0).
+    pub jitter: f64,
+
+    /// Consecutive failed attempts after which operations fail with
+    /// `AbutCode::Unreachable`. `None` retries forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(50),
+            max: Duration::from_secs(5),
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_attempts: None,
+        }
+    }
+}
+
+impl Backoff {
+    /// Delay before attempt number `attempt + 1`, before jitter.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let scale = self.multiplier.max(1.0).powi(attempt.min(64) as i32);
+        // Clamped in f64: the unclamped delay can exceed what a Duration holds.
+        Duration::try_from_secs_f64(self.initial.as_secs_f64() * scale).map_or(self.max, |d| d.min(self.max))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ClientConfig {
+    pub backoff: Backoff,
+
+    /// Buffer outbound frames during an outage. `None` makes sends fail
+    /// with `AbutCode::Unreachable` while disconnected.
+    pub queue: Option<QueueLimits>,
+
+    /// What to do when the outage queue is full. `Block` waits for the
+    /// reconnection for at most `send_timeout`, failing with
+    /// `AbutCode::Timeout`; with neither `send_timeout` nor
+    /// `backoff.max_attempts` set it could wait forever, so it fails like
+    /// `Error` instead.
+    pub overflow: OverflowPolicy,
+
+    pub reader: ReaderConfig,
+    pub send_timeout: Option<Duration>,
+}
+
+/// Reported to the callback registered with [`ReconnectingClient::on_state`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnState {
+    Connected,
+    /// The connection was lost or an attempt failed; `attempt` counts failures in a row.
+    Disconnected { attempt: u32 },
+    /// `max_attempts` was reached; operations fail until [`ReconnectingClient::reset`].
+    GaveUp,
+}
+
+type Conn = (FramedReader<UnixStream>, FramedWriter<UnixStream>);
+type Handshake = Box<dyn FnMut(&mut FramedReader<UnixStream>, &mut FramedWriter<UnixStream>) -> Result<(), AbutError> + Send>;
+
+pub struct ReconnectingClient {
+    path: PathBuf,
+    cfg: ClientConfig,
+    handshake: Option<Handshake>,
+    on_state: Option<Box<dyn FnMut(ConnState) + Send>>,
+    conn: Option<Conn>,
+    queue: VecDeque<Vec<u8>>,
+    queued_bytes: usize,
+    dropped: u64,
+    failures: u32,
+    next_attempt: Instant,
+    rng: u64,
+}
+
+impl ReconnectingClient {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self::with_config(path, ClientConfig::default())
+    }
+
+    pub fn with_config(path: impl AsRef<Path>, cfg: ClientConfig) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64)
+            ^ u64::from(std::process::id()) << 32;
+        Self {
+            path: path.as_ref().to_owned(),
+            cfg,
+            handshake: None,
+            on_state: None,
+            conn: None,
+            queue: VecDeque::new(),
+            queued_bytes: 0,
+            dropped: 0,
+            failures: 0,
+            next_attempt: Instant::now(),
+            rng: seed | 1,
+        }
+    }
+
+    /// Runs `f` on every fresh connection before any queued or new frame is sent.
+    pub fn handshake(
+        mut self,
+        f: impl FnMut(&mut FramedReader<UnixStream>, &mut FramedWriter<UnixStream>) -> Result<(), AbutError> + Send + 'static,
+    ) -> Self {
+        self.handshake = Some(Box::new(f));
+        self
+    }
+
+    pub fn on_state(mut self, f: impl FnMut(ConnState) + Send + 'static) -> Self {
+        self.on_state = Some(Box::new(f));
+        self
+    }
+
+    pub fn is_connected(&self) -> bool { self.conn.is_some() }
+    pub fn queued_frames(&self) -> usize { self.queue.len() }
+    pub fn queued_bytes(&self) -> usize { self.queued_bytes }
+
+    /// Frames discarded by the overflow policy so far.
+    pub fn dropped(&self) -> u64 { self.dropped }
+
+    /// Clears a `GaveUp` state so the next operation tries again immediately.
+    pub fn reset(&mut self) {
+        self.failures = 0;
+        self.next_attempt = Instant::now();
+    }
+
+    fn emit(&mut self, state: ConnState) {
+        if let Some(f) = self.on_state.as_mut() {
+            f(state);
+        }
+    }
+
+    fn gave_up(&self) -> bool {
+        self.cfg.backoff.max_attempts.is_some_and(|max| self.failures >= max)
+    }
+
+    fn unreachable(&self) -> AbutError {
+        AbutError::new(AbutCode::Unreachable)
+            .ctx(format_args!("{} after {} attempts", self.path.display(), self.failures))
+    }
+
+    fn jittered(&mut self, delay: Duration) -> Duration {
+        // xorshift64: good enough to de-synchronise a herd of clients.
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        let unit = (self.rng >> 11) as f64 / (1u64 << 53) as f64;
+        let jitter = self.cfg.backoff.jitter.clamp(0.0, 1.0);
+        Duration::try_from_secs_f64(delay.as_secs_f64() * (1.0 + jitter * (2.0 * unit - 1.0))).unwrap_or(delay)
+    }
+
+    fn open(&mut self) -> Result<Conn, AbutError> {
+        let stream = UnixStream::connect(&self.path)?;
+        let mut reader = FramedReader::with_deadlines(stream.try_clone()?, self.cfg.reader);
+        let mut writer = match self.cfg.send_timeout {
+            Some(timeout) => FramedWriter::with_send_timeout(stream, timeout),
+            None => FramedWriter::new(stream),
+        };
+        if let Some(handshake) = self.handshake.as_mut() {
+            handshake(&mut reader, &mut writer)?;
+        }
+        Ok((reader, writer))
+    }
+
+    /// Makes one connection attempt if the backoff allows it now.
+    fn try_connect(&mut self) -> Result<bool, AbutError> {
+        if self.conn.is_some() {
+            return Ok(true);
+        }
+        if self.gave_up() {
+            return Err(self.unreachable());
+        }
+        if Instant::now() < self.next_attempt {
+            return Ok(false);
+        }
+        match self.open() {
+            Ok(conn) => {
+                self.conn = Some(conn);
+                self.failures = 0;
+                self.emit(ConnState::Connected);
+                Ok(self.flush_queue())
+            }
+            Err(_) => {
+                self.failures += 1;
+                let delay = self.cfg.backoff.delay(self.failures - 1);
+                self.next_attempt = Instant::now() + self.jittered(delay);
+                if self.gave_up() {
+                    self.emit(ConnState::GaveUp);
+                    return Err(self.unreachable());
+                }
+                self.emit(ConnState::Disconnected { attempt: self.failures });
+                Ok(false)
+            }
+        }
+    }
+
+    /// Blocks until connected (and the outage queue is flushed), honouring the backoff.
+    pub fn connect(&mut self) -> Result<(), AbutError> {
+        self.connect_before(None)
+    }
+
+    /// Like [`connect`](Self::connect), but fails with `AbutCode::Timeout` at `deadline`.
+    fn connect_before(&mut self, deadline: Option<Instant>) -> Result<(), AbutError> {
+        while !self.try_connect()? {
+            let mut wait = self.next_attempt.saturating_duration_since(Instant::now());
+            if let Some(deadline) = deadline {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining <= wait {
+                    std::thread::sleep(remaining);
+                    return Err(AbutError::timeout(true, "reconnect"));
+                }
+                wait = wait.min(remaining);
+            }
+            std::thread::sleep(wait);
+        }
+        Ok(())
+    }
+
+    fn lost(&mut self) {
+        if self.conn.take().is_some() {
+            self.next_attempt = Instant::now();
+            self.emit(ConnState::Disconnected { attempt: 0 });
+        }
+    }
+
+    /// Sends queued frames in order; returns false if the connection dropped meanwhile.
+    fn flush_queue(&mut self) -> bool {
+        while let Some(frame) = self.queue.front() {
+            let Some((_, writer)) = self.conn.as_mut() else { return false };
+            if writer.write_frame(frame).is_err() {
+                self.lost();
+                return false;
+            }
+            self.queued_bytes -= frame.len();
+            self.queue.pop_front();
+        }
+        true
+    }
+
+    fn enqueue(&mut self, bytes: &[u8]) -> Result<(), AbutError> {
+        let Some(limits) = self.cfg.queue else {
+            return Err(self.unreachable());
+        };
+        let fits = |q: &Self| q.queue.len() < limits.max_frames && q.queued_bytes + bytes.len() <= limits.max_bytes;
+        if bytes.len() > limits.max_bytes {
+            return Err(AbutError::new(AbutCode::QueueFull).ctx(format_args!("frame of {} bytes exceeds queue capacity", bytes.len())));
+        }
+        let deadline = self.cfg.send_timeout.map(|t| Instant::now() + t);
+        let unbounded = deadline.is_none() && self.cfg.backoff.max_attempts.is_none();
+        while !fits(self) {
+            match self.cfg.overflow {
+                OverflowPolicy::Block if !unbounded => {
+                    self.connect_before(deadline)?;
+                    if self.conn.is_some() {
+                        return self.send_frame(bytes);
+                    }
+                }
+                OverflowPolicy::DropOldest => {
+                    let old = self.queue.pop_front().expect("queue is full, so not empty");
+                    self.queued_bytes -= old.len();
+                    self.dropped += 1;
+                }
+                OverflowPolicy::DropNewest => {
+                    self.dropped += 1;
+                    return Ok(());
+                }
+                OverflowPolicy::Block | OverflowPolicy::Error => {
+                    return Err(AbutError::new(AbutCode::QueueFull)
+                        .ctx(format_args!("{} frames, {} bytes queued", self.queue.len(), self.queued_bytes)));
+                }
+            }
+        }
+        self.queued_bytes += bytes.len();
+        self.queue.push_back(bytes.to_vec());
+        Ok(())
+    }
+}
+
+impl FrameSink for ReconnectingClient {
+    type Error = AbutError;
+
+    /// Sends now if connected (reconnecting first if an attempt is due);
+    /// otherwise queues the frame according to the config.
+    fn send_frame(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        if self.try_connect()?
+            && let Some((_, writer)) = self.conn.as_mut()
+        {
+            match writer.write_frame(bytes) {
+                Ok(()) => return Ok(()),
+                Err(_) => self.lost(),
+            }
+        }
+        self.enqueue(bytes)
+    }
+}
+
+impl FrameSource for ReconnectingClient {
+    type Error = AbutError;
+
+    /// Receives the next frame, transparently reconnecting if the connection
+    /// is lost. Frames in flight at the time of the loss are gone.
+    fn recv_frame(&mut self, dst: &mut [u8]) -> Result<usize, Self::Error> {
+        loop {
+            self.connect()?;
+            let Some((reader, _)) = self.conn.as_mut() else { continue };
+            match reader.read_frame(dst) {
+                Ok(n) => return Ok(n),
+                Err(e) if e.code.is_decode() || e.code == AbutCode::BufferTooSmall => return Err(e),
+                Err(e) if e.code == AbutCode::Timeout && e.stream_aligned() == Some(true) => return Err(e),
+                Err(_) => self.lost(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+    use std::sync::{Arc, Mutex};
+
+    fn socket_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("abut-client-{}-{name}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn fast_backoff() -> Backoff {
+        Backoff { initial: Duration::from_millis(5), max: Duration::from_millis(20), ..Default::default() }
+    }
+
+    #[test]
+    fn test_backoff_grows_and_caps() {
+        let b = Backoff { initial: Duration::from_millis(10), max: Duration::from_millis(70), multiplier: 2.0, ..Default::default() };
+        let delays: Vec<_> = (0..5).map(|n| b.delay(n).as_millis()).collect();
+        assert_eq!(delays, vec![10, 20, 40, 70, 70]);
+
+        let huge = Backoff { multiplier: 1e300, ..b };
+        assert_eq!(huge.delay(u32::MAX), b.max);
+        assert_eq!(Backoff { initial: Duration::MAX, ..b }.delay(1000), b.max);
+    }
+
+    #[test]
+    fn test_queue_while_down_and_replay_after_restart() {
+        let path = socket_path("restart");
+        let handshakes = Arc::new(Mutex::new(0));
+        let states = Arc::new(Mutex::new(Vec::new()));
+        let (h, s) = (handshakes.clone(), states.clone());
+
+        let cfg = ClientConfig {
+            backoff: fast_backoff(),
+            queue: Some(QueueLimits { max_frames: 2, max_bytes: 1024 }),
+            overflow: OverflowPolicy::DropOldest,
+            ..Default::default()
+        };
+        let mut client = ReconnectingClient::with_config(&path, cfg)
+            .handshake(move |_, w| {
+                *h.lock().unwrap() += 1;
+                w.write_frame(b"hello")
+            })
+            .on_state(move |st| s.lock().unwrap().push(st));
+
+        // First server instance: reads the handshake and one frame, then dies.
+        let listener = UnixListener::bind(&path).unwrap();
+        client.send_frame(b"first").unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let mut r = FramedReader::new(&stream);
+        let mut buf = Vec::new();
+        r.recv_into(&mut buf).unwrap();
+        assert_eq!(buf, b"hello");
+        r.recv_into(&mut buf).unwrap();
+        assert_eq!(buf, b"first");
+        drop(stream);
+        drop(listener);
+        std::fs::remove_file(&path).unwrap();
+
+        // Outage: frames are queued and the oldest is evicted.
+        for msg in [&b"a"[..], b"b", b"c"] {
+            client.send_frame(msg).unwrap();
+        }
+        assert!(!client.is_connected());
+        assert_eq!(client.queued_frames(), 2);
+        assert_eq!(client.dropped(), 1);
+
+        // Second instance: the next send reconnects, handshakes and replays the queue.
+        let listener = UnixListener::bind(&path).unwrap();
+        std::thread::sleep(Duration::from_millis(40));
+        client.send_frame(b"d").unwrap();
+        assert!(client.is_connected());
+
+        let (stream, _) = listener.accept().unwrap();
+        let mut r = FramedReader::new(&stream);
+        let frames: Vec<Vec<u8>> = (0..4).map(|_| { r.recv_into(&mut buf).unwrap(); buf.clone() }).collect();
+        assert_eq!(frames, vec![b"hello".to_vec(), b"b".to_vec(), b"c".to_vec(), b"d".to_vec()]);
+
+        assert_eq!(*handshakes.lock().unwrap(), 2);
+        let states = states.lock().unwrap();
+        assert_eq!(states.first(), Some(&ConnState::Connected));
+        assert_eq!(states.last(), Some(&ConnState::Connected));
+        assert!(states.contains(&ConnState::Disconnected { attempt: 0 }));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_gives_up_after_max_attempts() {
+        let path = socket_path("absent");
+        let cfg = ClientConfig {
+            backoff: Backoff { max_attempts: Some(3), ..fast_backoff() },
+            ..Default::default()
+        };
+        let mut client = ReconnectingClient::with_config(&path, cfg);
+        let err = client.connect().unwrap_err();
+        assert_eq!(err.code, AbutCode::Unreachable);
+
+        // Without a queue, sends fail straight away while down.
+        assert_eq!(client.send_frame(b"x").unwrap_err().code, AbutCode::Unreachable);
+    }
+
+    #[test]
+    fn test_overflow_error_policy() {
+        let path = socket_path("overflow");
+        let cfg = ClientConfig {
+            backoff: Backoff { initial: Duration::from_secs(60), ..Default::default() },
+            queue: Some(QueueLimits { max_frames: 1, max_bytes: 1024 }),
+            overflow: OverflowPolicy::Error,
+            ..Default::default()
+        };
+        let mut client = ReconnectingClient::with_config(&path, cfg);
+        client.send_frame(b"kept").unwrap();
+        assert_eq!(client.send_frame(b"rejected").unwrap_err().code, AbutCode::QueueFull);
+        assert_eq!(client.queued_bytes(), 4);
+    }
+
+    #[test]
+    fn test_overflow_block_is_bounded() {
+        let path = socket_path("block");
+        let cfg = ClientConfig {
+            backoff: Backoff { initial: Duration::from_secs(60), ..Default::default() },
+            queue: Some(QueueLimits { max_frames: 1, max_bytes: 1024 }),
+            overflow: OverflowPolicy::Block,
+            ..Default::default()
+        };
+        // Nothing bounds the wait: Block fails like Error.
+        let mut client = ReconnectingClient::with_config(&path, cfg);
+        client.send_frame(b"kept").unwrap();
+        assert_eq!(client.send_frame(b"rejected").unwrap_err().code, AbutCode::QueueFull);
+
+        let mut client = ReconnectingClient::with_config(&path, ClientConfig { send_timeout: Some(Duration::from_millis(50)), ..cfg });
+        client.send_frame(b"kept").unwrap();
+        let started = Instant::now();
+        assert_eq!(client.send_frame(b"waits").unwrap_err().code, AbutCode::Timeout);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
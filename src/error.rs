@@ -19,16 +19,72 @@ use std::{fmt, io};
 
 use liaise::{Liaise, RegisterErrors};
 
-#[derive(RegisterErrors, Debug, Copy, Clone)]
+use crate::TimedOut;
+
+#[derive(RegisterErrors, Debug, Copy, Clone, PartialEq, Eq)]
 #[error_prefix = "FILE"] // Sets the reporting prefix
 pub enum AbutCode {
     Io = 1,
     BufferTooSmall = 2,
     FrameTooLarge = 3,
+    DecodeLimit = 4,
+    Closed = 5,
+    TruncatedFrame = 6,
+    Protocol = 7,
+    Timeout = 8,
+    PeerDead = 9,
+    QueueFull = 20,
+    Unreachable = 21,
+    RateLimited = 22,
+    SchemaMismatch = 23,
+    PolicyDenied = 24,
+    PolicyInvalid = 26,
+    BadSpeed = 27,
+    #[cfg(feature = "audit")]
+    AuditCorrupt = 25,
     #[cfg(feature = "postcard")]
     PostcardEncode = 10,
     #[cfg(feature = "postcard")]
-    PostcardDecode = 11
+    PostcardDecode = 11,
+    #[cfg(feature = "cbor")]
+    CborEncode = 12,
+    #[cfg(feature = "cbor")]
+    CborDecode = 13,
+    #[cfg(feature = "msgpack")]
+    MsgpackEncode = 14,
+    #[cfg(feature = "msgpack")]
+    MsgpackDecode = 15,
+    #[cfg(feature = "json")]
+    JsonEncode = 16,
+    #[cfg(feature = "json")]
+    JsonDecode = 17,
+    #[cfg(feature = "cbor")]
+    CborNonCanonical = 18,
+    #[cfg(any(feature = "zstd", feature = "lz4"))]
+    Decompress = 19,
+}
+
+impl AbutCode {
+    /// True for failures confined to a single frame's payload.
+    ///
+    /// The frame has been consumed in full, so the stream is still aligned
+    /// and the next frame can be read normally.
+    pub fn is_decode(self) -> bool {
+        match self {
+            Self::DecodeLimit => true,
+            #[cfg(feature = "postcard")]
+            Self::PostcardDecode => true,
+            #[cfg(feature = "cbor")]
+            Self::CborDecode | Self::CborNonCanonical => true,
+            #[cfg(any(feature = "zstd", feature = "lz4"))]
+            Self::Decompress => true,
+            #[cfg(feature = "msgpack")]
+            Self::MsgpackDecode => true,
+            #[cfg(feature = "json")]
+            Self::JsonDecode => true,
+            _ => false,
+        }
+    }
 }
 
 impl Liaise for AbutCode {
@@ -39,10 +95,41 @@ impl Liaise for AbutCode {
             Self::Io => "I/O error",
             Self::BufferTooSmall => "Buffer too small",
             Self::FrameTooLarge => "Frame too large",
+            Self::DecodeLimit => "Decode limit exceeded",
+            Self::Closed => "Peer closed the stream",
+            Self::TruncatedFrame => "Stream ended mid-frame",
+            Self::Protocol => "Protocol violation",
+            Self::Timeout => "Timed out",
+            Self::PeerDead => "Peer stopped responding",
+            Self::QueueFull => "Outbound queue full",
+            Self::Unreachable => "Peer unreachable",
+            Self::RateLimited => "Peer exceeded its rate limit",
+            Self::SchemaMismatch => "Peer message schema is incompatible",
+            Self::PolicyDenied => "Message type not permitted by policy",
+            Self::PolicyInvalid => "Policy file is malformed",
+            Self::BadSpeed => "Replay speed is out of range",
+            #[cfg(feature = "audit")]
+            Self::AuditCorrupt => "Audit log was truncated or modified",
             #[cfg(feature = "postcard")]
             Self::PostcardEncode => "Postcard encode failed",
             #[cfg(feature = "postcard")]
             Self::PostcardDecode => "Postcard decode failed",
+            #[cfg(feature = "cbor")]
+            Self::CborEncode => "CBOR encode failed",
+            #[cfg(feature = "cbor")]
+            Self::CborDecode => "CBOR decode failed",
+            #[cfg(feature = "msgpack")]
+            Self::MsgpackEncode => "MessagePack encode failed",
+            #[cfg(feature = "msgpack")]
+            Self::MsgpackDecode => "MessagePack decode failed",
+            #[cfg(feature = "json")]
+            Self::JsonEncode => "JSON encode failed",
+            #[cfg(feature = "json")]
+            Self::JsonDecode => "JSON decode failed",
+            #[cfg(feature = "cbor")]
+            Self::CborNonCanonical => "CBOR input is not canonical",
+            #[cfg(any(feature = "zstd", feature = "lz4"))]
+            Self::Decompress => "Frame decompression failed",
         }
     }
 }
@@ -61,8 +148,17 @@ pub struct AbutError {
 #[derive(Debug)]
 pub enum AbutSource {
     Io(io::Error),
+    Timeout(TimedOut),
     #[cfg(feature = "postcard")]
     Postcard(postcard::Error),
+    #[cfg(feature = "cbor")]
+    Cbor(serde_cbor::Error),
+    #[cfg(feature = "msgpack")]
+    MsgpackEncode(rmp_serde::encode::Error),
+    #[cfg(feature = "msgpack")]
+    MsgpackDecode(rmp_serde::decode::Error),
+    #[cfg(feature = "json")]
+    Json(serde_json::Error),
 }
 
 impl AbutError {
@@ -96,6 +192,48 @@ impl AbutError {
         Self::new(AbutCode::FrameTooLarge).ctx(format_args!("len {len} exceeds max {max}"))
     }
 
+    /// The peer closed the stream cleanly, between two frames.
+    #[inline]
+    pub fn closed() -> Self {
+        Self::new(AbutCode::Closed)
+    }
+
+    /// The stream ended part-way through a frame's `part` ("length prefix" or "body").
+    #[inline]
+    pub fn truncated_frame(part: &str, got: usize, expected: usize) -> Self {
+        Self::new(AbutCode::TruncatedFrame).ctx(format_args!("{part} ended after {got} of {expected} bytes"))
+    }
+
+    /// A read or write deadline (`what`: "idle", "frame" or "send") expired.
+    #[inline]
+    pub fn timeout(aligned: bool, what: &str) -> Self {
+        let src = TimedOut { aligned };
+        Self {
+            code: AbutCode::Timeout,
+            ctx: Some(format!("{what} deadline: {src}")),
+            source: Some(AbutSource::Timeout(src)),
+        }
+    }
+
+    /// For `AbutCode::Timeout` errors, whether the stream is still at a frame boundary.
+    pub fn stream_aligned(&self) -> Option<bool> {
+        match &self.source {
+            Some(AbutSource::Timeout(t)) => Some(t.aligned),
+            _ => None,
+        }
+    }
+
+    /// The peer sent a well-formed frame whose contents break the expected protocol.
+    #[inline]
+    pub fn protocol(ctx: impl fmt::Display) -> Self {
+        Self::new(AbutCode::Protocol).ctx(ctx)
+    }
+
+    #[inline]
+    pub fn decode_limit(what: &str, value: usize, max: usize) -> Self {
+        Self::new(AbutCode::DecodeLimit).ctx(format_args!("{what} {value} exceeds max {max}"))
+    }
+
     #[cfg(feature = "postcard")]
     #[inline]
     pub fn postcard_encode(err: postcard::Error) -> Self {
@@ -115,6 +253,79 @@ impl AbutError {
             source: Some(AbutSource::Postcard(err)),
         }
     }
+
+    #[cfg(feature = "cbor")]
+    #[inline]
+    pub fn cbor_encode(err: serde_cbor::Error) -> Self {
+        Self {
+            code: AbutCode::CborEncode,
+            ctx: Some(err.to_string()),
+            source: Some(AbutSource::Cbor(err)),
+        }
+    }
+
+    #[cfg(feature = "cbor")]
+    #[inline]
+    pub fn cbor_decode(err: serde_cbor::Error) -> Self {
+        Self {
+            code: AbutCode::CborDecode,
+            ctx: Some(err.to_string()),
+            source: Some(AbutSource::Cbor(err)),
+        }
+    }
+
+    #[cfg(feature = "cbor")]
+    #[inline]
+    pub fn cbor_non_canonical(len: usize) -> Self {
+        Self::new(AbutCode::CborNonCanonical)
+            .ctx(format_args!("{len} byte frame differs from its canonical encoding"))
+    }
+
+    #[cfg(any(feature = "zstd", feature = "lz4"))]
+    #[inline]
+    pub fn decompress(algorithm: &str, err: impl fmt::Display) -> Self {
+        Self::new(AbutCode::Decompress).ctx(format_args!("{algorithm}: {err}"))
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[inline]
+    pub fn msgpack_encode(err: rmp_serde::encode::Error) -> Self {
+        Self {
+            code: AbutCode::MsgpackEncode,
+            ctx: Some(err.to_string()),
+            source: Some(AbutSource::MsgpackEncode(err)),
+        }
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[inline]
+    pub fn msgpack_decode(err: rmp_serde::decode::Error) -> Self {
+        Self {
+            code: AbutCode::MsgpackDecode,
+            ctx: Some(err.to_string()),
+            source: Some(AbutSource::MsgpackDecode(err)),
+        }
+    }
+
+    #[cfg(feature = "json")]
+    #[inline]
+    pub fn json_encode(err: serde_json::Error) -> Self {
+        Self {
+            code: AbutCode::JsonEncode,
+            ctx: Some(err.to_string()),
+            source: Some(AbutSource::Json(err)),
+        }
+    }
+
+    #[cfg(feature = "json")]
+    #[inline]
+    pub fn json_decode(err: serde_json::Error) -> Self {
+        Self {
+            code: AbutCode::JsonDecode,
+            ctx: Some(err.to_string()),
+            source: Some(AbutSource::Json(err)),
+        }
+    }
 }
 
 impl fmt::Display for AbutError {
@@ -132,8 +343,17 @@ impl std::error::Error for AbutError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match &self.source {
             Some(AbutSource::Io(e)) => Some(e),
+            Some(AbutSource::Timeout(e)) => Some(e),
             #[cfg(feature = "postcard")]
             Some(AbutSource::Postcard(e)) => Some(e),
+            #[cfg(feature = "cbor")]
+            Some(AbutSource::Cbor(e)) => Some(e),
+            #[cfg(feature = "msgpack")]
+            Some(AbutSource::MsgpackEncode(e)) => Some(e),
+            #[cfg(feature = "msgpack")]
+            Some(AbutSource::MsgpackDecode(e)) => Some(e),
+            #[cfg(feature = "json")]
+            Some(AbutSource::Json(e)) => Some(e),
             None => None,
         }
     }
@@ -1,51 +1,152 @@
 #![cfg(feature = "cbor")]
 
 use crate::{
-    AbutError,
-    frame::{FramedReader, FramedWriter}
+    AbutError, Codec, DecodeLimits, MessageSource,
+    frame::{FramedReader, FramedWriter, Messages, limit}
 };
 use std::io::{Read, Write};
 use {
     serde::{Serialize, de::DeserializeOwned},
+    serde_cbor::Value,
 };
 
+/// Encodes `value` in canonical CBOR (RFC 7049 §3.9).
+///
+/// Map keys are sorted length-first then bytewise, integers and floats use
+/// their shortest form and every array/map carries a definite length, so
+/// equal values always produce identical bytes.
+pub fn to_canonical_vec<T: Serialize>(value: &T) -> Result<Vec<u8>, AbutError> {
+    let value = serde_cbor::value::to_value(value).map_err(AbutError::cbor_encode)?;
+    serde_cbor::to_vec(&value).map_err(AbutError::cbor_encode)
+}
+
+/// Returns an error unless `bytes` is exactly the canonical encoding of the value it holds.
+pub fn check_canonical(bytes: &[u8]) -> Result<(), AbutError> {
+    let value = decode_limited::<Value>(bytes, &DecodeLimits::default())?;
+    ensure_canonical(&value, bytes)
+}
+
+fn ensure_canonical(value: &Value, bytes: &[u8]) -> Result<(), AbutError> {
+    let canonical = serde_cbor::to_vec(value).map_err(AbutError::cbor_encode)?;
+    if canonical != bytes {
+        return Err(AbutError::cbor_non_canonical(bytes.len()));
+    }
+    Ok(())
+}
+
+fn decode_limited<T: DeserializeOwned>(bytes: &[u8], limits: &DecodeLimits) -> Result<T, AbutError> {
+    let mut de = serde_cbor::Deserializer::from_slice(bytes);
+    let value = limit::deserialize(&mut de, limits).map_err(|e| e.into_abut(AbutError::cbor_decode))?;
+    de.end().map_err(AbutError::cbor_decode)?;
+    Ok(value)
+}
+
+/// [`Codec`] marker for (non-canonical) CBOR.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Cbor;
+
+impl Codec for Cbor {
+    fn encode<T: Serialize>(value: &T, buf: &mut Vec<u8>) -> Result<(), AbutError> {
+        serde_cbor::to_writer(buf, value).map_err(AbutError::cbor_encode)
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8], limits: &DecodeLimits) -> Result<T, AbutError> {
+        decode_limited(bytes, limits)
+    }
+}
+
 pub struct FramedCborWriter<W: Write> {
     inner: FramedWriter<W>,
+    canonical: bool,
 }
 
 impl<W: Write> FramedCborWriter<W> {
     pub fn new(inner: W) -> Self {
-        Self { inner: FramedWriter::new(inner) }
+        Self { inner: FramedWriter::new(inner), canonical: false }
+    }
+
+    /// Creates a writer that emits canonical CBOR (see [`to_canonical_vec`]).
+    pub fn canonical(inner: W) -> Self {
+        Self { inner: FramedWriter::new(inner), canonical: true }
     }
 
+    pub fn set_canonical(&mut self, canonical: bool) { self.canonical = canonical; }
+    pub fn is_canonical(&self) -> bool { self.canonical }
+
     pub fn send<T: Serialize>(&mut self, value: &T) -> Result<(), AbutError> {
-        let encoded = ::serde_cbor::to_vec(value)
-            .map_err(|e| AbutError::new(crate::AbutCode::Io).ctx(e))?; // or add CborEncode code (recommended)
+        let encoded = if self.canonical {
+            to_canonical_vec(value)?
+        } else {
+            ::serde_cbor::to_vec(value).map_err(AbutError::cbor_encode)?
+        };
         self.inner.write_frame(&encoded)
     }
+
+    pub fn flush(&mut self) -> Result<(), AbutError> {
+        self.inner.flush()
+    }
 }
 
 pub struct FramedCborReader<R: Read> {
     inner: FramedReader<R>,
     buf: Vec<u8>,
+    strict: bool,
 }
 
 impl<R: Read> FramedCborReader<R> {
     pub fn new(inner: R) -> Self {
-        Self { inner: FramedReader::new(inner), buf: Vec::new() }
+        Self { inner: FramedReader::new(inner), buf: Vec::new(), strict: false }
+    }
+
+    /// Creates a reader that rejects frames which are not canonical CBOR.
+    pub fn strict(inner: R) -> Self {
+        Self { inner: FramedReader::new(inner), buf: Vec::new(), strict: true }
     }
 
     pub fn with_inner(inner: FramedReader<R>) -> Self {
-        Self { inner, buf: Vec::new() }
+        Self { inner, buf: Vec::new(), strict: false }
     }
 
+    /// When enabled, non-canonical frames fail with `CborNonCanonical`.
+    /// The frame is consumed either way, so the stream stays aligned.
+    pub fn set_strict(&mut self, strict: bool) { self.strict = strict; }
+    pub fn is_strict(&self) -> bool { self.strict }
+
     pub fn recv<T: DeserializeOwned>(&mut self) -> Result<T, AbutError> {
         self.inner.recv_into(&mut self.buf)?;
-        ::serde_cbor::from_slice(&self.buf)
-            .map_err(|e| AbutError::new(crate::AbutCode::Io).ctx(e)) // or add CborDecode code
+        self.decode()
+    }
+
+    /// Like [`recv`](Self::recv), but returns `Ok(None)` on a clean EOF at a frame boundary.
+    pub fn try_recv<T: DeserializeOwned>(&mut self) -> Result<Option<T>, AbutError> {
+        if !self.inner.try_recv_into(&mut self.buf)? {
+            return Ok(None);
+        }
+        self.decode().map(Some)
+    }
+
+    /// Iterates over decoded messages until the peer closes the stream.
+    pub fn messages<T: DeserializeOwned>(&mut self) -> Messages<'_, Self, T> {
+        Messages::new(self)
     }
 
     pub fn inner_mut(&mut self) -> &mut FramedReader<R> { &mut self.inner }
+
+    fn decode<T: DeserializeOwned>(&self) -> Result<T, AbutError> {
+        let limits = self.inner.config().limits;
+        if self.strict {
+            let value = decode_limited::<Value>(&self.buf, &limits)?;
+            ensure_canonical(&value, &self.buf)?;
+            return serde_cbor::value::from_value(value).map_err(AbutError::cbor_decode);
+        }
+        decode_limited(&self.buf, &limits)
+    }
+}
+
+impl<R: Read> MessageSource for FramedCborReader<R> {
+    fn try_recv<T: DeserializeOwned>(&mut self) -> Result<Option<T>, AbutError> {
+        FramedCborReader::try_recv(self)
+    }
 }
 
 #[cfg(test)]
@@ -70,7 +171,7 @@ mod tests {
         let original = TelemetryData {
             id: 42,
             label: "sensor_alpha".to_string(),
-            values: vec![1.0, 2.5, 3.14],
+            values: vec![1.0, 2.5, 3.5],
         };
 
         writer.send(&original).expect("Send should succeed");
@@ -142,4 +243,103 @@ mod tests {
         // This should fail at the Framing layer before even reaching CBOR logic
         assert!(res.is_err());
     }
-}
\ No newline at end of file
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Signed {
+        zeta: u64,
+        alpha: std::collections::HashMap<String, i32>,
+        mid: f64,
+    }
+
+    fn signed_sample() -> Signed {
+        let alpha = [("ccc", 3), ("a", 1), ("bb", -2), ("dd", 4)]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect();
+        Signed { zeta: 500, alpha, mid: 1.5 }
+    }
+
+    #[test]
+    fn test_canonical_encoding_is_deterministic() {
+        let first = to_canonical_vec(&signed_sample()).unwrap();
+        for _ in 0..8 {
+            // HashMap iteration order differs between instances.
+            assert_eq!(to_canonical_vec(&signed_sample()).unwrap(), first);
+        }
+        check_canonical(&first).unwrap();
+    }
+
+    #[test]
+    fn test_canonical_sorts_keys_length_first() {
+        let bytes = to_canonical_vec(&signed_sample()).unwrap();
+        let value: Value = serde_cbor::from_slice(&bytes).unwrap();
+
+        // Top-level keys: "mid" (3) < "zeta" (4) < "alpha" (5).
+        let expected_prefix = [0xa3, 0x63, b'm', b'i', b'd', 0xf9, 0x3e, 0x00, 0x64, b'z', b'e', b't', b'a'];
+        assert_eq!(&bytes[..expected_prefix.len()], &expected_prefix);
+        assert!(matches!(value, Value::Map(_)));
+    }
+
+    #[test]
+    fn test_strict_reader_rejects_non_canonical() {
+        let mut buffer = Vec::new();
+        let mut raw = FramedWriter::new(&mut buffer);
+        // {"bb": 1, "a": 2} with keys out of canonical order.
+        raw.write_frame(&[0xa2, 0x62, b'b', b'b', 0x01, 0x61, b'a', 0x02]).unwrap();
+        // The integer 1 in a non-shortest (one extra byte) form.
+        raw.write_frame(&[0x18, 0x01]).unwrap();
+        // An indefinite-length array [1].
+        raw.write_frame(&[0x9f, 0x01, 0xff]).unwrap();
+
+        let mut writer = FramedCborWriter::canonical(&mut buffer);
+        writer.send(&signed_sample()).unwrap();
+
+        let mut reader = FramedCborReader::strict(Cursor::new(buffer));
+        let err = reader.recv::<std::collections::BTreeMap<String, u8>>().unwrap_err();
+        assert!(matches!(err.code, crate::AbutCode::CborNonCanonical));
+        let err = reader.recv::<u8>().unwrap_err();
+        assert!(matches!(err.code, crate::AbutCode::CborNonCanonical));
+        let err = reader.recv::<Vec<u8>>().unwrap_err();
+        assert!(matches!(err.code, crate::AbutCode::CborNonCanonical));
+
+        let decoded: Signed = reader.recv().expect("canonical frame accepted");
+        assert_eq!(decoded, signed_sample());
+    }
+
+    #[test]
+    fn test_lenient_reader_accepts_non_canonical() {
+        let mut buffer = Vec::new();
+        FramedWriter::new(&mut buffer).write_frame(&[0x18, 0x01]).unwrap();
+
+        let mut reader = FramedCborReader::new(Cursor::new(buffer));
+        assert_eq!(reader.recv::<u8>().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_cbor_nesting_limit() {
+        let mut frame = vec![0x81; 100]; // 100 nested one-element arrays
+        frame.push(0x01);
+        let mut buffer = Vec::new();
+        FramedWriter::new(&mut buffer).write_frame(&frame).unwrap();
+        FramedCborWriter::new(&mut buffer).send(&"still aligned").unwrap();
+
+        let mut reader = FramedCborReader::new(Cursor::new(buffer));
+        let err = reader.recv::<Value>().unwrap_err();
+        assert!(matches!(err.code, crate::AbutCode::DecodeLimit));
+        assert!(err.to_string().contains("nesting depth"));
+
+        assert_eq!(reader.recv::<String>().unwrap(), "still aligned");
+    }
+
+    #[test]
+    fn test_cbor_declared_length_limit() {
+        let mut buffer = Vec::new();
+        // An array header claiming u32::MAX elements with no elements following.
+        FramedWriter::new(&mut buffer).write_frame(&[0x9a, 0xff, 0xff, 0xff, 0xff]).unwrap();
+
+        let mut reader = FramedCborReader::strict(Cursor::new(buffer));
+        let err = reader.recv::<Vec<u8>>().unwrap_err();
+        assert!(matches!(err.code, crate::AbutCode::DecodeLimit));
+        assert!(err.to_string().contains("collection length"));
+    }
+}
@@ -0,0 +1,214 @@
+//! Chunked transfer of payloads larger than `max_frame_len`.
+//!
+//! One logical message is sent as a run of frames, each starting with a
+//! one-byte flag: [`CHUNK_MORE`] for every chunk but the last, which carries
+//! [`CHUNK_LAST`]. The last chunk may be empty, so a writer never needs to
+//! know the total length up front.
+
+use std::io::{self, Read, Write};
+
+use crate::{AbutCode, AbutError};
+use super::{FramedReader, FramedWriter};
+
+/// Flag byte of a chunk that is followed by at least one more.
+pub const CHUNK_MORE: u8 = 0x00;
+/// Flag byte of the final chunk of a message.
+pub const CHUNK_LAST: u8 = 0x01;
+
+/// Payload bytes per chunk used by [`FramedWriter::write_stream`].
+/// Leaves room for the flag byte under the default `max_frame_len`.
+pub const DEFAULT_CHUNK_LEN: usize = 32 * 1024;
+
+impl<W: Write> FramedWriter<W> {
+    /// Sends everything `src` yields as one chunked message of [`DEFAULT_CHUNK_LEN`] chunks.
+    ///
+    /// Returns the number of payload bytes sent. Does NOT flush.
+    pub fn write_stream<S: Read>(&mut self, src: S) -> Result<u64, AbutError> {
+        self.write_stream_chunked(src, DEFAULT_CHUNK_LEN)
+    }
+
+    /// Like [`write_stream`](Self::write_stream) with an explicit chunk payload size.
+    ///
+    /// `chunk_len + 1` must not exceed the receiver's `max_frame_len`.
+    pub fn write_stream_chunked<S: Read>(&mut self, mut src: S, chunk_len: usize) -> Result<u64, AbutError> {
+        let chunk_len = chunk_len.max(1);
+        let mut chunk = vec![0u8; chunk_len + 1];
+        let mut total = 0u64;
+
+        loop {
+            let filled = fill(&mut src, &mut chunk[1..])?;
+            total += filled as u64;
+            if filled < chunk_len {
+                chunk[0] = CHUNK_LAST;
+                return self.write_frame(&chunk[..=filled]).map(|()| total);
+            }
+            chunk[0] = CHUNK_MORE;
+            self.write_frame(&chunk)?;
+        }
+    }
+}
+
+fn fill<S: Read>(src: &mut S, buf: &mut [u8]) -> Result<usize, AbutError> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match src.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(filled)
+}
+
+impl<R: Read> FramedReader<R> {
+    /// Starts receiving a chunked message, exposing its payload as [`Read`].
+    ///
+    /// Only one chunk is buffered at a time. The body must be read to EOF (or
+    /// [`skip`](ChunkedBody::skip)ped) before the next frame can be received.
+    pub fn recv_stream(&mut self) -> ChunkedBody<'_, R> {
+        ChunkedBody { reader: self, chunk: Vec::new(), pos: 0, last: false, total: 0 }
+    }
+}
+
+/// Payload of a chunked message, returned by [`FramedReader::recv_stream`].
+///
+/// Read errors wrap the underlying [`AbutError`]; a peer closing the stream
+/// before the final chunk surfaces as `AbutCode::TruncatedFrame`.
+#[derive(Debug)]
+pub struct ChunkedBody<'a, R: Read> {
+    reader: &'a mut FramedReader<R>,
+    chunk: Vec<u8>,
+    pos: usize,
+    last: bool,
+    total: u64,
+}
+
+impl<R: Read> ChunkedBody<'_, R> {
+    /// Payload bytes received so far.
+    pub fn received(&self) -> u64 { self.total }
+
+    /// True once the final chunk has been received and fully read.
+    pub fn is_done(&self) -> bool { self.last && self.pos == self.chunk.len() }
+
+    /// Discards the rest of the message, leaving the reader at the next frame.
+    pub fn skip(mut self) -> Result<u64, AbutError> {
+        while !self.last {
+            self.next_chunk()?;
+        }
+        Ok(self.total)
+    }
+
+    fn next_chunk(&mut self) -> Result<(), AbutError> {
+        match self.reader.recv_into(&mut self.chunk) {
+            Ok(()) => {}
+            Err(e) if e.code == AbutCode::Closed => {
+                return Err(AbutError::new(AbutCode::TruncatedFrame)
+                    .ctx(format_args!("stream closed after {} bytes, before the final chunk", self.total)));
+            }
+            Err(e) => return Err(e),
+        }
+        match self.chunk.first() {
+            Some(&CHUNK_MORE) => {}
+            Some(&CHUNK_LAST) => self.last = true,
+            Some(flag) => return Err(AbutError::protocol(format_args!("unknown chunk flag {flag:#04x}"))),
+            None => return Err(AbutError::protocol("empty chunk frame")),
+        }
+        self.pos = 1;
+        self.total += (self.chunk.len() - 1) as u64;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for ChunkedBody<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            if self.last || buf.is_empty() {
+                return Ok(0);
+            }
+            self.next_chunk().map_err(io::Error::other)?;
+        }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_stream_roundtrip_larger_than_max_frame() {
+        let payload = pattern(10_000);
+        let mut buffer = Vec::new();
+        let mut writer = FramedWriter::new(&mut buffer);
+        let sent = writer.write_stream_chunked(Cursor::new(&payload), 1000).unwrap();
+        writer.write_frame(b"trailer").unwrap();
+        assert_eq!(sent, 10_000);
+
+        let mut reader = FramedReader::with_max(Cursor::new(buffer), 1001);
+        let mut body = reader.recv_stream();
+        let mut out = Vec::new();
+        body.read_to_end(&mut out).unwrap();
+        assert!(body.is_done());
+        assert_eq!(out, payload);
+
+        let mut trailer = Vec::new();
+        reader.recv_into(&mut trailer).unwrap();
+        assert_eq!(trailer, b"trailer");
+    }
+
+    #[test]
+    fn test_stream_exact_multiple_and_empty() {
+        let mut buffer = Vec::new();
+        let mut writer = FramedWriter::new(&mut buffer);
+        writer.write_stream_chunked(Cursor::new(pattern(300)), 100).unwrap();
+        writer.write_stream(io::empty()).unwrap();
+
+        let mut reader = FramedReader::new(Cursor::new(buffer));
+        let mut out = Vec::new();
+        reader.recv_stream().read_to_end(&mut out).unwrap();
+        assert_eq!(out, pattern(300));
+
+        out.clear();
+        reader.recv_stream().read_to_end(&mut out).unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_stream_skip_keeps_alignment() {
+        let mut buffer = Vec::new();
+        let mut writer = FramedWriter::new(&mut buffer);
+        writer.write_stream_chunked(Cursor::new(pattern(500)), 64).unwrap();
+        writer.write_frame(b"next").unwrap();
+
+        let mut reader = FramedReader::new(Cursor::new(buffer));
+        let mut body = reader.recv_stream();
+        body.read_exact(&mut [0u8; 10]).unwrap();
+        assert_eq!(body.skip().unwrap(), 500);
+
+        let mut next = Vec::new();
+        reader.recv_into(&mut next).unwrap();
+        assert_eq!(next, b"next");
+    }
+
+    #[test]
+    fn test_stream_closed_before_last_chunk() {
+        let mut buffer = Vec::new();
+        FramedWriter::new(&mut buffer).write_stream_chunked(Cursor::new(pattern(250)), 100).unwrap();
+        let last_chunk = crate::frame::LEN_PREFIX + 1 + 50;
+        buffer.truncate(buffer.len() - last_chunk);
+
+        let mut reader = FramedReader::new(Cursor::new(buffer));
+        let err = reader.recv_stream().read_to_end(&mut Vec::new()).unwrap_err();
+        let inner = err.into_inner().unwrap().downcast::<AbutError>().unwrap();
+        assert_eq!(inner.code, AbutCode::TruncatedFrame);
+    }
+}
@@ -0,0 +1,200 @@
+//! Optional per-frame compression (`zstd` and `lz4` features).
+//!
+//! A writer with compression enabled puts a one-byte flag in front of every
+//! frame: [`FLAG_RAW`] for frames sent as-is, or the algorithm used. Frames
+//! below the threshold, and frames that would not shrink, go raw. The reader
+//! must have `ReaderConfig::compressed` set; it checks the declared
+//! decompressed size against `max_frame_len` before inflating anything.
+
+use crate::AbutError;
+
+/// The frame follows uncompressed.
+pub const FLAG_RAW: u8 = 0x00;
+/// The frame is a zstd frame with its content size recorded.
+pub const FLAG_ZSTD: u8 = 0x01;
+/// The frame is an lz4 block preceded by its decompressed size (`u32` LE).
+pub const FLAG_LZ4: u8 = 0x02;
+
+/// Frames shorter than this are not worth compressing by default.
+pub const DEFAULT_THRESHOLD: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    #[cfg(feature = "zstd")]
+    Zstd { level: i32 },
+    #[cfg(feature = "lz4")]
+    Lz4,
+}
+
+/// Writer-side compression settings; see [`FramedWriter::set_compression`](super::FramedWriter::set_compression).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compression {
+    pub algorithm: Algorithm,
+    /// Frames shorter than this are sent raw.
+    pub threshold: usize,
+}
+
+impl Compression {
+    pub fn new(algorithm: Algorithm) -> Self {
+        Self { algorithm, threshold: DEFAULT_THRESHOLD }
+    }
+
+    #[cfg(feature = "zstd")]
+    pub fn zstd(level: i32) -> Self {
+        Self::new(Algorithm::Zstd { level })
+    }
+
+    #[cfg(feature = "lz4")]
+    pub fn lz4() -> Self {
+        Self::new(Algorithm::Lz4)
+    }
+
+    #[cfg_attr(not(any(feature = "zstd", feature = "lz4")), allow(unused_variables, unreachable_code))]
+    fn pack(&self, bytes: &[u8]) -> Result<(u8, Vec<u8>), AbutError> {
+        Ok(match self.algorithm {
+            #[cfg(feature = "zstd")]
+            Algorithm::Zstd { level } => (FLAG_ZSTD, zstd::bulk::compress(bytes, level)?),
+            #[cfg(feature = "lz4")]
+            Algorithm::Lz4 => (FLAG_LZ4, lz4_flex::block::compress_prepend_size(bytes)),
+        })
+    }
+
+    /// Replaces `wire` with the flagged encoding of `bytes`.
+    pub(crate) fn encode(&self, bytes: &[u8], wire: &mut Vec<u8>) -> Result<(), AbutError> {
+        wire.clear();
+        if bytes.len() >= self.threshold {
+            let (flag, packed) = self.pack(bytes)?;
+            if packed.len() < bytes.len() {
+                wire.push(flag);
+                wire.extend_from_slice(&packed);
+                return Ok(());
+            }
+        }
+        wire.push(FLAG_RAW);
+        wire.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// Decodes a flagged frame into `dst`, refusing output longer than `max`.
+pub(crate) fn decode(wire: &[u8], max: usize, dst: &mut Vec<u8>) -> Result<(), AbutError> {
+    let (&flag, body) = wire.split_first().ok_or_else(|| AbutError::protocol("compressed frame without a flag byte"))?;
+    dst.clear();
+    match flag {
+        FLAG_RAW => {
+            if body.len() > max {
+                return Err(AbutError::frame_too_large(body.len(), max));
+            }
+            dst.extend_from_slice(body);
+        }
+        #[cfg(feature = "zstd")]
+        FLAG_ZSTD => {
+            let declared = zstd::zstd_safe::get_frame_content_size(body)
+                .map_err(|_| AbutError::decompress("zstd", "not a zstd frame"))?
+                .ok_or_else(|| AbutError::decompress("zstd", "frame does not declare its size"))?;
+            if declared > max as u64 {
+                return Err(AbutError::frame_too_large(declared as usize, max));
+            }
+            *dst = zstd::bulk::decompress(body, declared as usize).map_err(|e| AbutError::decompress("zstd", e))?;
+        }
+        #[cfg(feature = "lz4")]
+        FLAG_LZ4 => {
+            let (size, block) = body
+                .split_first_chunk::<4>()
+                .ok_or_else(|| AbutError::decompress("lz4", "missing size prefix"))?;
+            let declared = u32::from_le_bytes(*size) as usize;
+            if declared > max {
+                return Err(AbutError::frame_too_large(declared, max));
+            }
+            *dst = lz4_flex::block::decompress(block, declared).map_err(|e| AbutError::decompress("lz4", e))?;
+            if dst.len() != declared {
+                return Err(AbutError::decompress("lz4", format_args!("{} bytes decoded, {declared} declared", dst.len())));
+            }
+        }
+        other => return Err(AbutError::protocol(format_args!("unsupported compression flag {other:#04x}"))),
+    }
+    Ok(())
+}
+
+#[cfg(all(test, any(feature = "zstd", feature = "lz4")))]
+mod tests {
+    use super::*;
+    use crate::frame::tests::Drops;
+    use crate::frame::{FramedReader, FramedWriter, LEN_PREFIX};
+    use crate::{AbutCode, ReaderConfig};
+    use std::io::Cursor;
+
+    fn algorithms() -> Vec<Compression> {
+        vec![
+            #[cfg(feature = "zstd")]
+            Compression::zstd(3),
+            #[cfg(feature = "lz4")]
+            Compression::lz4(),
+        ]
+    }
+
+    #[test]
+    fn test_roundtrip_and_small_frames_stay_raw() {
+        let log_line = b"INFO request served in 3ms\n".repeat(100);
+        for c in algorithms() {
+            let mut buf = Vec::new();
+            let mut w = FramedWriter::new(&mut buf);
+            w.set_compression(Some(c));
+            w.write_frame(b"tiny").unwrap();
+            w.write_frame(&log_line).unwrap();
+
+            // Length prefix, raw flag, payload.
+            assert_eq!(&buf[4..9], b"\x00tiny");
+            assert!(buf.len() < log_line.len() / 4);
+
+            let cfg = ReaderConfig { compressed: true, ..Default::default() };
+            let mut r = FramedReader::with_config(Cursor::new(&buf), cfg);
+            let frames: Vec<_> = r.frames().map(Result::unwrap).collect();
+            assert_eq!(frames, vec![b"tiny".to_vec(), log_line.clone()]);
+
+            // A frame too big for the caller's slice is consumed, and observed as dropped.
+            let mut r = FramedReader::with_config(Cursor::new(&buf), cfg);
+            let drops = Drops::default();
+            r.set_observer(Some(Box::new(drops.clone())));
+            assert_eq!(r.read_frame(&mut [0u8; 2]).unwrap_err().code, AbutCode::BufferTooSmall);
+            assert_eq!(*drops.0.lock().unwrap(), [(5, Some(b"\x00tiny".to_vec()), AbutCode::BufferTooSmall)]);
+        }
+    }
+
+    #[test]
+    fn test_decompression_bomb_is_refused() {
+        let bomb = vec![0u8; 1 << 20];
+        for c in algorithms() {
+            let mut buf = Vec::new();
+            let mut w = FramedWriter::new(&mut buf);
+            w.set_compression(Some(c));
+            w.write_frame(&bomb).unwrap();
+            w.write_frame(b"after").unwrap();
+            assert!(buf.len() < 8 * 1024);
+
+            let wire_len = buf.len() - 2 * LEN_PREFIX - 1 - b"after".len();
+            let cfg = ReaderConfig { compressed: true, max_frame_len: 64 * 1024, ..Default::default() };
+            let mut r = FramedReader::with_config(Cursor::new(buf), cfg);
+            let drops = Drops::default();
+            r.set_observer(Some(Box::new(drops.clone())));
+            let mut dst = Vec::new();
+            assert_eq!(r.recv_into(&mut dst).unwrap_err().code, AbutCode::FrameTooLarge);
+            // The observer still hears of it, with the bytes as received.
+            let (len, bytes, code) = drops.0.lock().unwrap().pop().unwrap();
+            assert_eq!((len, bytes.map(|b| b.len()), code), (wire_len, Some(wire_len), AbutCode::FrameTooLarge));
+            // The compressed frame was consumed whole, so the stream is still aligned.
+            r.recv_into(&mut dst).unwrap();
+            assert_eq!(dst, b"after");
+        }
+    }
+
+    #[test]
+    fn test_corrupt_payload_is_a_decode_error() {
+        let mut wire = Vec::new();
+        algorithms()[0].encode(&[7u8; 4096], &mut wire).unwrap();
+        let last = wire.len() - 1;
+        wire.truncate(last / 2);
+        let err = decode(&wire, 1 << 20, &mut Vec::new()).unwrap_err();
+        assert!(err.code.is_decode() || err.code == AbutCode::FrameTooLarge, "{err}");
+    }
+}
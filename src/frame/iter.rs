@@ -0,0 +1,103 @@
+//! Iterator adapters over framed readers.
+
+use std::io::Read;
+use std::marker::PhantomData;
+
+use serde::de::DeserializeOwned;
+
+use crate::{AbutError, MessageSource};
+use super::FramedReader;
+
+/// Iterator returned by [`FramedReader::frames`].
+///
+/// Yields owned frames until a clean EOF at a frame boundary. Any error,
+/// including EOF part-way through a frame, is yielded once and ends iteration.
+#[derive(Debug)]
+pub struct Frames<'a, R: Read> {
+    reader: &'a mut FramedReader<R>,
+    done: bool,
+}
+
+impl<'a, R: Read> Frames<'a, R> {
+    pub(crate) fn new(reader: &'a mut FramedReader<R>) -> Self {
+        Self { reader, done: false }
+    }
+}
+
+impl<R: Read> Iterator for Frames<'_, R> {
+    type Item = Result<Vec<u8>, AbutError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let mut frame = Vec::new();
+        match self.reader.try_recv_into(&mut frame) {
+            Ok(true) => Some(Ok(frame)),
+            Ok(false) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl<R: Read> std::iter::FusedIterator for Frames<'_, R> {}
+
+/// Iterator returned by the typed readers' `messages::<T>()`.
+///
+/// Decode errors leave the stream aligned, so they are yielded and iteration
+/// continues (or they are skipped, see [`skip_decode_errors`](Self::skip_decode_errors)).
+/// Any other error is yielded once and ends iteration.
+pub struct Messages<'a, S: ?Sized, T> {
+    source: &'a mut S,
+    skip_decode_errors: bool,
+    skipped: usize,
+    done: bool,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<'a, S: MessageSource + ?Sized, T: DeserializeOwned> Messages<'a, S, T> {
+    pub fn new(source: &'a mut S) -> Self {
+        Self { source, skip_decode_errors: false, skipped: 0, done: false, _marker: PhantomData }
+    }
+
+    /// Silently drops frames that fail to decode instead of yielding their errors.
+    pub fn skip_decode_errors(mut self) -> Self {
+        self.skip_decode_errors = true;
+        self
+    }
+
+    /// Number of frames dropped so far because of `skip_decode_errors`.
+    pub fn skipped(&self) -> usize { self.skipped }
+}
+
+impl<S: MessageSource + ?Sized, T: DeserializeOwned> Iterator for Messages<'_, S, T> {
+    type Item = Result<T, AbutError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            match self.source.try_recv::<T>() {
+                Ok(Some(msg)) => return Some(Ok(msg)),
+                Ok(None) => self.done = true,
+                Err(e) if e.code.is_decode() => {
+                    if !self.skip_decode_errors {
+                        return Some(Err(e));
+                    }
+                    self.skipped += 1;
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
+}
+
+impl<S: MessageSource + ?Sized, T: DeserializeOwned> std::iter::FusedIterator for Messages<'_, S, T> {}
@@ -0,0 +1,143 @@
+#![cfg(feature = "json")]
+
+use std::io::{Read, Write};
+use crate::{AbutError, Codec, DecodeLimits, MessageSource, frame::{FramedReader, FramedWriter, Messages, limit}};
+
+use serde::{Serialize, de::DeserializeOwned};
+
+/// [`Codec`] marker for compact JSON.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json;
+
+impl Codec for Json {
+    fn encode<T: Serialize>(value: &T, buf: &mut Vec<u8>) -> Result<(), AbutError> {
+        serde_json::to_writer(buf, value).map_err(AbutError::json_encode)
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8], limits: &DecodeLimits) -> Result<T, AbutError> {
+        let mut de = serde_json::Deserializer::from_slice(bytes);
+        let value = limit::deserialize(&mut de, limits).map_err(|e| e.into_abut(AbutError::json_decode))?;
+        de.end().map_err(AbutError::json_decode)?;
+        Ok(value)
+    }
+}
+
+/// Writes compact JSON documents, one per frame.
+pub struct FramedJsonWriter<W: Write> {
+    inner: FramedWriter<W>,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> FramedJsonWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner: FramedWriter::new(inner), buf: Vec::new() }
+    }
+
+    pub fn send<T: Serialize>(&mut self, value: &T) -> Result<(), AbutError> {
+        self.buf.clear();
+        Json::encode(value, &mut self.buf)?;
+        self.inner.write_frame(&self.buf)
+    }
+
+    pub fn flush(&mut self) -> Result<(), AbutError> {
+        self.inner.flush()
+    }
+}
+
+pub struct FramedJsonReader<R: Read> {
+    inner: FramedReader<R>,
+    buf: Vec<u8>,
+}
+
+impl<R: Read> FramedJsonReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner: FramedReader::new(inner), buf: Vec::new() }
+    }
+
+    pub fn with_inner(inner: FramedReader<R>) -> Self {
+        Self { inner, buf: Vec::new() }
+    }
+
+    pub fn recv<T: DeserializeOwned>(&mut self) -> Result<T, AbutError> {
+        self.inner.recv_into(&mut self.buf)?;
+        self.decode()
+    }
+
+    /// Like [`recv`](Self::recv), but returns `Ok(None)` on a clean EOF at a frame boundary.
+    pub fn try_recv<T: DeserializeOwned>(&mut self) -> Result<Option<T>, AbutError> {
+        if !self.inner.try_recv_into(&mut self.buf)? {
+            return Ok(None);
+        }
+        self.decode().map(Some)
+    }
+
+    /// Iterates over decoded messages until the peer closes the stream.
+    pub fn messages<T: DeserializeOwned>(&mut self) -> Messages<'_, Self, T> {
+        Messages::new(self)
+    }
+
+    pub fn inner_mut(&mut self) -> &mut FramedReader<R> { &mut self.inner }
+
+    fn decode<T: DeserializeOwned>(&self) -> Result<T, AbutError> {
+        Json::decode(&self.buf, &self.inner.config().limits)
+    }
+}
+
+impl<R: Read> MessageSource for FramedJsonReader<R> {
+    fn try_recv<T: DeserializeOwned>(&mut self) -> Result<Option<T>, AbutError> {
+        FramedJsonReader::try_recv(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AbutCode;
+    use serde::{Deserialize, Serialize};
+    use std::io::Cursor;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Sample {
+        id: u32,
+        name: String,
+    }
+
+    #[test]
+    fn test_json_roundtrip() {
+        let mut buffer = Vec::new();
+        let mut writer = FramedJsonWriter::new(&mut buffer);
+
+        let original = Sample { id: 3, name: "gauge".into() };
+        writer.send(&original).unwrap();
+
+        assert_eq!(&buffer[4..], br#"{"id":3,"name":"gauge"}"#);
+
+        let mut reader = FramedJsonReader::new(Cursor::new(buffer));
+        let decoded: Sample = reader.recv().unwrap();
+        assert_eq!(original, decoded);
+    }
+
+    #[test]
+    fn test_json_decode_error_keeps_alignment() {
+        let mut buffer = Vec::new();
+        FramedWriter::new(&mut buffer).write_frame(b"{not json").unwrap();
+        FramedJsonWriter::new(&mut buffer).send(&[1, 2]).unwrap();
+
+        let mut reader = FramedJsonReader::new(Cursor::new(buffer));
+        let err = reader.recv::<Vec<u8>>().unwrap_err();
+        assert!(matches!(err.code, AbutCode::JsonDecode));
+
+        let next: Vec<u8> = reader.recv().unwrap();
+        assert_eq!(next, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_json_respects_max_frame_len() {
+        let mut buffer = Vec::new();
+        FramedJsonWriter::new(&mut buffer).send(&"a fairly long string value").unwrap();
+
+        let mut reader = FramedJsonReader::with_inner(FramedReader::with_max(Cursor::new(buffer), 8));
+        let err = reader.recv::<String>().unwrap_err();
+        assert!(matches!(err.code, AbutCode::FrameTooLarge));
+    }
+}
@@ -0,0 +1,375 @@
+//! Resource limits for the typed decoders.
+//!
+//! [`deserialize`] wraps any serde `Deserializer` so that every sequence,
+//! map and enum it produces is counted against a [`DecodeLimits`] budget.
+//! The checks run as the value is built, before the inner decoder recurses
+//! further or the visitor allocates storage for a declared length.
+
+use std::cell::Cell;
+use std::fmt;
+use std::mem::size_of;
+
+use serde::de::{
+    self, DeserializeSeed, Deserializer, EnumAccess, MapAccess, SeqAccess, VariantAccess, Visitor,
+};
+
+use crate::{AbutError, DecodeLimits};
+
+/// Outcome of a limited decode that did not produce a value.
+pub(crate) enum LimitedError<E> {
+    /// A [`DecodeLimits`] bound was hit.
+    Limit(AbutError),
+    /// The underlying decoder failed on its own.
+    Decode(E),
+}
+
+impl<E> LimitedError<E> {
+    /// Collapses into an `AbutError`, mapping decoder failures through `decode`.
+    pub(crate) fn into_abut(self, decode: impl FnOnce(E) -> AbutError) -> AbutError {
+        match self {
+            Self::Limit(err) => err,
+            Self::Decode(err) => decode(err),
+        }
+    }
+}
+
+/// Deserializes a `T` from `de`, enforcing `limits`.
+pub(crate) fn deserialize<'de, T, D>(de: D, limits: &DecodeLimits) -> Result<T, LimitedError<D::Error>>
+where
+    T: de::Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    let budget = Budget::new(limits);
+    T::deserialize(Limited { de, budget: &budget }).map_err(|e| match budget.violation.take() {
+        Some(err) => LimitedError::Limit(err),
+        None => LimitedError::Decode(e),
+    })
+}
+
+struct Budget {
+    limits: DecodeLimits,
+    depth: Cell<usize>,
+    alloc: Cell<usize>,
+    violation: Cell<Option<AbutError>>,
+}
+
+impl Budget {
+    fn new(limits: &DecodeLimits) -> Self {
+        Self { limits: *limits, depth: Cell::new(0), alloc: Cell::new(0), violation: Cell::new(None) }
+    }
+
+    fn fail<E: de::Error>(&self, err: AbutError) -> E {
+        let e = E::custom(&err);
+        self.violation.set(Some(err));
+        e
+    }
+
+    fn enter<E: de::Error>(&self) -> Result<(), E> {
+        let depth = self.depth.get() + 1;
+        if depth > self.limits.max_depth {
+            return Err(self.fail(AbutError::decode_limit("nesting depth", depth, self.limits.max_depth)));
+        }
+        self.depth.set(depth);
+        Ok(())
+    }
+
+    fn leave(&self) {
+        self.depth.set(self.depth.get() - 1);
+    }
+
+    fn check_len<E: de::Error>(&self, len: usize) -> Result<(), E> {
+        if len > self.limits.max_collection_len {
+            return Err(self.fail(AbutError::decode_limit(
+                "collection length",
+                len,
+                self.limits.max_collection_len,
+            )));
+        }
+        Ok(())
+    }
+
+    fn charge<E: de::Error>(&self, bytes: usize) -> Result<(), E> {
+        let total = self.alloc.get().saturating_add(bytes);
+        if total > self.limits.max_alloc {
+            return Err(self.fail(AbutError::decode_limit("decoded size", total, self.limits.max_alloc)));
+        }
+        self.alloc.set(total);
+        Ok(())
+    }
+}
+
+struct Limited<'b, D> {
+    de: D,
+    budget: &'b Budget,
+}
+
+macro_rules! forward_deserialize {
+    ($($method:ident ( $($arg:ident : $ty:ty),* );)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, $($arg: $ty,)* visitor: V) -> Result<V::Value, Self::Error> {
+                self.de.$method($($arg,)* Wrap { visitor, budget: self.budget })
+            }
+        )*
+    };
+}
+
+impl<'de, D: Deserializer<'de>> Deserializer<'de> for Limited<'_, D> {
+    type Error = D::Error;
+
+    forward_deserialize! {
+        deserialize_any();
+        deserialize_bool();
+        deserialize_i8();
+        deserialize_i16();
+        deserialize_i32();
+        deserialize_i64();
+        deserialize_i128();
+        deserialize_u8();
+        deserialize_u16();
+        deserialize_u32();
+        deserialize_u64();
+        deserialize_u128();
+        deserialize_f32();
+        deserialize_f64();
+        deserialize_char();
+        deserialize_str();
+        deserialize_string();
+        deserialize_bytes();
+        deserialize_byte_buf();
+        deserialize_option();
+        deserialize_unit();
+        deserialize_unit_struct(name: &'static str);
+        deserialize_newtype_struct(name: &'static str);
+        deserialize_seq();
+        deserialize_tuple(len: usize);
+        deserialize_tuple_struct(name: &'static str, len: usize);
+        deserialize_map();
+        deserialize_struct(name: &'static str, fields: &'static [&'static str]);
+        deserialize_enum(name: &'static str, variants: &'static [&'static str]);
+        deserialize_identifier();
+        deserialize_ignored_any();
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.de.is_human_readable()
+    }
+}
+
+struct Wrap<'b, V> {
+    visitor: V,
+    budget: &'b Budget,
+}
+
+macro_rules! forward_visit {
+    ($($method:ident ( $ty:ty );)*) => {
+        $(
+            fn $method<E: de::Error>(self, v: $ty) -> Result<Self::Value, E> {
+                self.visitor.$method(v)
+            }
+        )*
+    };
+}
+
+impl<'de, V: Visitor<'de>> Visitor<'de> for Wrap<'_, V> {
+    type Value = V::Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.visitor.expecting(f)
+    }
+
+    forward_visit! {
+        visit_bool(bool);
+        visit_i8(i8);
+        visit_i16(i16);
+        visit_i32(i32);
+        visit_i64(i64);
+        visit_i128(i128);
+        visit_u8(u8);
+        visit_u16(u16);
+        visit_u32(u32);
+        visit_u64(u64);
+        visit_u128(u128);
+        visit_f32(f32);
+        visit_f64(f64);
+        visit_char(char);
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        self.budget.charge(v.len())?;
+        self.visitor.visit_str(v)
+    }
+
+    fn visit_borrowed_str<E: de::Error>(self, v: &'de str) -> Result<Self::Value, E> {
+        self.budget.charge(v.len())?;
+        self.visitor.visit_borrowed_str(v)
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<Self::Value, E> {
+        self.budget.charge(v.len())?;
+        self.visitor.visit_string(v)
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        self.budget.charge(v.len())?;
+        self.visitor.visit_bytes(v)
+    }
+
+    fn visit_borrowed_bytes<E: de::Error>(self, v: &'de [u8]) -> Result<Self::Value, E> {
+        self.budget.charge(v.len())?;
+        self.visitor.visit_borrowed_bytes(v)
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        self.budget.charge(v.len())?;
+        self.visitor.visit_byte_buf(v)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+        self.visitor.visit_none()
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, de: D) -> Result<Self::Value, D::Error> {
+        self.visitor.visit_some(Limited { de, budget: self.budget })
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        self.visitor.visit_unit()
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(self, de: D) -> Result<Self::Value, D::Error> {
+        self.visitor.visit_newtype_struct(Limited { de, budget: self.budget })
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+        if let Some(len) = seq.size_hint() {
+            self.budget.check_len(len)?;
+        }
+        self.budget.enter()?;
+        let out = self.visitor.visit_seq(Access { inner: seq, budget: self.budget, count: 0 });
+        self.budget.leave();
+        out
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        if let Some(len) = map.size_hint() {
+            self.budget.check_len(len)?;
+        }
+        self.budget.enter()?;
+        let out = self.visitor.visit_map(Access { inner: map, budget: self.budget, count: 0 });
+        self.budget.leave();
+        out
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Self::Value, A::Error> {
+        self.budget.enter()?;
+        let out = self.visitor.visit_enum(Access { inner: data, budget: self.budget, count: 0 });
+        self.budget.leave();
+        out
+    }
+}
+
+/// Wraps the seq/map/enum accessors handed to a visitor.
+struct Access<'b, A> {
+    inner: A,
+    budget: &'b Budget,
+    count: usize,
+}
+
+impl<A> Access<'_, A> {
+    fn next<E: de::Error>(&mut self, elem_size: usize) -> Result<(), E> {
+        self.count += 1;
+        self.budget.check_len(self.count)?;
+        self.budget.charge(elem_size)
+    }
+}
+
+struct Seed<'b, S> {
+    seed: S,
+    budget: &'b Budget,
+}
+
+impl<'de, S: DeserializeSeed<'de>> DeserializeSeed<'de> for Seed<'_, S> {
+    type Value = S::Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, de: D) -> Result<Self::Value, D::Error> {
+        self.seed.deserialize(Limited { de, budget: self.budget })
+    }
+}
+
+impl<'de, A: SeqAccess<'de>> SeqAccess<'de> for Access<'_, A> {
+    type Error = A::Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error> {
+        let budget = self.budget;
+        match self.inner.next_element_seed(Seed { seed, budget })? {
+            Some(v) => {
+                self.next(size_of::<T::Value>())?;
+                Ok(Some(v))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.inner.size_hint()
+    }
+}
+
+impl<'de, A: MapAccess<'de>> MapAccess<'de> for Access<'_, A> {
+    type Error = A::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error> {
+        let budget = self.budget;
+        match self.inner.next_key_seed(Seed { seed, budget })? {
+            Some(k) => {
+                self.next(size_of::<K::Value>())?;
+                Ok(Some(k))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Self::Error> {
+        self.budget.charge(size_of::<V::Value>())?;
+        self.inner.next_value_seed(Seed { seed, budget: self.budget })
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.inner.size_hint()
+    }
+}
+
+impl<'de, 'b, A: EnumAccess<'de>> EnumAccess<'de> for Access<'b, A> {
+    type Error = A::Error;
+    type Variant = Access<'b, A::Variant>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self::Variant), Self::Error> {
+        let budget = self.budget;
+        let (value, variant) = self.inner.variant_seed(Seed { seed, budget })?;
+        Ok((value, Access { inner: variant, budget, count: 0 }))
+    }
+}
+
+impl<'de, A: VariantAccess<'de>> VariantAccess<'de> for Access<'_, A> {
+    type Error = A::Error;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        self.inner.unit_variant()
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Self::Error> {
+        self.inner.newtype_variant_seed(Seed { seed, budget: self.budget })
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, Self::Error> {
+        self.inner.tuple_variant(len, Wrap { visitor, budget: self.budget })
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.inner.struct_variant(fields, Wrap { visitor, budget: self.budget })
+    }
+}
@@ -2,23 +2,108 @@
 //!
 //! Format: `<u32_le_len><frame_bytes...>`
 
-use crate::{AbutError, FrameSink, FrameSource, ReaderConfig};
+use crate::{AbutCode, AbutError, Direction, FrameSink, FrameSource, ReaderConfig, SocketTimeout};
 
 use super::BufferTooSmall;
 
-use std::io::{Read, Write};
+pub use chunked::{ChunkedBody, DEFAULT_CHUNK_LEN};
+pub use compress::{Algorithm, Compression};
+pub use iter::{Frames, Messages};
+pub use queued::{QueueDepth, QueueSender, QueuedWriter};
+
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+/// Applies a socket-level timeout to a stream; captured by the deadline-aware constructors.
+type SetTimeout<S> = fn(&S, Option<Duration>) -> io::Result<()>;
+/// Reads back a socket-level timeout, so it can be restored afterwards.
+type GetTimeout<S> = fn(&S) -> io::Result<Option<Duration>>;
 
 /// Number of bytes used for the length prefix.
 pub const LEN_PREFIX: usize = 4;
 
+/// Watches the frames crossing a [`FramedReader`] or [`FramedWriter`].
+pub trait FrameObserver: Send {
+    /// Called with every whole frame read or written (before compression).
+    /// An error fails the read or write, although the frame has crossed.
+    fn observe(&mut self, direction: Direction, frame: &[u8]) -> Result<(), AbutError>;
+
+    /// Called with an inbound frame that was consumed from the stream but
+    /// not delivered. An error replaces the one the read fails with anyway.
+    /// Ignored by default.
+    fn dropped(&mut self, frame: Dropped<'_>) -> Result<(), AbutError> {
+        let _ = frame;
+        Ok(())
+    }
+}
+
+/// An inbound frame a [`FramedReader`] consumed without delivering it.
+#[derive(Debug, Clone, Copy)]
+pub struct Dropped<'a> {
+    /// Its length prefix.
+    pub len: usize,
+    /// Its bytes as received (still compressed, with `ReaderConfig::compressed`),
+    /// or `None` if it was drained without being kept.
+    pub bytes: Option<&'a [u8]>,
+    /// Why it was not delivered: `FrameTooLarge`, `BufferTooSmall` or a
+    /// decompression error.
+    pub code: AbutCode,
+}
+
+impl<F: FnMut(Direction, &[u8]) -> Result<(), AbutError> + Send> FrameObserver for F {
+    fn observe(&mut self, direction: Direction, frame: &[u8]) -> Result<(), AbutError> {
+        self(direction, frame)
+    }
+}
+
+struct Observer(Box<dyn FrameObserver>);
+
+impl std::fmt::Debug for Observer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("FrameObserver")
+    }
+}
+
 /// A writer that frames telemetry frames with a u32 length prefix.
 #[derive(Debug)]
 pub struct FramedWriter<W: Write> {
     inner: W,
+    send_timeout: Option<(Duration, SetTimeout<W>, GetTimeout<W>)>,
+    compression: Option<Compression>,
+    wire: Vec<u8>,
+    observer: Option<Observer>,
 }
 
 impl<W: Write> FramedWriter<W> {
-    pub fn new(inner: W) -> Self { Self { inner } }
+    pub fn new(inner: W) -> Self { Self { inner, send_timeout: None, compression: None, wire: Vec::new(), observer: None } }
+
+    /// Creates a writer that gives up on a frame if it cannot be written within `timeout`.
+    ///
+    /// A frame that times out fails with `AbutCode::Timeout`; if any of its
+    /// bytes were already written the stream is no longer aligned and the
+    /// connection should be dropped. The socket's own write timeout is
+    /// restored after every frame.
+    pub fn with_send_timeout(inner: W, timeout: Duration) -> Self
+    where
+        W: SocketTimeout,
+    {
+        Self { send_timeout: Some((timeout, W::set_write_timeout, W::write_timeout)), ..Self::new(inner) }
+    }
+
+    pub fn send_timeout(&self) -> Option<Duration> {
+        self.send_timeout.map(|(timeout, ..)| timeout)
+    }
+
+    /// Changes the per-frame timeout set by [`with_send_timeout`](Self::with_send_timeout).
+    pub fn set_send_timeout(&mut self, timeout: Option<Duration>)
+    where
+        W: SocketTimeout,
+    {
+        self.send_timeout = match timeout {
+            Some(timeout) => Some((timeout, W::set_write_timeout, W::write_timeout)),
+            None => None,
+        };
+    }
 
     /// Convenience wrapper that delegates to the `TelemetrySink` implementation.
     ///
@@ -29,19 +114,77 @@ impl<W: Write> FramedWriter<W> {
 
     pub fn into_inner(self) -> W { self.inner }
     pub fn inner_mut(&mut self) -> &mut W { &mut self.inner }
+
+    pub fn compression(&self) -> Option<Compression> { self.compression }
+
+    /// Compresses frames from now on; every frame then carries a flag byte,
+    /// so the reader needs `ReaderConfig::compressed`.
+    pub fn set_compression(&mut self, compression: Option<Compression>) { self.compression = compression; }
+
+    /// Shows every frame written from now on to `observer`.
+    pub fn set_observer(&mut self, observer: Option<Box<dyn FrameObserver>>) { self.observer = observer.map(Observer); }
     
     /// Writes one frame. Does NOT flush (caller controls flushing).
     pub fn write_frame(&mut self, bytes: &[u8]) -> Result<(), AbutError> {
+        match self.compression {
+            None => self.write_raw(bytes)?,
+            Some(compression) => {
+                let mut wire = std::mem::take(&mut self.wire);
+                let result = compression.encode(bytes, &mut wire).and_then(|()| self.write_raw(&wire));
+                self.wire = wire;
+                result?;
+            }
+        }
+        match &mut self.observer {
+            Some(Observer(observer)) => observer.observe(Direction::Outbound, bytes),
+            None => Ok(()),
+        }
+    }
+
+    fn write_raw(&mut self, bytes: &[u8]) -> Result<(), AbutError> {
         let len: u32 = bytes
             .len()
             .try_into()
             .map_err(|_| AbutError::frame_too_large(bytes.len(), u32::MAX as usize))?;
 
+        if let Some((timeout, set, get)) = self.send_timeout {
+            let deadline = Instant::now() + timeout;
+            let previous = get(&self.inner)?;
+            let result = self.write_before(&len.to_le_bytes(), bytes, deadline, set);
+            let restored = set(&self.inner, previous);
+            return result.and(restored.map_err(AbutError::from));
+        }
+
         self.inner.write_all(&len.to_le_bytes())?;
         self.inner.write_all(bytes)?;
         Ok(())
     }
 
+    /// Writes `prefix` then `body` by `deadline`, leaving the socket timeout
+    /// for the caller to restore.
+    fn write_before(&mut self, prefix: &[u8], body: &[u8], deadline: Instant, set: SetTimeout<W>) -> Result<(), AbutError> {
+        let total = prefix.len() + body.len();
+        let mut written = 0;
+        while written < total {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(AbutError::timeout(written == 0, "send"));
+            }
+            set(&self.inner, Some(remaining))?;
+            let chunk = match written.checked_sub(prefix.len()) {
+                Some(off) => &body[off..],
+                None => &prefix[written..],
+            };
+            match self.inner.write(chunk) {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero).into()),
+                Ok(n) => written += n,
+                Err(e) if is_retry(&e) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
     pub fn flush(&mut self) -> Result<(), AbutError> {
         self.inner.flush()?;
         Ok(())
@@ -67,6 +210,12 @@ impl<R: Read> FramedReader<R> {
 pub struct FramedReader<R: Read> {
     inner: R,
     cfg: ReaderConfig,
+    deadlines: Option<(SetTimeout<R>, GetTimeout<R>)>,
+    /// The socket's own read timeout, saved while a deadline is armed.
+    saved: Option<Option<Duration>>,
+    frame_deadline: Option<Instant>,
+    wire: Vec<u8>,
+    observer: Option<Observer>,
 }
 
 impl<R: Read> FramedReader<R> {
@@ -74,64 +223,265 @@ impl<R: Read> FramedReader<R> {
     pub fn with_max(inner: R, max_frame_len: usize) -> Self {
         Self::with_config(inner, ReaderConfig { max_frame_len, ..Default::default() })
     }
-    pub fn with_config(inner: R, cfg: ReaderConfig) -> Self { Self { inner, cfg } }
+    pub fn with_config(inner: R, cfg: ReaderConfig) -> Self {
+        Self { inner, cfg, deadlines: None, saved: None, frame_deadline: None, wire: Vec::new(), observer: None }
+    }
+
+    /// Like [`with_config`](Self::with_config), but enforces the config's
+    /// `idle_timeout` and `frame_timeout` using the socket's read timeout.
+    ///
+    /// Readers built any other way ignore those two fields. The socket's own
+    /// read timeout is restored after every frame.
+    pub fn with_deadlines(inner: R, cfg: ReaderConfig) -> Self
+    where
+        R: SocketTimeout,
+    {
+        Self { deadlines: Some((R::set_read_timeout, R::read_timeout)), ..Self::with_config(inner, cfg) }
+    }
 
     pub fn into_inner(self) -> R { self.inner }
     pub fn inner_mut(&mut self) -> &mut R { &mut self.inner }
     pub fn config(&self) -> ReaderConfig { self.cfg }
+    pub fn set_config(&mut self, cfg: ReaderConfig) { self.cfg = cfg; }
 
     fn drain_exact(&mut self, len: usize) -> Result<(), AbutError> {
-        let mut sink = std::io::sink();
-        std::io::copy(&mut self.inner.by_ref().take(len as u64), &mut sink)?;
+        let mut scratch = [0u8; 4096];
+        let mut drained = 0;
+        while drained < len {
+            let want = scratch.len().min(len - drained);
+            match self.read_some(&mut scratch[..want], self.frame_deadline, false)? {
+                0 => return Err(AbutError::truncated_frame("body", drained, len)),
+                n => drained += n,
+            }
+        }
         Ok(())
     }
 
+    /// Performs one successful `read`, bounded by `deadline` if there is one.
+    ///
+    /// Returns 0 only at EOF. `aligned` records whether a timeout here leaves
+    /// the stream at a frame boundary; the socket's own read timeout firing
+    /// is reported the same way.
+    fn read_some(&mut self, buf: &mut [u8], deadline: Option<Instant>, aligned: bool) -> Result<usize, AbutError> {
+        let what = match deadline {
+            None => "read",
+            Some(_) if aligned => "idle",
+            Some(_) => "frame",
+        };
+        loop {
+            if let Some((set, get)) = self.deadlines {
+                match deadline {
+                    Some(deadline) => {
+                        let remaining = deadline.saturating_duration_since(Instant::now());
+                        if remaining.is_zero() {
+                            return Err(AbutError::timeout(aligned, what));
+                        }
+                        if self.saved.is_none() {
+                            self.saved = Some(get(&self.inner)?);
+                        }
+                        set(&self.inner, Some(remaining))?;
+                    }
+                    None => self.restore_timeout()?,
+                }
+            }
+            match self.inner.read(buf) {
+                Ok(n) => return Ok(n),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                    return Err(AbutError::timeout(aligned, what));
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Puts back the socket's own read timeout if a deadline replaced it.
+    fn restore_timeout(&mut self) -> Result<(), AbutError> {
+        if let (Some(previous), Some((set, _))) = (self.saved.take(), self.deadlines) {
+            set(&self.inner, previous)?;
+        }
+        Ok(())
+    }
+
+    /// Runs one frame read, then restores the socket's own read timeout.
+    fn restoring<T>(&mut self, read: impl FnOnce(&mut Self) -> Result<T, AbutError>) -> Result<T, AbutError> {
+        let result = read(self);
+        let restored = self.restore_timeout();
+        result.and_then(|value| restored.map(|()| value))
+    }
+
+    /// Fills `buf` from the stream, returning how many bytes arrived before EOF.
+    fn read_full(&mut self, buf: &mut [u8]) -> Result<usize, AbutError> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            match self.read_some(&mut buf[filled..], self.frame_deadline, false)? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        Ok(filled)
+    }
+
+    /// Reads the length prefix.
+    ///
+    /// EOF before the first byte is a clean close (`AbutCode::Closed`);
+    /// EOF inside the prefix is `AbutCode::TruncatedFrame`. The idle deadline
+    /// covers the wait for the first byte, the frame deadline everything after.
     fn read_len(&mut self) -> Result<usize, AbutError> {
         let mut len_buf = [0u8; LEN_PREFIX];
-        self.inner.read_exact(&mut len_buf)?;
-        Ok(u32::from_le_bytes(len_buf) as usize)
+        let idle_deadline = self.cfg.idle_timeout.map(|t| Instant::now() + t);
+        self.frame_deadline = None;
+
+        let first = self.read_some(&mut len_buf, idle_deadline, true)?;
+        if first == 0 {
+            return Err(AbutError::closed());
+        }
+        self.frame_deadline = self.cfg.frame_timeout.map(|t| Instant::now() + t);
+
+        match first + self.read_full(&mut len_buf[first..])? {
+            LEN_PREFIX => Ok(u32::from_le_bytes(len_buf) as usize),
+            got => Err(AbutError::truncated_frame("length prefix", got, LEN_PREFIX)),
+        }
+    }
+
+    fn read_body(&mut self, dst: &mut [u8]) -> Result<(), AbutError> {
+        let got = self.read_full(dst)?;
+        if got < dst.len() {
+            return Err(AbutError::truncated_frame("body", got, dst.len()));
+        }
+        Ok(())
     }
 
     /// Reads the next frame into `dst`, resizing it exactly to the frame length.
+    ///
+    /// Fails with `AbutCode::Closed` if the peer closed the stream at a frame
+    /// boundary and `AbutCode::TruncatedFrame` if it closed mid-frame.
     pub fn recv_into(&mut self, dst: &mut Vec<u8>) -> Result<(), AbutError> {
-        let len = self.read_len()?;
+        self.restoring(|this| {
+            let len = this.read_len()?;
+            this.recv_body(len, dst)?;
+            this.observe(dst)
+        })
+    }
+
+    /// Shows every frame read from now on to `observer`.
+    pub fn set_observer(&mut self, observer: Option<Box<dyn FrameObserver>>) { self.observer = observer.map(Observer); }
+
+    fn observe(&mut self, frame: &[u8]) -> Result<(), AbutError> {
+        match &mut self.observer {
+            Some(Observer(observer)) => observer.observe(Direction::Inbound, frame),
+            None => Ok(()),
+        }
+    }
+
+    /// Reports a consumed frame that fails with `err` to the observer.
+    fn dropped(&mut self, len: usize, bytes: Option<&[u8]>, err: AbutError) -> AbutError {
+        match &mut self.observer {
+            Some(Observer(observer)) => observer.dropped(Dropped { len, bytes, code: err.code }).err().unwrap_or(err),
+            None => err,
+        }
+    }
+
+    /// Like [`recv_into`](Self::recv_into), but returns `Ok(false)` if the peer
+    /// closed the stream cleanly at a frame boundary.
+    ///
+    /// EOF part-way through a length prefix or body is still an error.
+    pub fn try_recv_into(&mut self, dst: &mut Vec<u8>) -> Result<bool, AbutError> {
+        match self.recv_into(dst) {
+            Ok(()) => Ok(true),
+            Err(e) if e.code == AbutCode::Closed => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
 
-        if len > self.cfg.max_frame_len {
+    /// Iterates over frames until the stream ends cleanly at a frame boundary.
+    ///
+    /// The iterator stops after yielding the first error.
+    pub fn frames(&mut self) -> Frames<'_, R> {
+        Frames::new(self)
+    }
+
+    /// Longest length prefix accepted: the flag byte of a compressed frame
+    /// does not count against `max_frame_len`.
+    fn max_wire_len(&self) -> usize {
+        self.cfg.max_frame_len.saturating_add(usize::from(self.cfg.compressed))
+    }
+
+    fn check_len(&mut self, len: usize) -> Result<(), AbutError> {
+        if len > self.max_wire_len() {
+            let err = AbutError::frame_too_large(len, self.cfg.max_frame_len);
             if self.cfg.drain_oversize_up_to != 0 && len <= self.cfg.drain_oversize_up_to {
                 self.drain_exact(len)?;
+                return Err(self.dropped(len, None, err));
             }
-            return Err(AbutError::frame_too_large(len, self.cfg.max_frame_len));
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    fn recv_body(&mut self, len: usize, dst: &mut Vec<u8>) -> Result<(), AbutError> {
+        self.check_len(len)?;
+        if self.cfg.compressed {
+            let mut wire = std::mem::take(&mut self.wire);
+            wire.resize(len, 0);
+            let result = self.read_body(&mut wire).and_then(|()| {
+                compress::decode(&wire, self.cfg.max_frame_len, dst).map_err(|e| self.dropped(len, Some(&wire), e))
+            });
+            self.wire = wire;
+            return result;
         }
 
         dst.clear();
         dst.resize(len, 0u8);
-        self.inner.read_exact(dst)?;
-        Ok(())
+        self.read_body(dst)
     }
 
     /// Reads the next frame into a caller-provided slice.
+    ///
+    /// End-of-stream is reported the same way as [`recv_into`](Self::recv_into).
+    /// With `ReaderConfig::compressed` the size is only known after
+    /// decompressing, so a frame too big for `dst` is always consumed.
     pub fn read_frame(&mut self, dst: &mut [u8]) -> Result<usize, AbutError> {
+        self.restoring(|this| this.read_frame_once(dst))
+    }
+
+    fn read_frame_once(&mut self, dst: &mut [u8]) -> Result<usize, AbutError> {
         let len = self.read_len()?;
 
-        if len > self.cfg.max_frame_len {
-            if self.cfg.drain_oversize_up_to != 0 && len <= self.cfg.drain_oversize_up_to {
-                self.drain_exact(len)?;
-            }
-            return Err(AbutError::frame_too_large(len, self.cfg.max_frame_len));
+        if self.cfg.compressed {
+            let mut frame = Vec::new();
+            self.recv_body(len, &mut frame)?;
+            let Some(dst) = dst.get_mut(..frame.len()) else {
+                let wire = std::mem::take(&mut self.wire);
+                let err = self.dropped(len, Some(&wire), AbutError::buffer_too_small(frame.len()));
+                self.wire = wire;
+                return Err(err);
+            };
+            dst.copy_from_slice(&frame);
+            self.observe(&frame)?;
+            return Ok(frame.len());
         }
+        self.check_len(len)?;
 
         if dst.len() < len {
+            let err = AbutError::buffer_too_small(len);
             if self.cfg.drain_on_small_buffer {
                 self.drain_exact(len)?;
+                return Err(self.dropped(len, None, err));
             }
-            return Err(AbutError::buffer_too_small(len));
+            return Err(err);
         }
 
-        self.inner.read_exact(&mut dst[..len])?;
+        self.read_body(&mut dst[..len])?;
+        self.observe(&dst[..len])?;
         Ok(len)
     }
 }
 
+fn is_retry(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted)
+}
+
 impl<R: Read> FrameSource for FramedReader<R> {
     type Error = AbutError;
     fn recv_frame(&mut self, dst: &mut [u8]) -> Result<usize, Self::Error> {
@@ -145,7 +495,7 @@ impl From<BufferTooSmall> for AbutError {
 }
 
 #[allow(unused)]
-fn send_structured_log<W: Write>(mut sink: impl FrameSink<Error = std::io::Error>) {
+fn send_structured_log(mut sink: impl FrameSink<Error = std::io::Error>) {
     let payload = b"hello";
     let mut framed = vec![];
     framed.extend_from_slice(payload);
@@ -155,7 +505,15 @@ fn send_structured_log<W: Write>(mut sink: impl FrameSink<Error = std::io::Error
 
 
 pub mod cbor;
+pub mod chunked;
+pub mod compress;
+mod iter;
+pub mod json;
+#[cfg(any(feature = "postcard", feature = "cbor", feature = "msgpack", feature = "json"))]
+pub(crate) mod limit;
+pub mod msgpack;
 pub mod postcard;
+pub mod queued;
 
 
 
@@ -163,6 +521,22 @@ pub mod postcard;
 mod tests {
     use super::*;
     use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+
+    /// `(len, bytes, code)` of a dropped frame.
+    pub(super) type Seen = (usize, Option<Vec<u8>>, AbutCode);
+
+    /// Keeps every dropped frame.
+    #[derive(Clone, Default)]
+    pub(super) struct Drops(pub(super) Arc<Mutex<Vec<Seen>>>);
+
+    impl FrameObserver for Drops {
+        fn observe(&mut self, _: Direction, _: &[u8]) -> Result<(), AbutError> { Ok(()) }
+        fn dropped(&mut self, frame: Dropped<'_>) -> Result<(), AbutError> {
+            self.0.lock().unwrap().push((frame.len, frame.bytes.map(<[u8]>::to_vec), frame.code));
+            Ok(())
+        }
+    }
 
     #[test]
     fn test_roundtrip_basic() {
@@ -252,4 +626,81 @@ mod tests {
         let res = reader.recv_into(&mut dst);
         assert!(res.is_err(), "Should fail due to UnexpectedEof");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_frames_iterator_stops_at_clean_eof() {
+        let mut buffer = Vec::new();
+        let mut writer = FramedWriter::new(&mut buffer);
+        writer.write_frame(b"one").unwrap();
+        writer.write_frame(b"").unwrap();
+        writer.write_frame(b"three").unwrap();
+
+        let mut reader = FramedReader::new(Cursor::new(buffer));
+        let frames: Vec<Vec<u8>> = reader.frames().collect::<Result<_, _>>().unwrap();
+        assert_eq!(frames, vec![b"one".to_vec(), vec![], b"three".to_vec()]);
+    }
+
+    #[test]
+    fn test_frames_iterator_reports_truncation() {
+        let mut buffer = Vec::new();
+        FramedWriter::new(&mut buffer).write_frame(b"whole").unwrap();
+        FramedWriter::new(&mut buffer).write_frame(b"partial").unwrap();
+        buffer.truncate(buffer.len() - 3);
+
+        let mut reader = FramedReader::new(Cursor::new(buffer));
+        let mut frames = reader.frames();
+        assert_eq!(frames.next().unwrap().unwrap(), b"whole");
+        assert!(frames.next().unwrap().is_err());
+        assert!(frames.next().is_none());
+    }
+
+    #[test]
+    fn test_clean_eof_is_closed() {
+        let mut buffer = Vec::new();
+        FramedWriter::new(&mut buffer).write_frame(b"last").unwrap();
+
+        let mut reader = FramedReader::new(Cursor::new(buffer));
+        let mut dst = Vec::new();
+        reader.recv_into(&mut dst).unwrap();
+
+        let err = reader.recv_into(&mut dst).unwrap_err();
+        assert_eq!(err.code, AbutCode::Closed);
+        assert!(!reader.try_recv_into(&mut dst).unwrap());
+    }
+
+    #[test]
+    fn test_truncated_prefix_and_body() {
+        let mut reader = FramedReader::new(Cursor::new(vec![0x05, 0x00]));
+        let err = reader.recv_into(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.code, AbutCode::TruncatedFrame);
+        assert!(err.to_string().contains("length prefix ended after 2 of 4 bytes"));
+
+        let mut reader = FramedReader::new(Cursor::new(vec![0x05, 0x00, 0x00, 0x00, b'a', b'b']));
+        let err = reader.read_frame(&mut [0u8; 8]).unwrap_err();
+        assert_eq!(err.code, AbutCode::TruncatedFrame);
+        assert!(err.to_string().contains("body ended after 2 of 5 bytes"));
+
+        // A frame that is being drained counts as truncated too.
+        let mut reader = FramedReader::new(Cursor::new(vec![0x05, 0x00, 0x00, 0x00, b'a']));
+        let err = reader.read_frame(&mut [0u8; 1]).unwrap_err();
+        assert_eq!(err.code, AbutCode::TruncatedFrame);
+    }
+
+    #[test]
+    fn test_drained_frames_reach_the_observer() {
+        let mut buffer = Vec::new();
+        let mut writer = FramedWriter::new(&mut buffer);
+        for frame in [&b"far too long"[..], b"too long", b"ok"] {
+            writer.write_frame(frame).unwrap();
+        }
+
+        let cfg = ReaderConfig { max_frame_len: 10, drain_oversize_up_to: 64, drain_on_small_buffer: true, ..Default::default() };
+        let mut reader = FramedReader::with_config(Cursor::new(buffer), cfg);
+        let drops = Drops::default();
+        reader.set_observer(Some(Box::new(drops.clone())));
+        assert_eq!(reader.read_frame(&mut [0u8; 16]).unwrap_err().code, AbutCode::FrameTooLarge);
+        assert_eq!(reader.read_frame(&mut [0u8; 4]).unwrap_err().code, AbutCode::BufferTooSmall);
+        assert_eq!(reader.read_frame(&mut [0u8; 4]).unwrap(), 2);
+        assert_eq!(*drops.0.lock().unwrap(), [(12, None, AbutCode::FrameTooLarge), (8, None, AbutCode::BufferTooSmall)]);
+    }
+}
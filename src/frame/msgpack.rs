@@ -0,0 +1,147 @@
+#![cfg(feature = "msgpack")]
+
+use std::io::{Read, Write};
+use crate::{AbutError, Codec, DecodeLimits, MessageSource, frame::{FramedReader, FramedWriter, Messages, limit}};
+
+use serde::{Serialize, de::DeserializeOwned};
+
+/// [`Codec`] marker for MessagePack, with structs encoded as named maps.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Msgpack;
+
+impl Codec for Msgpack {
+    fn encode<T: Serialize>(value: &T, buf: &mut Vec<u8>) -> Result<(), AbutError> {
+        rmp_serde::encode::write_named(buf, value).map_err(AbutError::msgpack_encode)
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8], limits: &DecodeLimits) -> Result<T, AbutError> {
+        let mut de = rmp_serde::Deserializer::from_read_ref(bytes);
+        limit::deserialize(&mut de, limits).map_err(|e| e.into_abut(AbutError::msgpack_decode))
+    }
+}
+
+/// Writes MessagePack-encoded values, one per frame.
+///
+/// Structs are encoded as maps keyed by field name so that scripting
+/// tools can decode frames without knowing the Rust field order.
+pub struct FramedMsgpackWriter<W: Write> {
+    inner: FramedWriter<W>,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> FramedMsgpackWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner: FramedWriter::new(inner), buf: Vec::new() }
+    }
+
+    pub fn send<T: Serialize>(&mut self, value: &T) -> Result<(), AbutError> {
+        self.buf.clear();
+        Msgpack::encode(value, &mut self.buf)?;
+        self.inner.write_frame(&self.buf)
+    }
+
+    pub fn flush(&mut self) -> Result<(), AbutError> {
+        self.inner.flush()
+    }
+}
+
+pub struct FramedMsgpackReader<R: Read> {
+    inner: FramedReader<R>,
+    buf: Vec<u8>,
+}
+
+impl<R: Read> FramedMsgpackReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner: FramedReader::new(inner), buf: Vec::new() }
+    }
+
+    pub fn with_inner(inner: FramedReader<R>) -> Self {
+        Self { inner, buf: Vec::new() }
+    }
+
+    pub fn recv<T: DeserializeOwned>(&mut self) -> Result<T, AbutError> {
+        self.inner.recv_into(&mut self.buf)?;
+        self.decode()
+    }
+
+    /// Like [`recv`](Self::recv), but returns `Ok(None)` on a clean EOF at a frame boundary.
+    pub fn try_recv<T: DeserializeOwned>(&mut self) -> Result<Option<T>, AbutError> {
+        if !self.inner.try_recv_into(&mut self.buf)? {
+            return Ok(None);
+        }
+        self.decode().map(Some)
+    }
+
+    /// Iterates over decoded messages until the peer closes the stream.
+    pub fn messages<T: DeserializeOwned>(&mut self) -> Messages<'_, Self, T> {
+        Messages::new(self)
+    }
+
+    pub fn inner_mut(&mut self) -> &mut FramedReader<R> { &mut self.inner }
+
+    fn decode<T: DeserializeOwned>(&self) -> Result<T, AbutError> {
+        Msgpack::decode(&self.buf, &self.inner.config().limits)
+    }
+}
+
+impl<R: Read> MessageSource for FramedMsgpackReader<R> {
+    fn try_recv<T: DeserializeOwned>(&mut self) -> Result<Option<T>, AbutError> {
+        FramedMsgpackReader::try_recv(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AbutCode;
+    use serde::{Deserialize, Serialize};
+    use std::io::Cursor;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Sample {
+        id: u32,
+        name: String,
+        tags: Vec<String>,
+    }
+
+    #[test]
+    fn test_msgpack_roundtrip() {
+        let mut buffer = Vec::new();
+        let mut writer = FramedMsgpackWriter::new(&mut buffer);
+
+        let original = Sample { id: 7, name: "probe".into(), tags: vec!["a".into(), "b".into()] };
+        writer.send(&original).unwrap();
+        writer.send(&99u8).unwrap();
+
+        let mut reader = FramedMsgpackReader::new(Cursor::new(buffer));
+        let decoded: Sample = reader.recv().unwrap();
+        let second: u8 = reader.recv().unwrap();
+
+        assert_eq!(original, decoded);
+        assert_eq!(second, 99);
+    }
+
+    #[test]
+    fn test_msgpack_structs_are_named_maps() {
+        let mut buffer = Vec::new();
+        let mut writer = FramedMsgpackWriter::new(&mut buffer);
+        writer.send(&Sample { id: 1, name: String::new(), tags: vec![] }).unwrap();
+
+        // 0x83 = fixmap with three entries, then the "id" key as a fixstr.
+        assert_eq!(&buffer[4..8], &[0x83, 0xa2, b'i', b'd']);
+    }
+
+    #[test]
+    fn test_msgpack_decode_error_keeps_alignment() {
+        let mut buffer = Vec::new();
+        FramedWriter::new(&mut buffer).write_frame(&[0xc1]).unwrap(); // 0xc1 is never used
+        FramedMsgpackWriter::new(&mut buffer).send(&"after").unwrap();
+
+        let mut reader = FramedMsgpackReader::new(Cursor::new(buffer));
+        let err = reader.recv::<String>().unwrap_err();
+        assert!(matches!(err.code, AbutCode::MsgpackDecode));
+
+        let next: String = reader.recv().unwrap();
+        assert_eq!(next, "after");
+    }
+}
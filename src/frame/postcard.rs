@@ -1,11 +1,29 @@
 #![cfg(feature = "postcard")]
 
 use std::io::{Read, Write};
-use crate::{AbutError, frame::{FramedReader, FramedWriter}};
+use crate::{AbutError, Codec, DecodeLimits, MessageSource, frame::{FramedReader, FramedWriter, Messages, limit}};
 
 #[cfg(feature = "postcard")]
 use serde::{Serialize, de::DeserializeOwned};
 
+/// [`Codec`] marker for postcard.
+#[cfg(feature = "postcard")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Postcard;
+
+#[cfg(feature = "postcard")]
+impl Codec for Postcard {
+    fn encode<T: Serialize>(value: &T, buf: &mut Vec<u8>) -> Result<(), AbutError> {
+        *buf = postcard::to_extend(value, std::mem::take(buf)).map_err(AbutError::postcard_encode)?;
+        Ok(())
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8], limits: &DecodeLimits) -> Result<T, AbutError> {
+        let mut de = postcard::Deserializer::from_bytes(bytes);
+        limit::deserialize(&mut de, limits).map_err(|e| e.into_abut(AbutError::postcard_decode))
+    }
+}
+
 #[cfg(feature = "postcard")]
 pub struct FramedPostcardWriter<W: Write> {
     inner: FramedWriter<W>,
@@ -57,10 +75,34 @@ impl<R: Read> FramedPostcardReader<R> {
 
     pub fn recv<T: DeserializeOwned>(&mut self) -> Result<T, AbutError> {
         self.inner.recv_into(&mut self.buf)?;
-        postcard::from_bytes(&self.buf).map_err(AbutError::postcard_decode)
+        self.decode()
+    }
+
+    /// Like [`recv`](Self::recv), but returns `Ok(None)` on a clean EOF at a frame boundary.
+    pub fn try_recv<T: DeserializeOwned>(&mut self) -> Result<Option<T>, AbutError> {
+        if !self.inner.try_recv_into(&mut self.buf)? {
+            return Ok(None);
+        }
+        self.decode().map(Some)
+    }
+
+    /// Iterates over decoded messages until the peer closes the stream.
+    pub fn messages<T: DeserializeOwned>(&mut self) -> Messages<'_, Self, T> {
+        Messages::new(self)
     }
 
     pub fn inner_mut(&mut self) -> &mut FramedReader<R> { &mut self.inner }
+
+    fn decode<T: DeserializeOwned>(&self) -> Result<T, AbutError> {
+        Postcard::decode(&self.buf, &self.inner.config().limits)
+    }
+}
+
+#[cfg(feature = "postcard")]
+impl<R: Read> MessageSource for FramedPostcardReader<R> {
+    fn try_recv<T: DeserializeOwned>(&mut self) -> Result<Option<T>, AbutError> {
+        FramedPostcardReader::try_recv(self)
+    }
 }
 
 
@@ -146,4 +188,50 @@ mod tests {
 
         assert!(res.is_err(), "Postcard should fail to decode invalid bytes");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_postcard_declared_length_limit() {
+        let mut buffer = Vec::new();
+        // A Vec<()> claiming u32::MAX elements fits in a five byte payload.
+        FramedWriter::new(&mut buffer).write_frame(&[0xff, 0xff, 0xff, 0xff, 0x0f]).unwrap();
+
+        let mut reader = FramedPostcardReader::new(Cursor::new(buffer));
+        let err = reader.recv::<Vec<()>>().unwrap_err();
+        assert!(matches!(err.code, crate::AbutCode::DecodeLimit));
+    }
+
+    #[test]
+    fn test_postcard_alloc_limit() {
+        let mut buffer = Vec::new();
+        FramedPostcardWriter::new(&mut buffer).send(&vec!["x".repeat(300); 4]).unwrap();
+
+        let cfg = crate::ReaderConfig {
+            limits: crate::DecodeLimits { max_alloc: 1024, ..Default::default() },
+            ..Default::default()
+        };
+        let mut reader = FramedPostcardReader::with_inner(FramedReader::with_config(Cursor::new(buffer), cfg));
+        let err = reader.recv::<Vec<String>>().unwrap_err();
+        assert!(matches!(err.code, crate::AbutCode::DecodeLimit));
+        assert!(err.to_string().contains("decoded size"));
+    }
+
+    #[test]
+    fn test_postcard_messages_iterator() {
+        let mut buffer = Vec::new();
+        let mut writer = FramedPostcardWriter::new(&mut buffer);
+        writer.send(&DeviceCommand::Reboot).unwrap();
+        writer.inner.write_frame(&[0x09]).unwrap(); // unknown variant index
+        writer.send(&DeviceCommand::SetGain(3)).unwrap();
+
+        let mut reader = FramedPostcardReader::new(Cursor::new(buffer.clone()));
+        let all: Vec<_> = reader.messages::<DeviceCommand>().collect();
+        assert_eq!(all.len(), 3);
+        assert!(all[1].as_ref().is_err_and(|e| e.code.is_decode()));
+
+        let mut reader = FramedPostcardReader::new(Cursor::new(buffer));
+        let mut messages = reader.messages::<DeviceCommand>().skip_decode_errors();
+        let ok: Vec<_> = messages.by_ref().map(Result::unwrap).collect();
+        assert_eq!(ok, vec![DeviceCommand::Reboot, DeviceCommand::SetGain(3)]);
+        assert_eq!(messages.skipped(), 1);
+    }
+}
@@ -0,0 +1,199 @@
+//! A [`FramedWriter`] drained by a background thread behind a bounded queue.
+//!
+//! Producers enqueue frames and return immediately while the queue has room;
+//! a slow consumer only fills the queue, and what happens then is the
+//! caller's choice of [`OverflowPolicy`].
+
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::Arc;
+
+use super::FramedWriter;
+use crate::worker::{Backlog, Outlet, Shared, Worker};
+use crate::{AbutError, FrameSink, OverflowPolicy, QueueLimits};
+
+/// Snapshot of a queue's occupancy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct QueueDepth {
+    pub frames: usize,
+    pub bytes: usize,
+    /// Frames discarded by the overflow policy since the writer was created.
+    pub dropped: u64,
+}
+
+/// Frames in the order they were queued.
+#[derive(Default)]
+struct Fifo {
+    queue: VecDeque<Vec<u8>>,
+    bytes: usize,
+}
+
+impl Backlog for Fifo {
+    type Tag = ();
+
+    fn frames(&self) -> usize { self.queue.len() }
+    fn bytes(&self) -> usize { self.bytes }
+
+    fn push(&mut self, (): (), data: Vec<u8>) {
+        self.bytes += data.len();
+        self.queue.push_back(data);
+    }
+
+    fn evict(&mut self) -> bool {
+        let Some(old) = self.queue.pop_front() else { return false };
+        self.bytes -= old.len();
+        true
+    }
+
+    fn next(&mut self, wire: &mut Vec<u8>) -> bool {
+        let Some(frame) = self.queue.pop_front() else { return false };
+        self.bytes -= frame.len();
+        *wire = frame;
+        true
+    }
+
+    fn clear(&mut self) {
+        self.queue.clear();
+        self.bytes = 0;
+    }
+}
+
+impl<W: Write + Send + 'static> Outlet for FramedWriter<W> {
+    fn send(&mut self, wire: &[u8]) -> Result<(), AbutError> { self.write_frame(wire) }
+    fn idle(&mut self) -> Result<(), AbutError> { self.flush() }
+}
+
+/// Owns a writer thread and the bounded queue feeding it.
+///
+/// Dropping it sends whatever is still queued, then joins the thread.
+pub struct QueuedWriter<W: Write + Send + 'static> {
+    worker: Worker<Fifo, FramedWriter<W>>,
+}
+
+/// A cloneable producer handle onto a [`QueuedWriter`]'s queue.
+#[derive(Clone)]
+pub struct QueueSender {
+    shared: Arc<Shared<Fifo>>,
+}
+
+impl<W: Write + Send + 'static> QueuedWriter<W> {
+    pub fn new(writer: FramedWriter<W>, limits: QueueLimits, policy: OverflowPolicy) -> Self {
+        Self { worker: Worker::spawn("queued writer", Fifo::default(), writer, limits, policy) }
+    }
+
+    pub fn sender(&self) -> QueueSender {
+        QueueSender { shared: self.worker.shared().clone() }
+    }
+
+    pub fn depth(&self) -> QueueDepth {
+        self.worker.shared().depth()
+    }
+
+    /// Queues a frame, applying the overflow policy if the queue is full.
+    pub fn write_frame(&mut self, bytes: &[u8]) -> Result<(), AbutError> {
+        self.worker.shared().enqueue((), bytes)
+    }
+
+    /// Blocks until every queued frame has been written and flushed.
+    pub fn flush(&mut self) -> Result<(), AbutError> {
+        self.worker.flush()
+    }
+
+    /// Stops accepting frames, sends the rest and returns the underlying writer.
+    pub fn close(self) -> Result<FramedWriter<W>, AbutError> {
+        self.worker.close()
+    }
+}
+
+impl<W: Write + Send + 'static> FrameSink for QueuedWriter<W> {
+    type Error = AbutError;
+    fn send_frame(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.write_frame(bytes)
+    }
+}
+
+impl QueueSender {
+    pub fn depth(&self) -> QueueDepth {
+        self.shared.depth()
+    }
+}
+
+impl FrameSink for QueueSender {
+    type Error = AbutError;
+    fn send_frame(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.shared.enqueue((), bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AbutCode;
+    use crate::frame::FramedReader;
+    use crate::worker::testing::{Gate, parked};
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    fn stalled(limits: QueueLimits, policy: OverflowPolicy) -> (QueuedWriter<Gate>, std::sync::mpsc::Sender<()>) {
+        let (gate, release) = Gate::new();
+        let mut q = QueuedWriter::new(FramedWriter::new(gate), limits, policy);
+        q.write_frame(b"head").unwrap();
+        parked(|| q.depth().frames);
+        (q, release)
+    }
+
+    #[test]
+    fn test_overflow_policies() {
+        let limits = QueueLimits { max_frames: 2, max_bytes: 1024 };
+
+        let (mut q, gate) = stalled(limits, OverflowPolicy::Error);
+        q.write_frame(b"a").unwrap();
+        q.write_frame(b"b").unwrap();
+        assert_eq!(q.write_frame(b"c").unwrap_err().code, AbutCode::QueueFull);
+        drop(gate);
+
+        let (mut q, gate) = stalled(limits, OverflowPolicy::DropNewest);
+        for f in [b"a", b"b", b"c"] {
+            q.write_frame(f).unwrap();
+        }
+        assert_eq!(q.depth(), QueueDepth { frames: 2, bytes: 2, dropped: 1 });
+        drop(gate);
+
+        let (mut q, gate) = stalled(limits, OverflowPolicy::DropOldest);
+        for f in [b"a", b"b", b"c"] {
+            q.write_frame(f).unwrap();
+        }
+        assert_eq!(q.depth().dropped, 1);
+        drop(gate);
+        let written = q.close().unwrap().into_inner().written.concat();
+        let mut r = FramedReader::new(&written[..]);
+        let frames: Vec<_> = r.frames().map(Result::unwrap).collect();
+        assert_eq!(frames, vec![b"head".to_vec(), b"b".to_vec(), b"c".to_vec()]);
+    }
+
+    #[test]
+    fn test_block_policy_waits_for_room() {
+        let (mut q, gate) = stalled(QueueLimits { max_frames: 1, max_bytes: 1024 }, OverflowPolicy::Block);
+        q.write_frame(b"a").unwrap();
+        let release = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            drop(gate);
+        });
+        q.write_frame(b"b").unwrap();
+        q.flush().unwrap();
+        assert_eq!(q.depth().frames, 0);
+        drop(q);
+        release.join().unwrap();
+    }
+
+    #[test]
+    fn test_write_error_surfaces_to_producers() {
+        let (a, b) = UnixStream::pair().unwrap();
+        drop(b);
+        let mut q = QueuedWriter::new(FramedWriter::new(a), QueueLimits::default(), OverflowPolicy::Block);
+        let mut sender = q.sender();
+        sender.send_frame(b"lost").unwrap();
+        assert_eq!(q.flush().unwrap_err().code, AbutCode::Io);
+        assert_eq!(sender.send_frame(b"x").unwrap_err().code, AbutCode::Io);
+    }
+}
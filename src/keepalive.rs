@@ -0,0 +1,285 @@
+//! Heartbeats and dead-peer detection over any frame transport.
+//!
+//! [`Keepalive`] prefixes every frame with a one-byte tag so that ping/pong
+//! control frames can share the connection with application data without
+//! the application ever seeing them. Both ends must use it.
+
+use std::time::{Duration, Instant};
+
+use crate::{AbutCode, AbutError, FrameSink, FrameSource};
+
+/// Tag of a frame carrying application data.
+pub const TAG_DATA: u8 = 0x00;
+/// Tag of a ping; the payload is an 8-byte little-endian nonce.
+pub const TAG_PING: u8 = 0x01;
+/// Tag of a pong echoing a ping's nonce.
+pub const TAG_PONG: u8 = 0x02;
+
+const CONTROL_LEN: usize = 1 + 8;
+
+#[derive(Debug, Clone, Copy)]
+pub struct KeepaliveConfig {
+    /// Time between pings.
+    pub interval: Duration,
+
+    /// Number of consecutive intervals without hearing from the peer before
+    /// it is declared dead.
+    pub max_missed: u32,
+}
+
+impl Default for KeepaliveConfig {
+    fn default() -> Self {
+        Self { interval: Duration::from_secs(1), max_missed: 3 }
+    }
+}
+
+/// Reported through [`Keepalive::on_event`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeepaliveEvent {
+    /// A pong arrived; carries the measured round-trip time.
+    Rtt(Duration),
+    /// The peer missed `missed` heartbeats in a row.
+    PeerDead { missed: u32 },
+}
+
+/// A sink/source pair with heartbeats injected underneath the application's frames.
+///
+/// Pings are sent from [`tick`](Self::tick), which `send_frame` and
+/// `recv_frame` call on every use. Pongs are only processed while receiving,
+/// so the source should be a reader built with `FramedReader::with_deadlines`
+/// and an `idle_timeout` shorter than `interval`: its aligned timeouts are
+/// absorbed here and give the heartbeat a chance to run.
+pub struct Keepalive<S, R> {
+    sink: S,
+    source: R,
+    cfg: KeepaliveConfig,
+    scratch: Vec<u8>,
+    nonce: u64,
+    last_ping: Option<(u64, Instant)>,
+    last_heard: Instant,
+    missed: u32,
+    rtt: Option<Duration>,
+    dead: bool,
+    on_event: Option<Box<dyn FnMut(KeepaliveEvent) + Send>>,
+}
+
+impl<S, R> Keepalive<S, R>
+where
+    S: FrameSink<Error = AbutError>,
+    R: FrameSource<Error = AbutError>,
+{
+    pub fn new(sink: S, source: R) -> Self {
+        Self::with_config(sink, source, KeepaliveConfig::default())
+    }
+
+    pub fn with_config(sink: S, source: R, cfg: KeepaliveConfig) -> Self {
+        Self {
+            sink,
+            source,
+            cfg,
+            scratch: Vec::new(),
+            nonce: 0,
+            last_ping: None,
+            last_heard: Instant::now(),
+            missed: 0,
+            rtt: None,
+            dead: false,
+            on_event: None,
+        }
+    }
+
+    /// Registers a callback for round-trip measurements and peer death.
+    pub fn on_event(&mut self, f: impl FnMut(KeepaliveEvent) + Send + 'static) {
+        self.on_event = Some(Box::new(f));
+    }
+
+    /// Most recent round-trip time, once a pong has been received.
+    pub fn rtt(&self) -> Option<Duration> { self.rtt }
+
+    /// Heartbeat intervals elapsed since the peer was last heard from.
+    pub fn missed(&self) -> u32 { self.missed }
+
+    pub fn is_dead(&self) -> bool { self.dead }
+
+    pub fn into_parts(self) -> (S, R) { (self.sink, self.source) }
+
+    /// Sends a ping if one is due and checks whether the peer has gone quiet.
+    ///
+    /// Fails with `AbutCode::PeerDead` once `max_missed` intervals pass
+    /// without any frame from the peer, and on every call after that.
+    pub fn tick(&mut self) -> Result<(), AbutError> {
+        if self.dead {
+            return Err(self.dead_error());
+        }
+        let now = Instant::now();
+        let due = match self.last_ping {
+            Some((_, sent)) => now.duration_since(sent) >= self.cfg.interval,
+            None => true,
+        };
+        if !due {
+            return Ok(());
+        }
+
+        self.missed = (now.duration_since(self.last_heard).as_nanos() / self.cfg.interval.as_nanos().max(1)) as u32;
+        if self.missed >= self.cfg.max_missed {
+            self.dead = true;
+            self.emit(KeepaliveEvent::PeerDead { missed: self.missed });
+            return Err(self.dead_error());
+        }
+
+        self.nonce += 1;
+        self.send_control(TAG_PING, self.nonce)?;
+        self.last_ping = Some((self.nonce, now));
+        Ok(())
+    }
+
+    fn dead_error(&self) -> AbutError {
+        AbutError::new(AbutCode::PeerDead).ctx(format_args!(
+            "no frame for {} heartbeat intervals of {:?}",
+            self.missed, self.cfg.interval
+        ))
+    }
+
+    fn emit(&mut self, event: KeepaliveEvent) {
+        if let Some(f) = self.on_event.as_mut() {
+            f(event);
+        }
+    }
+
+    fn send_control(&mut self, tag: u8, nonce: u64) -> Result<(), AbutError> {
+        let mut frame = [0u8; CONTROL_LEN];
+        frame[0] = tag;
+        frame[1..].copy_from_slice(&nonce.to_le_bytes());
+        self.sink.send_frame(&frame)
+    }
+
+    /// Handles a control frame; returns `Ok(true)` for data the caller should deliver.
+    fn on_frame(&mut self, frame: &[u8]) -> Result<bool, AbutError> {
+        self.last_heard = Instant::now();
+        self.missed = 0;
+
+        let (&tag, body) = frame.split_first().ok_or_else(|| AbutError::protocol("empty keepalive frame"))?;
+        if tag == TAG_DATA {
+            return Ok(true);
+        }
+        let nonce = body
+            .try_into()
+            .map(u64::from_le_bytes)
+            .map_err(|_| AbutError::protocol(format_args!("keepalive control frame of {} bytes", frame.len())))?;
+        match tag {
+            TAG_PING => self.send_control(TAG_PONG, nonce)?,
+            TAG_PONG => {
+                if let Some((sent_nonce, sent_at)) = self.last_ping
+                    && sent_nonce == nonce
+                {
+                    let rtt = sent_at.elapsed();
+                    self.rtt = Some(rtt);
+                    self.emit(KeepaliveEvent::Rtt(rtt));
+                }
+            }
+            other => return Err(AbutError::protocol(format_args!("unknown keepalive tag {other:#04x}"))),
+        }
+        Ok(false)
+    }
+}
+
+impl<S, R> FrameSink for Keepalive<S, R>
+where
+    S: FrameSink<Error = AbutError>,
+    R: FrameSource<Error = AbutError>,
+{
+    type Error = AbutError;
+
+    fn send_frame(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.tick()?;
+        self.scratch.clear();
+        self.scratch.push(TAG_DATA);
+        self.scratch.extend_from_slice(bytes);
+        self.sink.send_frame(&self.scratch)
+    }
+}
+
+impl<S, R> FrameSource for Keepalive<S, R>
+where
+    S: FrameSink<Error = AbutError>,
+    R: FrameSource<Error = AbutError>,
+{
+    type Error = AbutError;
+
+    /// Receives the next data frame; `dst` needs one spare byte for the tag.
+    fn recv_frame(&mut self, dst: &mut [u8]) -> Result<usize, Self::Error> {
+        loop {
+            self.tick()?;
+            let n = match self.source.recv_frame(dst) {
+                Ok(n) => n,
+                Err(e) if e.code == AbutCode::Timeout && e.stream_aligned() == Some(true) => continue,
+                Err(e) => return Err(e),
+            };
+            if self.on_frame(&dst[..n])? {
+                dst.copy_within(1..n, 0);
+                return Ok(n - 1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ReaderConfig;
+    use crate::frame::{FramedReader, FramedWriter};
+    use std::os::unix::net::UnixStream;
+    use std::sync::{Arc, Mutex};
+
+    type Link = Keepalive<FramedWriter<UnixStream>, FramedReader<UnixStream>>;
+
+    fn link(stream: UnixStream, interval_ms: u64) -> Link {
+        let cfg = ReaderConfig { idle_timeout: Some(Duration::from_millis(5)), ..Default::default() };
+        let reader = FramedReader::with_deadlines(stream.try_clone().unwrap(), cfg);
+        let kcfg = KeepaliveConfig { interval: Duration::from_millis(interval_ms), max_missed: 3 };
+        Keepalive::with_config(FramedWriter::new(stream), reader, kcfg)
+    }
+
+    #[test]
+    fn test_pings_are_invisible_and_measure_rtt() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let echo = std::thread::spawn(move || {
+            let mut b = link(b, 10);
+            let mut buf = [0u8; 64];
+            let n = b.recv_frame(&mut buf).unwrap();
+            b.send_frame(&buf[..n]).unwrap();
+            // Keep answering pings until the peer hangs up.
+            while b.recv_frame(&mut buf).is_ok() {}
+        });
+
+        let mut a = link(a, 10);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let seen = events.clone();
+        a.on_event(move |e| seen.lock().unwrap().push(e));
+
+        a.send_frame(b"hello").unwrap();
+        let mut buf = [0u8; 64];
+        let n = a.recv_frame(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"hello");
+        assert!(a.rtt().is_some());
+        assert!(matches!(events.lock().unwrap()[0], KeepaliveEvent::Rtt(_)));
+
+        drop(a);
+        echo.join().unwrap();
+    }
+
+    #[test]
+    fn test_silent_peer_is_declared_dead() {
+        let (a, _silent) = UnixStream::pair().unwrap();
+        let mut a = link(a, 10);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let seen = events.clone();
+        a.on_event(move |e| seen.lock().unwrap().push(e));
+
+        let err = a.recv_frame(&mut [0u8; 16]).unwrap_err();
+        assert_eq!(err.code, AbutCode::PeerDead);
+        assert!(a.is_dead());
+        assert!(matches!(events.lock().unwrap()[..], [KeepaliveEvent::PeerDead { missed }] if missed >= 3));
+        assert_eq!(a.send_frame(b"late").unwrap_err().code, AbutCode::PeerDead);
+    }
+}
@@ -15,11 +15,29 @@
 //! 
 
 
+pub mod activation;
+pub mod audit;
+pub mod capture;
+pub mod client;
 pub mod error;
 pub mod frame;
+pub mod keepalive;
+pub mod peer;
+pub mod policy;
+pub mod priority;
+pub mod ratelimit;
+pub mod reliable;
+pub mod router;
+pub mod schema;
+pub mod server;
+pub mod shutdown;
+pub mod shm;
+pub mod sidecar;
 pub mod traits;
 pub mod types;
+mod worker;
 
 pub use error::*;
+pub use peer::{Admission, PeerCred};
 pub use traits::*;
 pub use types::*;
\ No newline at end of file
@@ -0,0 +1,98 @@
+//! Identity of the process on the other side of a Unix socket.
+
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+
+/// Credentials of a connected peer, as reported by the kernel (`SO_PEERCRED`).
+///
+/// These are captured when the peer called `connect`/`socketpair` and cannot
+/// be forged by the peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PeerCred {
+    pub pid: i32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl PeerCred {
+    pub fn of(stream: &UnixStream) -> io::Result<Self> {
+        let mut cred = libc::ucred { pid: 0, uid: 0, gid: 0 };
+        let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+        // SAFETY: `cred` and `len` are valid for writes and sized for SO_PEERCRED.
+        let rc = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                (&mut cred as *mut libc::ucred).cast(),
+                &mut len,
+            )
+        };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { pid: cred.pid, uid: cred.uid, gid: cred.gid })
+    }
+
+    /// Executable of the peer process, from `/proc/<pid>/exe`.
+    ///
+    /// Unlike the credentials this is read now, not at connect time: if the
+    /// peer has exited, its pid may belong to another process.
+    pub fn exe(&self) -> io::Result<PathBuf> {
+        std::fs::read_link(format!("/proc/{}/exe", self.pid))
+    }
+
+    /// Credentials of the current process.
+    pub fn current() -> Self {
+        // SAFETY: these calls cannot fail.
+        unsafe { Self { pid: libc::getpid(), uid: libc::getuid(), gid: libc::getgid() } }
+    }
+}
+
+/// Decides whether a connecting peer may talk to us at all.
+pub trait Admission: Send + Sync {
+    fn admit(&self, peer: &PeerCred) -> bool;
+}
+
+impl<F: Fn(&PeerCred) -> bool + Send + Sync> Admission for F {
+    fn admit(&self, peer: &PeerCred) -> bool {
+        self(peer)
+    }
+}
+
+/// Admits every peer.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AllowAll;
+
+impl Admission for AllowAll {
+    fn admit(&self, _peer: &PeerCred) -> bool {
+        true
+    }
+}
+
+/// Admits only peers running as the same user as this process.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SameUid;
+
+impl Admission for SameUid {
+    fn admit(&self, peer: &PeerCred) -> bool {
+        peer.uid == PeerCred::current().uid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_socketpair_peer_is_self() {
+        let (a, _b) = UnixStream::pair().unwrap();
+        let peer = PeerCred::of(&a).unwrap();
+        assert_eq!(peer, PeerCred::current());
+        assert_eq!(peer.exe().unwrap(), std::env::current_exe().unwrap());
+        assert!(SameUid.admit(&peer));
+        assert!(!(|p: &PeerCred| p.uid != peer.uid).admit(&peer));
+    }
+}
@@ -0,0 +1,357 @@
+//! Per-message-type access control.
+//!
+//! A [`Policy`] decides, from the peer's identity (uid, gid, executable),
+//! which routed message IDs may cross the connection in each direction.
+//! [`Enforced`] applies it to a [`FrameSink`] or [`FrameSource`]; every
+//! decision can be reported to an audit callback.
+//!
+//! Policies are loaded from a line-based file. Each non-empty line that is not
+//! a `#` comment reads `<allow|deny> <selector>... <recv|send|both> <ids>`:
+//!
+//! ```text
+//! # The telemetry sidecar may report metrics (10, 11) but nothing else.
+//! allow uid=1000 exe=/usr/libexec/telemetry recv 10,11
+//! allow any  send *
+//! deny  any  both 99
+//! ```
+//!
+//! Selectors are `any`, `uid=N`, `gid=N` and `exe=PATH`; a line applies when
+//! all of them match. `recv` covers what the peer sends to us, `send` what we
+//! send to it. IDs are a comma-separated list without spaces, or `*`. A
+//! message is allowed if some `allow` line covers it and no `deny` line does;
+//! anything else is denied.
+//!
+//! If the peer's executable cannot be read, `exe=` selectors fail closed: they
+//! match on `deny` lines and never on `allow` lines.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::router::message_id;
+use crate::{AbutCode, AbutError, Admission, Direction, FrameSink, FrameSource, PeerCred};
+
+/// Who the peer is, for the purpose of policy decisions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub cred: PeerCred,
+    /// `None` if the executable could not be read (e.g. a peer in another
+    /// pid namespace); `exe=` selectors then match only on `deny` lines.
+    pub exe: Option<PathBuf>,
+}
+
+impl Identity {
+    /// Identity of the process on the other side of `stream`.
+    pub fn of(stream: &std::os::unix::net::UnixStream) -> Result<Self, AbutError> {
+        Ok(Self::resolve(PeerCred::of(stream)?))
+    }
+
+    pub fn resolve(cred: PeerCred) -> Self {
+        Self { cred, exe: cred.exe().ok() }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Effect {
+    Allow,
+    Deny,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Selector {
+    uid: Option<u32>,
+    gid: Option<u32>,
+    exe: Option<PathBuf>,
+}
+
+impl Selector {
+    /// An unknown executable matches an `exe=` selector only when `effect`
+    /// is `Deny`, so that it can never slip past a deny line.
+    fn matches(&self, peer: &Identity, effect: Effect) -> bool {
+        self.uid.is_none_or(|uid| uid == peer.cred.uid)
+            && self.gid.is_none_or(|gid| gid == peer.cred.gid)
+            && self.exe.as_ref().is_none_or(|exe| match &peer.exe {
+                Some(peer_exe) => peer_exe == exe,
+                None => effect == Effect::Deny,
+            })
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    line: usize,
+    effect: Effect,
+    selector: Selector,
+    /// `None` for both directions.
+    direction: Option<Direction>,
+    /// `None` for every ID.
+    ids: Option<BTreeSet<u16>>,
+}
+
+impl Rule {
+    fn covers(&self, peer: &Identity, direction: Direction, id: Option<u16>) -> bool {
+        self.selector.matches(peer, self.effect)
+            && self.direction.is_none_or(|d| d == direction)
+            && match (&self.ids, id) {
+                (None, _) => true,
+                (Some(ids), Some(id)) => ids.contains(&id),
+                (Some(_), None) => false,
+            }
+    }
+}
+
+/// The outcome of checking one frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decision {
+    pub allowed: bool,
+    /// Line of the rule that decided, or `None` when no rule applied.
+    pub line: Option<usize>,
+}
+
+/// A parsed policy file.
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    rules: Vec<Rule>,
+}
+
+fn invalid(line: usize, msg: impl std::fmt::Display) -> AbutError {
+    AbutError::new(AbutCode::PolicyInvalid).ctx(format_args!("line {line}: {msg}"))
+}
+
+impl Policy {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, AbutError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, AbutError> {
+        let mut rules = Vec::new();
+        for (i, raw) in text.lines().enumerate() {
+            let line = i + 1;
+            let content = raw.split_once('#').map_or(raw, |(before, _)| before);
+            let mut words = content.split_whitespace();
+            let effect = match words.next() {
+                None => continue,
+                Some("allow") => Effect::Allow,
+                Some("deny") => Effect::Deny,
+                Some(other) => return Err(invalid(line, format_args!("expected allow or deny, got {other:?}"))),
+            };
+
+            let mut selector = Selector::default();
+            let mut any = false;
+            let direction = loop {
+                match words.next() {
+                    Some("recv") => break Some(Direction::Inbound),
+                    Some("send") => break Some(Direction::Outbound),
+                    Some("both") => break None,
+                    Some("any") => any = true,
+                    Some(word) => {
+                        let (key, value) =
+                            word.split_once('=').ok_or_else(|| invalid(line, format_args!("bad selector {word:?}")))?;
+                        let num = || value.parse().map_err(|_| invalid(line, format_args!("bad {key} {value:?}")));
+                        match key {
+                            "uid" => selector.uid = Some(num()?),
+                            "gid" => selector.gid = Some(num()?),
+                            "exe" => selector.exe = Some(PathBuf::from(value)),
+                            _ => return Err(invalid(line, format_args!("unknown selector {key:?}"))),
+                        }
+                    }
+                    None => return Err(invalid(line, "missing recv, send or both")),
+                }
+            };
+            if !any && selector.uid.is_none() && selector.gid.is_none() && selector.exe.is_none() {
+                return Err(invalid(line, "no selector; use `any` to match every peer"));
+            }
+
+            let ids = match words.collect::<Vec<_>>()[..] {
+                [] => return Err(invalid(line, "missing message IDs")),
+                ["*"] => None,
+                [list] => Some(
+                    list.split(',')
+                        .filter(|id| !id.is_empty())
+                        .map(|id| id.parse().map_err(|_| invalid(line, format_args!("bad message ID {id:?}"))))
+                        .collect::<Result<_, _>>()?,
+                ),
+                [_, extra, ..] => {
+                    return Err(invalid(line, format_args!("unexpected {extra:?}; separate IDs with commas")));
+                }
+            };
+            rules.push(Rule { line, effect, selector, direction, ids });
+        }
+        Ok(Self { rules })
+    }
+
+    /// Decides whether message `id` may cross in `direction` for `peer`.
+    /// Frames too short to carry an ID (`None`) are only allowed by `*` rules.
+    pub fn decide(&self, peer: &Identity, direction: Direction, id: Option<u16>) -> Decision {
+        let mut allowed_by = None;
+        for rule in self.rules.iter().filter(|r| r.covers(peer, direction, id)) {
+            match rule.effect {
+                Effect::Deny => return Decision { allowed: false, line: Some(rule.line) },
+                Effect::Allow => allowed_by = allowed_by.or(Some(rule.line)),
+            }
+        }
+        Decision { allowed: allowed_by.is_some(), line: allowed_by }
+    }
+
+    /// Whether some `allow` line applies to `peer` at all.
+    pub fn admits(&self, peer: &Identity) -> bool {
+        self.rules.iter().any(|r| r.effect == Effect::Allow && r.selector.matches(peer, r.effect))
+    }
+}
+
+/// Turns away peers the policy has nothing to allow for.
+impl Admission for Policy {
+    fn admit(&self, peer: &PeerCred) -> bool {
+        self.admits(&Identity::resolve(*peer))
+    }
+}
+
+/// One checked frame, as reported to the audit callback.
+#[derive(Debug, Clone, Copy)]
+pub struct AuditRecord<'a> {
+    pub peer: &'a Identity,
+    pub direction: Direction,
+    pub id: Option<u16>,
+    pub len: usize,
+    pub decision: Decision,
+}
+
+type AuditFn = Arc<dyn Fn(&AuditRecord<'_>) + Send + Sync>;
+
+/// A sink or source whose frames are checked against a [`Policy`].
+///
+/// Outbound frames that are denied are not sent. Inbound frames that are
+/// denied have been consumed, so the stream stays usable; whether to carry on
+/// is up to the caller. Both fail with `AbutCode::PolicyDenied`.
+pub struct Enforced<T> {
+    inner: T,
+    policy: Arc<Policy>,
+    peer: Identity,
+    audit: Option<AuditFn>,
+    denied: u64,
+}
+
+impl<T> Enforced<T> {
+    pub fn new(inner: T, policy: Arc<Policy>, peer: Identity) -> Self {
+        Self { inner, policy, peer, audit: None, denied: 0 }
+    }
+
+    /// Reports every decision, allowed or not, to `audit`.
+    pub fn audit(mut self, audit: impl Fn(&AuditRecord<'_>) + Send + Sync + 'static) -> Self {
+        self.audit = Some(Arc::new(audit));
+        self
+    }
+
+    pub fn peer(&self) -> &Identity { &self.peer }
+    pub fn inner_mut(&mut self) -> &mut T { &mut self.inner }
+    pub fn into_inner(self) -> T { self.inner }
+
+    /// Frames refused so far.
+    pub fn denied(&self) -> u64 { self.denied }
+
+    fn check(&mut self, direction: Direction, frame: &[u8]) -> Result<(), AbutError> {
+        let id = message_id(frame);
+        let decision = self.policy.decide(&self.peer, direction, id);
+        if let Some(audit) = &self.audit {
+            audit(&AuditRecord { peer: &self.peer, direction, id, len: frame.len(), decision });
+        }
+        if decision.allowed {
+            return Ok(());
+        }
+        self.denied += 1;
+        let id = id.map_or_else(|| "frame without a message ID".to_owned(), |id| format!("message {id}"));
+        let verb = match direction {
+            Direction::Inbound => "from",
+            Direction::Outbound => "to",
+        };
+        Err(AbutError::new(AbutCode::PolicyDenied).ctx(format_args!("{id} {verb} uid {} denied", self.peer.cred.uid)))
+    }
+}
+
+impl<T: FrameSink<Error = AbutError>> FrameSink for Enforced<T> {
+    type Error = AbutError;
+
+    fn send_frame(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.check(Direction::Outbound, bytes)?;
+        self.inner.send_frame(bytes)
+    }
+}
+
+impl<T: FrameSource<Error = AbutError>> FrameSource for Enforced<T> {
+    type Error = AbutError;
+
+    fn recv_frame(&mut self, dst: &mut [u8]) -> Result<usize, Self::Error> {
+        let n = self.inner.recv_frame(dst)?;
+        self.check(Direction::Inbound, &dst[..n])?;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::{FramedReader, FramedWriter};
+    use std::os::unix::net::UnixStream;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn frame(id: u16) -> Vec<u8> {
+        let mut f = id.to_le_bytes().to_vec();
+        f.extend_from_slice(b"payload");
+        f
+    }
+
+    #[test]
+    fn test_parse_and_decide() {
+        let policy = Policy::parse(
+            "# telemetry\n\
+             allow uid=1000 recv 10,11\n\
+             allow any send *   # replies\n\
+             deny gid=5 both 11\n",
+        )
+        .unwrap();
+        let peer = |uid, gid| Identity { cred: PeerCred { pid: 1, uid, gid }, exe: None };
+
+        assert!(policy.decide(&peer(1000, 0), Direction::Inbound, Some(10)).allowed);
+        assert!(!policy.decide(&peer(1000, 0), Direction::Inbound, Some(12)).allowed);
+        assert!(!policy.decide(&peer(1001, 0), Direction::Inbound, Some(10)).allowed);
+        assert_eq!(policy.decide(&peer(1000, 5), Direction::Inbound, Some(11)), Decision { allowed: false, line: Some(4) });
+        assert_eq!(policy.decide(&peer(7, 7), Direction::Outbound, None), Decision { allowed: true, line: Some(3) });
+
+        for bad in ["permit any both *", "allow both *", "allow uid=x recv 1", "allow any recv", "allow any sideways 1",
+            "allow any recv 10 11"] {
+            assert_eq!(Policy::parse(bad).unwrap_err().code, AbutCode::PolicyInvalid, "{bad}");
+        }
+
+        // An unreadable executable is caught by exe= deny lines but passes no exe= allow line.
+        let policy = Policy::parse("allow any recv 1,2\nallow exe=/bin/x recv 3\ndeny exe=/bin/y recv 2").unwrap();
+        assert!(policy.decide(&peer(0, 0), Direction::Inbound, Some(1)).allowed);
+        assert_eq!(policy.decide(&peer(0, 0), Direction::Inbound, Some(2)), Decision { allowed: false, line: Some(3) });
+        assert!(!policy.decide(&peer(0, 0), Direction::Inbound, Some(3)).allowed);
+    }
+
+    #[test]
+    fn test_enforced_by_executable() {
+        let exe = std::env::current_exe().unwrap();
+        let policy = Arc::new(Policy::parse(&format!("allow exe={} recv 1\nallow any send 2", exe.display())).unwrap());
+        let (a, b) = UnixStream::pair().unwrap();
+        let peer = Identity::of(&a).unwrap();
+        assert!(policy.admit(&peer.cred));
+
+        let audited = Arc::new(AtomicUsize::new(0));
+        let counter = audited.clone();
+        let mut tx = Enforced::new(FramedWriter::new(&a), policy.clone(), peer.clone())
+            .audit(move |_| _ = counter.fetch_add(1, Ordering::Relaxed));
+        let mut rx = Enforced::new(FramedReader::new(&a), policy, peer);
+        let mut peer_w = FramedWriter::new(&b);
+
+        tx.send_frame(&frame(2)).unwrap();
+        assert_eq!(tx.send_frame(&frame(1)).unwrap_err().code, AbutCode::PolicyDenied);
+        assert_eq!((tx.denied(), audited.load(Ordering::Relaxed)), (1, 2));
+
+        peer_w.write_frame(&frame(2)).unwrap();
+        peer_w.write_frame(&frame(1)).unwrap();
+        let mut buf = [0u8; 64];
+        assert_eq!(rx.recv_frame(&mut buf).unwrap_err().code, AbutCode::PolicyDenied);
+        assert_eq!(rx.recv_frame(&mut buf).unwrap(), frame(1).len());
+    }
+}
@@ -0,0 +1,369 @@
+//! Priority scheduling of frames sharing one connection.
+//!
+//! [`PriorityWriter`] keeps a FIFO queue per [`Priority`] class and a thread
+//! that sends from the most urgent non-empty class first. Frames travel as
+//! chunks of at most `chunk_len` bytes, and the scheduler chooses again
+//! before every chunk, so a control frame overtakes a bulk frame that is
+//! already half-sent. A class passed over `starvation_limit` times in a row
+//! is served next regardless of priority.
+//!
+//! Every chunk carries its class and a last-chunk flag, which is all
+//! [`PriorityReader`] needs to reassemble the frames. Both ends must use it.
+
+use std::collections::VecDeque;
+
+use crate::worker::{Backlog, Outlet, Worker};
+use crate::{AbutError, FrameSink, FrameSource, OverflowPolicy, QueueLimits};
+
+/// Flag of a chunk followed by more of the same frame.
+pub const CHUNK_MORE: u8 = 0x00;
+/// Flag of a frame's final chunk.
+pub const CHUNK_LAST: u8 = 0x01;
+
+const HEADER_LEN: usize = 2;
+
+/// Scheduling class of a frame, most urgent first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Priority {
+    Control = 0,
+    High = 1,
+    #[default]
+    Normal = 2,
+    Bulk = 3,
+}
+
+const CLASSES: usize = 4;
+
+impl Priority {
+    pub const ALL: [Priority; CLASSES] = [Self::Control, Self::High, Self::Normal, Self::Bulk];
+
+    fn from_lane(lane: u8) -> Option<Self> {
+        Self::ALL.get(lane as usize).copied()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PriorityConfig {
+    /// Largest chunk written at once; frames are preemptible between chunks.
+    pub chunk_len: usize,
+
+    /// A waiting class skipped this many times in a row gets the next chunk.
+    pub starvation_limit: u32,
+
+    /// Bound on everything queued across classes; sends block while it is full.
+    pub queue: QueueLimits,
+}
+
+impl Default for PriorityConfig {
+    fn default() -> Self {
+        Self { chunk_len: 16 * 1024, starvation_limit: 16, queue: QueueLimits::default() }
+    }
+}
+
+struct Pending {
+    data: Vec<u8>,
+    sent: usize,
+}
+
+/// One FIFO per class, handed out a chunk at a time.
+struct Lanes {
+    classes: [VecDeque<Pending>; CLASSES],
+    skipped: [u32; CLASSES],
+    frames: usize,
+    bytes: usize,
+    chunk_len: usize,
+    starvation_limit: u32,
+}
+
+impl Lanes {
+    /// Picks the class to send the next chunk from.
+    fn pick(&mut self) -> Option<usize> {
+        let waiting = |c: &usize| !self.classes[*c].is_empty();
+        let first = (0..CLASSES).find(waiting)?;
+        let starved = (first + 1..CLASSES)
+            .filter(waiting)
+            .filter(|&c| self.skipped[c] >= self.starvation_limit)
+            .max_by_key(|&c| self.skipped[c]);
+        let chosen = starved.unwrap_or(first);
+        for c in 0..CLASSES {
+            if c == chosen {
+                self.skipped[c] = 0;
+            } else if !self.classes[c].is_empty() {
+                self.skipped[c] += 1;
+            }
+        }
+        Some(chosen)
+    }
+}
+
+impl Backlog for Lanes {
+    type Tag = Priority;
+
+    fn frames(&self) -> usize { self.frames }
+    fn bytes(&self) -> usize { self.bytes }
+
+    fn push(&mut self, priority: Priority, data: Vec<u8>) {
+        self.frames += 1;
+        self.bytes += data.len();
+        self.classes[priority as usize].push_back(Pending { data, sent: 0 });
+    }
+
+    /// Drops from the least urgent class first.
+    fn evict(&mut self) -> bool {
+        for class in self.classes.iter_mut().rev() {
+            if let Some(i) = class.iter().position(|p| p.sent == 0) {
+                let old = class.remove(i).expect("position is in range");
+                self.frames -= 1;
+                self.bytes -= old.data.len();
+                return true;
+            }
+        }
+        false
+    }
+
+    fn next(&mut self, wire: &mut Vec<u8>) -> bool {
+        let Some(class) = self.pick() else { return false };
+        let front = self.classes[class].front_mut().expect("picked class is non-empty");
+        let end = (front.sent + self.chunk_len).min(front.data.len());
+        let last = end == front.data.len();
+        wire.clear();
+        wire.push(class as u8);
+        wire.push(if last { CHUNK_LAST } else { CHUNK_MORE });
+        wire.extend_from_slice(&front.data[front.sent..end]);
+        front.sent = end;
+        if last {
+            let done = self.classes[class].pop_front().expect("front exists");
+            self.frames -= 1;
+            self.bytes -= done.data.len();
+        }
+        true
+    }
+
+    fn clear(&mut self) {
+        self.classes = Default::default();
+        self.frames = 0;
+        self.bytes = 0;
+    }
+}
+
+/// The sink a [`PriorityWriter`] sends its chunks into.
+struct Chunks<S>(S);
+
+impl<S: FrameSink<Error = AbutError> + Send + 'static> Outlet for Chunks<S> {
+    fn send(&mut self, wire: &[u8]) -> Result<(), AbutError> {
+        self.0.send_frame(wire)
+    }
+}
+
+/// Sends frames from a background thread, most urgent class first.
+///
+/// Like a [`QueuedWriter`](crate::frame::queued::QueuedWriter) it sends
+/// what is still queued when dropped.
+pub struct PriorityWriter<S: FrameSink<Error = AbutError> + Send + 'static> {
+    worker: Worker<Lanes, Chunks<S>>,
+}
+
+impl<S: FrameSink<Error = AbutError> + Send + 'static> PriorityWriter<S> {
+    pub fn new(sink: S) -> Self {
+        Self::with_config(sink, PriorityConfig::default())
+    }
+
+    pub fn with_config(sink: S, cfg: PriorityConfig) -> Self {
+        let lanes = Lanes {
+            classes: Default::default(),
+            skipped: [0; CLASSES],
+            frames: 0,
+            bytes: 0,
+            chunk_len: cfg.chunk_len.max(1),
+            starvation_limit: cfg.starvation_limit,
+        };
+        Self { worker: Worker::spawn("priority writer", lanes, Chunks(sink), cfg.queue, OverflowPolicy::Block) }
+    }
+
+    /// Queues a frame in class `priority`, blocking while the queue is full.
+    pub fn send(&self, priority: Priority, bytes: &[u8]) -> Result<(), AbutError> {
+        self.worker.shared().enqueue(priority, bytes)
+    }
+
+    /// Frames queued per class, including any partly sent.
+    pub fn queued(&self) -> [usize; CLASSES] {
+        self.worker.shared().backlog(|lanes| std::array::from_fn(|c| lanes.classes[c].len()))
+    }
+
+    /// Blocks until everything queued has been sent.
+    pub fn flush(&self) -> Result<(), AbutError> {
+        self.worker.flush()
+    }
+
+    /// Sends what is queued and returns the underlying sink.
+    pub fn close(self) -> Result<S, AbutError> {
+        self.worker.close().map(|chunks| chunks.0)
+    }
+}
+
+impl<S: FrameSink<Error = AbutError> + Send + 'static> FrameSink for PriorityWriter<S> {
+    type Error = AbutError;
+
+    /// Sends at [`Priority::Normal`].
+    fn send_frame(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.send(Priority::Normal, bytes)
+    }
+}
+
+/// Reassembles frames written by a [`PriorityWriter`].
+pub struct PriorityReader<R> {
+    source: R,
+    max_frame_len: usize,
+    partial: [Vec<u8>; CLASSES],
+    /// Classes whose current frame exceeded `max_frame_len` and is being discarded.
+    discarding: [bool; CLASSES],
+    ready: Option<(Priority, Vec<u8>)>,
+    scratch: Vec<u8>,
+    chunk_len: usize,
+}
+
+impl<R: FrameSource<Error = AbutError>> PriorityReader<R> {
+    /// `chunk_len` must be at least the writer's.
+    pub fn new(source: R, chunk_len: usize, max_frame_len: usize) -> Self {
+        Self {
+            source,
+            max_frame_len,
+            partial: Default::default(),
+            discarding: [false; CLASSES],
+            ready: None,
+            scratch: Vec::new(),
+            chunk_len,
+        }
+    }
+
+    pub fn into_inner(self) -> R { self.source }
+
+    /// Receives the next complete frame into `dst` and returns its class.
+    ///
+    /// A frame larger than `max_frame_len` is discarded as its chunks arrive
+    /// and reported once with `AbutCode::FrameTooLarge`.
+    pub fn recv_into(&mut self, dst: &mut Vec<u8>) -> Result<Priority, AbutError> {
+        let (priority, frame) = self.next()?;
+        *dst = frame;
+        Ok(priority)
+    }
+
+    fn next(&mut self) -> Result<(Priority, Vec<u8>), AbutError> {
+        if let Some(ready) = self.ready.take() {
+            return Ok(ready);
+        }
+        loop {
+            self.scratch.resize(HEADER_LEN + self.chunk_len, 0);
+            let n = self.source.recv_frame(&mut self.scratch)?;
+            let chunk = &self.scratch[..n];
+            let [lane, flag, body @ ..] = chunk else {
+                return Err(AbutError::protocol(format_args!("priority chunk of {n} bytes")));
+            };
+            let priority = Priority::from_lane(*lane)
+                .ok_or_else(|| AbutError::protocol(format_args!("unknown priority lane {lane}")))?;
+            let c = priority as usize;
+            let last = match *flag {
+                CHUNK_LAST => true,
+                CHUNK_MORE => false,
+                other => return Err(AbutError::protocol(format_args!("unknown chunk flag {other:#04x}"))),
+            };
+
+            let len = self.partial[c].len() + body.len();
+            if !self.discarding[c] && len > self.max_frame_len {
+                self.discarding[c] = true;
+                self.partial[c] = Vec::new();
+            }
+            if self.discarding[c] {
+                if last {
+                    self.discarding[c] = false;
+                    return Err(AbutError::frame_too_large(len, self.max_frame_len));
+                }
+                continue;
+            }
+            self.partial[c].extend_from_slice(body);
+            if last {
+                return Ok((priority, std::mem::take(&mut self.partial[c])));
+            }
+        }
+    }
+}
+
+impl<R: FrameSource<Error = AbutError>> FrameSource for PriorityReader<R> {
+    type Error = AbutError;
+
+    /// Receives the next complete frame of any class. If `dst` is too small
+    /// the frame is kept for the next call.
+    fn recv_frame(&mut self, dst: &mut [u8]) -> Result<usize, Self::Error> {
+        let (priority, frame) = self.next()?;
+        if frame.len() > dst.len() {
+            let needed = frame.len();
+            self.ready = Some((priority, frame));
+            return Err(AbutError::buffer_too_small(needed));
+        }
+        dst[..frame.len()].copy_from_slice(&frame);
+        Ok(frame.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AbutCode;
+    use crate::worker::testing::{Gate, parked};
+
+    struct Replay(VecDeque<Vec<u8>>);
+
+    impl FrameSource for Replay {
+        type Error = AbutError;
+        fn recv_frame(&mut self, dst: &mut [u8]) -> Result<usize, AbutError> {
+            let chunk = self.0.pop_front().ok_or_else(AbutError::closed)?;
+            dst[..chunk.len()].copy_from_slice(&chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    fn run(cfg: PriorityConfig, sends: &[(Priority, Vec<u8>)]) -> Vec<Vec<u8>> {
+        let (gate, release) = Gate::new();
+        let w = PriorityWriter::with_config(gate, cfg);
+        w.send(Priority::Bulk, b"w").unwrap();
+        parked(|| w.queued()[Priority::Bulk as usize]);
+        for (p, data) in sends {
+            w.send(*p, data).unwrap();
+        }
+        drop(release);
+        w.close().unwrap().written.split_off(1)
+    }
+
+    #[test]
+    fn test_control_preempts_bulk_at_chunk_boundary() {
+        let cfg = PriorityConfig { chunk_len: 4, ..Default::default() };
+        let chunks = run(cfg, &[(Priority::Bulk, vec![7; 10]), (Priority::Control, b"stop".to_vec())]);
+        assert_eq!(chunks[0], [&[0, CHUNK_LAST][..], b"stop"].concat());
+
+        let mut reader = PriorityReader::new(Replay(chunks.into()), 4, 64);
+        let mut buf = Vec::new();
+        assert_eq!(reader.recv_into(&mut buf).unwrap(), Priority::Control);
+        assert_eq!(buf, b"stop");
+        assert_eq!(reader.recv_into(&mut buf).unwrap(), Priority::Bulk);
+        assert_eq!(buf, vec![7; 10]);
+    }
+
+    #[test]
+    fn test_starved_class_gets_a_turn() {
+        let cfg = PriorityConfig { chunk_len: 64, starvation_limit: 2, ..Default::default() };
+        let mut sends = vec![(Priority::Bulk, b"b".to_vec())];
+        sends.extend((0..5).map(|i| (Priority::High, vec![i])));
+        let lanes: Vec<u8> = run(cfg, &sends).iter().map(|c| c[0]).collect();
+        assert_eq!(lanes, vec![1, 1, 3, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_reader_rejects_oversized_frame_and_recovers() {
+        let chunks = vec![vec![2, CHUNK_MORE, 1, 2, 3], vec![2, CHUNK_LAST, 4, 5], vec![1, CHUNK_LAST, 9]];
+        let mut reader = PriorityReader::new(Replay(chunks.into()), 8, 4);
+        let mut buf = Vec::new();
+        assert_eq!(reader.recv_into(&mut buf).unwrap_err().code, AbutCode::FrameTooLarge);
+        assert_eq!(reader.recv_into(&mut buf).unwrap(), Priority::High);
+        assert_eq!(buf, [9]);
+    }
+}
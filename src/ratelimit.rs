@@ -0,0 +1,310 @@
+//! Token-bucket throttling of inbound frames.
+//!
+//! [`RateLimited`] wraps a [`FrameSource`] and charges every received frame
+//! against a frames/sec and a bytes/sec bucket for the whole connection, and
+//! optionally against separate buckets per message type. What happens to a
+//! frame that exceeds a limit is set by [`ExceedAction`].
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::{AbutCode, AbutError, FrameSource};
+
+/// A sustained rate plus the burst allowed on top of it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate {
+    /// Zero (or NaN) means the bucket never refills: once the burst is spent,
+    /// every later frame is over the limit, and `Delay` fails it instead of
+    /// waiting forever. So does a rate too slow to refill within [`MAX_DELAY`].
+    pub per_sec: f64,
+    /// Bucket capacity; also the most a single frame is ever charged up front.
+    pub burst: f64,
+}
+
+impl Rate {
+    pub fn new(per_sec: f64, burst: f64) -> Self {
+        Self { per_sec, burst }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RateLimits {
+    pub frames: Option<Rate>,
+    pub bytes: Option<Rate>,
+}
+
+/// Longest a single frame is held under [`ExceedAction::Delay`]; a frame
+/// that would need longer fails as under [`ExceedAction::Disconnect`].
+pub const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// What to do with a frame that arrives over the limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExceedAction {
+    /// Hold the frame until the buckets allow it. Not reading meanwhile
+    /// pushes the backpressure onto the peer.
+    #[default]
+    Delay,
+    /// Discard the frame and count it.
+    Drop,
+    /// Fail with `AbutCode::RateLimited`, now and on every later call. The
+    /// transport underneath is left open: drop or shut it down.
+    Disconnect,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    rate: Rate,
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn new(rate: Rate, now: Instant) -> Self {
+        Self { rate, tokens: rate.burst, last: now }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        if self.rate.per_sec > 0.0 {
+            self.tokens = (self.tokens + elapsed * self.rate.per_sec).min(self.rate.burst);
+        }
+        self.last = now;
+    }
+
+    /// How long until `cost` may be charged; zero if it may be charged now,
+    /// `None` if the bucket will never hold enough.
+    ///
+    /// Costs above the burst only need a full bucket and leave it in debt,
+    /// so an occasional large frame is slowed rather than refused forever.
+    fn wait_for(&self, cost: f64) -> Option<Duration> {
+        let needed = cost.min(self.rate.burst) - self.tokens;
+        if needed <= 0.0 {
+            return Some(Duration::ZERO);
+        }
+        (self.rate.per_sec > 0.0)
+            .then(|| Duration::try_from_secs_f64(needed / self.rate.per_sec).unwrap_or(Duration::MAX))
+    }
+
+    fn charge(&mut self, cost: f64) {
+        self.tokens -= cost;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+    frames: Option<Bucket>,
+    bytes: Option<Bucket>,
+}
+
+impl Buckets {
+    fn new(limits: RateLimits, now: Instant) -> Self {
+        Self {
+            frames: limits.frames.map(|r| Bucket::new(r, now)),
+            bytes: limits.bytes.map(|r| Bucket::new(r, now)),
+        }
+    }
+
+    fn each(&mut self, len: usize) -> impl Iterator<Item = (&mut Bucket, f64)> {
+        self.frames.iter_mut().map(|b| (b, 1.0)).chain(self.bytes.iter_mut().map(move |b| (b, len as f64)))
+    }
+}
+
+type KeyFn = Box<dyn Fn(&[u8]) -> Option<u16> + Send>;
+
+/// A [`FrameSource`] that throttles what it passes on.
+pub struct RateLimited<S> {
+    inner: S,
+    action: ExceedAction,
+    conn: Buckets,
+    key: Option<KeyFn>,
+    per_key: HashMap<u16, RateLimits>,
+    keyed: HashMap<u16, Buckets>,
+    dropped: u64,
+    delayed: Duration,
+    tripped: bool,
+}
+
+impl<S: FrameSource<Error = AbutError>> RateLimited<S> {
+    /// Applies `limits` to the connection as a whole.
+    pub fn new(inner: S, limits: RateLimits, action: ExceedAction) -> Self {
+        Self {
+            inner,
+            action,
+            conn: Buckets::new(limits, Instant::now()),
+            key: None,
+            per_key: HashMap::new(),
+            keyed: HashMap::new(),
+            dropped: 0,
+            delayed: Duration::ZERO,
+            tripped: false,
+        }
+    }
+
+    /// Classifies frames by message type; `key` returns `None` for frames
+    /// that only count against the connection-wide limits.
+    pub fn keyed_by(mut self, key: impl Fn(&[u8]) -> Option<u16> + Send + 'static) -> Self {
+        self.key = Some(Box::new(key));
+        self
+    }
+
+    /// Adds limits for one message type, on top of the connection-wide ones.
+    pub fn limit(mut self, key: u16, limits: RateLimits) -> Self {
+        self.per_key.insert(key, limits);
+        self
+    }
+
+    pub fn inner_mut(&mut self) -> &mut S { &mut self.inner }
+    pub fn into_inner(self) -> S { self.inner }
+
+    /// Frames discarded under [`ExceedAction::Drop`].
+    pub fn dropped(&self) -> u64 { self.dropped }
+
+    /// Total time spent holding frames under [`ExceedAction::Delay`].
+    pub fn delayed(&self) -> Duration { self.delayed }
+
+    fn tripped_error() -> AbutError {
+        AbutError::new(AbutCode::RateLimited).ctx("connection was cut off for exceeding its rate limit")
+    }
+
+    /// Charges a frame of `len` bytes, returning false if it must be dropped.
+    fn admit(&mut self, frame: &[u8]) -> Result<bool, AbutError> {
+        let now = Instant::now();
+        let key = self.key.as_ref().and_then(|f| f(frame)).filter(|k| self.per_key.contains_key(k));
+        if let Some(k) = key {
+            let limits = self.per_key[&k];
+            self.keyed.entry(k).or_insert_with(|| Buckets::new(limits, now));
+        }
+
+        let len = frame.len();
+        let mut wait = Some(Duration::ZERO);
+        let keyed = key.and_then(|k| self.keyed.get_mut(&k));
+        for (bucket, cost) in self.conn.each(len).chain(keyed.into_iter().flat_map(|b| b.each(len))) {
+            bucket.refill(now);
+            wait = wait.zip(bucket.wait_for(cost)).map(|(a, b)| a.max(b));
+        }
+
+        if wait != Some(Duration::ZERO) {
+            match (self.action, wait) {
+                (ExceedAction::Delay, Some(wait)) if wait <= MAX_DELAY => {
+                    std::thread::sleep(wait);
+                    self.delayed = self.delayed.saturating_add(wait);
+                }
+                (ExceedAction::Drop, _) => {
+                    self.dropped += 1;
+                    return Ok(false);
+                }
+                (ExceedAction::Delay | ExceedAction::Disconnect, _) => {
+                    self.tripped = true;
+                    return Err(AbutError::new(AbutCode::RateLimited)
+                        .ctx(format_args!("frame of {len} bytes{} over the limit", key.map(|k| format!(" (type {k})")).unwrap_or_default())));
+                }
+            }
+        }
+
+        let now = Instant::now();
+        let keyed = key.and_then(|k| self.keyed.get_mut(&k));
+        for (bucket, cost) in self.conn.each(len).chain(keyed.into_iter().flat_map(|b| b.each(len))) {
+            bucket.refill(now);
+            bucket.charge(cost);
+        }
+        Ok(true)
+    }
+}
+
+impl<S: FrameSource<Error = AbutError>> FrameSource for RateLimited<S> {
+    type Error = AbutError;
+
+    fn recv_frame(&mut self, dst: &mut [u8]) -> Result<usize, Self::Error> {
+        if self.tripped {
+            return Err(Self::tripped_error());
+        }
+        loop {
+            let n = self.inner.recv_frame(dst)?;
+            if self.admit(&dst[..n])? {
+                return Ok(n);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::{FramedReader, FramedWriter};
+    use std::io::Cursor;
+
+    fn stream(frames: &[&[u8]]) -> FramedReader<Cursor<Vec<u8>>> {
+        let mut buf = Vec::new();
+        let mut w = FramedWriter::new(&mut buf);
+        for f in frames {
+            w.write_frame(f).unwrap();
+        }
+        FramedReader::new(Cursor::new(buf))
+    }
+
+    fn drain<S: FrameSource<Error = AbutError>>(src: &mut S) -> Vec<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut buf = [0u8; 64];
+        loop {
+            match src.recv_frame(&mut buf) {
+                Ok(n) => out.push(buf[..n].to_vec()),
+                Err(e) if e.code == AbutCode::Closed => return out,
+                Err(e) => panic!("{e}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_drop_counts_excess_frames() {
+        let limits = RateLimits { frames: Some(Rate::new(1.0, 2.0)), bytes: None };
+        let mut src = RateLimited::new(stream(&[b"a", b"b", b"c", b"d"]), limits, ExceedAction::Drop);
+        assert_eq!(drain(&mut src), vec![b"a".to_vec(), b"b".to_vec()]);
+        assert_eq!(src.dropped(), 2);
+    }
+
+    #[test]
+    fn test_delay_paces_bytes() {
+        let limits = RateLimits { frames: None, bytes: Some(Rate::new(1000.0, 10.0)) };
+        let mut src = RateLimited::new(stream(&[&[0; 10], &[0; 20], &[0; 10]]), limits, ExceedAction::Delay);
+        let start = Instant::now();
+        assert_eq!(drain(&mut src).len(), 3);
+        // 10 bytes of burst, then the 20-byte frame leaves 10 bytes of debt to wait out.
+        assert!(start.elapsed() >= Duration::from_millis(15));
+        assert!(src.delayed() >= Duration::from_millis(15));
+    }
+
+    #[test]
+    fn test_per_type_limit_disconnects() {
+        let limits = RateLimits { frames: Some(Rate::new(1.0, 1.0)), bytes: None };
+        let mut src = RateLimited::new(stream(&[b"\x01a", b"\x02b", b"\x02c", b"\x01d"]), RateLimits::default(), ExceedAction::Disconnect)
+            .keyed_by(|f| f.first().map(|&t| t as u16))
+            .limit(1, limits);
+        let mut buf = [0u8; 8];
+        assert_eq!(src.recv_frame(&mut buf).unwrap(), 2);
+        // Type 2 is unlimited.
+        assert_eq!(src.recv_frame(&mut buf).unwrap(), 2);
+        assert_eq!(src.recv_frame(&mut buf).unwrap(), 2);
+        assert_eq!(src.recv_frame(&mut buf).unwrap_err().code, AbutCode::RateLimited);
+        assert_eq!(src.recv_frame(&mut buf).unwrap_err().code, AbutCode::RateLimited);
+    }
+
+    #[test]
+    fn test_zero_and_tiny_rates() {
+        let limits = RateLimits { frames: Some(Rate::new(0.0, 1.0)), bytes: None };
+        let mut src = RateLimited::new(stream(&[b"a", b"b"]), limits, ExceedAction::Delay);
+        let mut buf = [0u8; 8];
+        assert_eq!(src.recv_frame(&mut buf).unwrap(), 1);
+        assert_eq!(src.recv_frame(&mut buf).unwrap_err().code, AbutCode::RateLimited);
+
+        let limits = RateLimits { frames: Some(Rate::new(1e-300, 1.0)), bytes: None };
+        let mut src = RateLimited::new(stream(&[b"a", b"b"]), limits, ExceedAction::Drop);
+        assert_eq!(drain(&mut src).len(), 1);
+        assert_eq!(src.dropped(), 1);
+
+        // Too slow to wait out: Delay fails rather than sleeping for ages.
+        let mut src = RateLimited::new(stream(&[b"a", b"b"]), limits, ExceedAction::Delay);
+        assert_eq!(src.recv_frame(&mut buf).unwrap(), 1);
+        assert_eq!(src.recv_frame(&mut buf).unwrap_err().code, AbutCode::RateLimited);
+        assert_eq!(src.delayed(), Duration::ZERO);
+    }
+}
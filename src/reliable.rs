@@ -0,0 +1,463 @@
+//! Exactly-once delivery across reconnects.
+//!
+//! [`Reliable`] numbers every outbound frame, keeps it until the peer
+//! acknowledges it, and discards inbound frames it has already delivered.
+//! When the transport breaks, the caller opens a new one and hands it to
+//! [`Reliable::resume`]: both sides exchange the next sequence number they
+//! expect, and whatever the peer has not yet seen is sent again. Both ends
+//! must use it.
+//!
+//! Session state lives in memory, so the guarantee holds across reconnects of
+//! the transport, not across restarts of either process.
+
+use std::collections::VecDeque;
+use std::io;
+use std::time::SystemTime;
+
+use crate::{AbutCode, AbutError, FrameSink, FrameSource, QueueLimits};
+
+/// Tag of a data frame: `seq: u64 LE` then the payload.
+pub const TAG_DATA: u8 = 0x20;
+/// Tag of a cumulative acknowledgement: the highest `seq: u64 LE` received.
+pub const TAG_ACK: u8 = 0x21;
+/// Tag of the resume handshake: `session: u64 LE`, `next_expected: u64 LE`.
+pub const TAG_RESUME: u8 = 0x22;
+/// Tag asking for an acknowledgement now, sent when the window is full:
+/// the last `seq: u64 LE` sent.
+pub const TAG_ACK_REQUEST: u8 = 0x23;
+
+const HEADER_LEN: usize = 1 + 8;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReliableConfig {
+    /// Bound on frames sent but not yet acknowledged. A send that would
+    /// exceed it asks the peer for an acknowledgement and reads until one
+    /// makes room. Frames the peer sends meanwhile are queued within the
+    /// same bound.
+    pub window: QueueLimits,
+
+    /// Acknowledge after this many received frames. At most
+    /// `window.max_frames`, or a full window would wait on an ack that never
+    /// comes.
+    pub ack_every: u32,
+
+    /// Largest payload accepted from the peer.
+    pub max_frame_len: usize,
+}
+
+impl Default for ReliableConfig {
+    fn default() -> Self {
+        Self { window: QueueLimits::default(), ack_every: 16, max_frame_len: 64 * 1024 }
+    }
+}
+
+pub struct Reliable<S, R> {
+    sink: S,
+    source: R,
+    cfg: ReliableConfig,
+    session: u64,
+    peer_session: Option<u64>,
+    next_seq: u64,
+    unacked: VecDeque<(u64, Vec<u8>)>,
+    unacked_bytes: usize,
+    /// Next inbound sequence number; everything below it has been accepted.
+    recv_next: u64,
+    pending_acks: u32,
+    inbound: VecDeque<Vec<u8>>,
+    inbound_bytes: usize,
+    scratch: Vec<u8>,
+}
+
+impl<S, R> Reliable<S, R>
+where
+    S: FrameSink<Error = AbutError>,
+    R: FrameSource<Error = AbutError>,
+{
+    /// Starts a session over a fresh transport, performing the resume handshake.
+    pub fn connect(sink: S, source: R, cfg: ReliableConfig) -> Result<Self, AbutError> {
+        if usize::try_from(cfg.ack_every).map_or(true, |n| n > cfg.window.max_frames) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("ack_every {} exceeds a window of {} frames", cfg.ack_every, cfg.window.max_frames),
+            )
+            .into());
+        }
+        let session = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64)
+            ^ u64::from(std::process::id()).rotate_left(32);
+        let mut this = Self {
+            sink,
+            source,
+            cfg,
+            session,
+            peer_session: None,
+            next_seq: 1,
+            unacked: VecDeque::new(),
+            unacked_bytes: 0,
+            recv_next: 1,
+            pending_acks: 0,
+            inbound: VecDeque::new(),
+            inbound_bytes: 0,
+            scratch: Vec::new(),
+        };
+        this.handshake()?;
+        Ok(this)
+    }
+
+    /// Continues the session over a new transport after the old one failed.
+    ///
+    /// Frames the peer never acknowledged are sent again; it drops any it
+    /// had in fact received.
+    pub fn resume(&mut self, sink: S, source: R) -> Result<(), AbutError> {
+        self.sink = sink;
+        self.source = source;
+        self.handshake()
+    }
+
+    pub fn sink_mut(&mut self) -> &mut S { &mut self.sink }
+    pub fn source_mut(&mut self) -> &mut R { &mut self.source }
+
+    /// Frames sent but not yet acknowledged.
+    pub fn unacked(&self) -> usize { self.unacked.len() }
+
+    /// Sequence number the next outbound frame will carry.
+    pub fn next_seq(&self) -> u64 { self.next_seq }
+
+    fn handshake(&mut self) -> Result<(), AbutError> {
+        let mut frame = [0u8; 1 + 16];
+        frame[0] = TAG_RESUME;
+        frame[1..9].copy_from_slice(&self.session.to_le_bytes());
+        frame[9..].copy_from_slice(&self.recv_next.to_le_bytes());
+        self.sink.send_frame(&frame)?;
+        self.pending_acks = 0;
+
+        let n = self.read_raw()?;
+        let body = &self.scratch[..n];
+        if body.len() != frame.len() || body[0] != TAG_RESUME {
+            return Err(AbutError::protocol("expected a resume frame from the peer"));
+        }
+        let peer_session = u64::from_le_bytes(body[1..9].try_into().expect("8 bytes"));
+        let peer_next = u64::from_le_bytes(body[9..].try_into().expect("8 bytes"));
+
+        if self.peer_session.is_some_and(|s| s != peer_session) {
+            return Err(AbutError::protocol("peer started a new session; delivery state was lost"));
+        }
+        self.peer_session = Some(peer_session);
+        let upto = peer_next.checked_sub(1).ok_or_else(|| AbutError::protocol("peer expects seq 0"))?;
+        self.acknowledged(upto)?;
+
+        for i in 0..self.unacked.len() {
+            let (seq, payload) = &self.unacked[i];
+            let frame = encode_data(*seq, payload);
+            self.sink.send_frame(&frame)?;
+        }
+        Ok(())
+    }
+
+    fn read_raw(&mut self) -> Result<usize, AbutError> {
+        self.scratch.resize(HEADER_LEN + self.cfg.max_frame_len, 0);
+        self.source.recv_frame(&mut self.scratch)
+    }
+
+    /// Drops retained frames up to and including `upto`, which must lie
+    /// between what the peer already acknowledged and what was sent.
+    fn acknowledged(&mut self, upto: u64) -> Result<(), AbutError> {
+        let sent = self.next_seq - 1;
+        let floor = sent - self.unacked.len() as u64;
+        if upto > sent {
+            return Err(AbutError::protocol(format_args!("peer acknowledged seq {upto}, only {sent} sent")));
+        }
+        if upto < floor {
+            return Err(AbutError::protocol(format_args!("peer acknowledged seq {upto}, below {floor} already acknowledged")));
+        }
+        while let Some((seq, payload)) = self.unacked.front() {
+            if *seq > upto {
+                break;
+            }
+            self.unacked_bytes -= payload.len();
+            self.unacked.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Sends a cumulative acknowledgement for everything received so far.
+    pub fn flush_acks(&mut self) -> Result<(), AbutError> {
+        if self.pending_acks == 0 {
+            return Ok(());
+        }
+        self.send_control(TAG_ACK, self.recv_next - 1)?;
+        self.pending_acks = 0;
+        Ok(())
+    }
+
+    fn send_control(&mut self, tag: u8, seq: u64) -> Result<(), AbutError> {
+        let mut frame = [0u8; HEADER_LEN];
+        frame[0] = tag;
+        frame[1..].copy_from_slice(&seq.to_le_bytes());
+        self.sink.send_frame(&frame)
+    }
+
+    /// Reads and handles one frame from the transport.
+    fn pump(&mut self) -> Result<(), AbutError> {
+        let n = self.read_raw()?;
+        let (&tag, body) = self.scratch[..n].split_first().ok_or_else(|| AbutError::protocol("empty reliable frame"))?;
+        let seq = body
+            .get(..8)
+            .map(|s| u64::from_le_bytes(s.try_into().expect("8 bytes")))
+            .ok_or_else(|| AbutError::protocol(format_args!("reliable frame of {n} bytes")))?;
+        match tag {
+            TAG_DATA if seq < self.recv_next => {}
+            TAG_DATA if seq == self.recv_next => {
+                self.inbound_bytes += body.len() - 8;
+                self.inbound.push_back(body[8..].to_vec());
+                self.recv_next += 1;
+                self.pending_acks += 1;
+                if self.pending_acks >= self.cfg.ack_every {
+                    self.flush_acks()?;
+                }
+            }
+            TAG_DATA => {
+                return Err(AbutError::protocol(format_args!("gap in sequence: got {seq}, expected {}", self.recv_next)));
+            }
+            TAG_ACK => self.acknowledged(seq)?,
+            TAG_ACK_REQUEST => {
+                self.send_control(TAG_ACK, self.recv_next - 1)?;
+                self.pending_acks = 0;
+            }
+            other => return Err(AbutError::protocol(format_args!("unexpected reliable tag {other:#04x}"))),
+        }
+        Ok(())
+    }
+}
+
+fn encode_data(seq: u64, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.push(TAG_DATA);
+    frame.extend_from_slice(&seq.to_le_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+impl<S, R> FrameSink for Reliable<S, R>
+where
+    S: FrameSink<Error = AbutError>,
+    R: FrameSource<Error = AbutError>,
+{
+    type Error = AbutError;
+
+    /// Sends a frame, retaining it for retransmission until acknowledged.
+    ///
+    /// Fails with `AbutCode::QueueFull`, having sent nothing, if the window
+    /// is full and so many frames from the peer are waiting to be read that
+    /// no more can be taken in while waiting.
+    ///
+    /// If the transport fails the frame is already retained: resume and
+    /// carry on rather than sending it again.
+    fn send_frame(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        let window = self.cfg.window;
+        if bytes.len() > window.max_bytes {
+            return Err(AbutError::new(AbutCode::QueueFull)
+                .ctx(format_args!("frame of {} bytes exceeds the retransmit window", bytes.len())));
+        }
+        let mut asked = false;
+        while self.unacked.len() >= window.max_frames || self.unacked_bytes + bytes.len() > window.max_bytes {
+            self.flush_acks()?;
+            // The peer acks every `ack_every` frames, which a full byte window may never reach.
+            if !asked {
+                self.send_control(TAG_ACK_REQUEST, self.next_seq - 1)?;
+                asked = true;
+            }
+            if self.inbound.len() >= window.max_frames || self.inbound_bytes >= window.max_bytes {
+                return Err(AbutError::new(AbutCode::QueueFull)
+                    .ctx(format_args!("{} received frames not yet read; read before sending more", self.inbound.len())));
+            }
+            self.pump()?;
+        }
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.unacked_bytes += bytes.len();
+        self.unacked.push_back((seq, bytes.to_vec()));
+        self.sink.send_frame(&encode_data(seq, bytes))
+    }
+}
+
+impl<S, R> FrameSource for Reliable<S, R>
+where
+    S: FrameSink<Error = AbutError>,
+    R: FrameSource<Error = AbutError>,
+{
+    type Error = AbutError;
+
+    /// Receives the next frame not delivered before. An aligned timeout from
+    /// the transport flushes pending acknowledgements before it is returned.
+    fn recv_frame(&mut self, dst: &mut [u8]) -> Result<usize, Self::Error> {
+        loop {
+            if let Some(front) = self.inbound.front() {
+                if front.len() > dst.len() {
+                    return Err(AbutError::buffer_too_small(front.len()));
+                }
+                let frame = self.inbound.pop_front().expect("checked above");
+                self.inbound_bytes -= frame.len();
+                dst[..frame.len()].copy_from_slice(&frame);
+                return Ok(frame.len());
+            }
+            match self.pump() {
+                Ok(()) => {}
+                Err(e) if e.code == AbutCode::Timeout && e.stream_aligned() == Some(true) => {
+                    self.flush_acks()?;
+                    return Err(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::{FramedReader, FramedWriter};
+    use std::os::unix::net::UnixStream;
+
+    type Link = Reliable<FramedWriter<UnixStream>, FramedReader<UnixStream>>;
+
+    fn parts(stream: UnixStream) -> (FramedWriter<UnixStream>, FramedReader<UnixStream>) {
+        (FramedWriter::new(stream.try_clone().unwrap()), FramedReader::new(stream))
+    }
+
+    fn pair(cfg: ReliableConfig) -> (Link, Link) {
+        let (a, b) = UnixStream::pair().unwrap();
+        let peer = std::thread::spawn(move || {
+            let (w, r) = parts(b);
+            Reliable::connect(w, r, cfg).unwrap()
+        });
+        let (w, r) = parts(a);
+        let a = Reliable::connect(w, r, cfg).unwrap();
+        (a, peer.join().unwrap())
+    }
+
+    #[test]
+    fn test_resume_retransmits_without_duplicates() {
+        let cfg = ReliableConfig { ack_every: 2, ..Default::default() };
+        let (mut a, mut b) = pair(cfg);
+
+        for msg in [&b"one"[..], b"two", b"three"] {
+            a.send_frame(msg).unwrap();
+        }
+        let mut buf = [0u8; 16];
+        // b receives all three but only "one" and "two" are acknowledged (ack_every = 2).
+        for want in [&b"one"[..], b"two", b"three"] {
+            let n = b.recv_frame(&mut buf).unwrap();
+            assert_eq!(&buf[..n], want);
+        }
+        // a learns of the ack for 2 on its next read; "three" is still pending.
+        a.pump().unwrap();
+        assert_eq!(a.unacked(), 1);
+
+        // The transport breaks; a frame sent now is retained but lost.
+        a.send_frame(b"four").unwrap();
+        let (x, y) = UnixStream::pair().unwrap();
+        let resumed = std::thread::spawn(move || {
+            let (w, r) = parts(y);
+            b.resume(w, r).unwrap();
+            b
+        });
+        let (w, r) = parts(x);
+        a.resume(w, r).unwrap();
+        let mut b = resumed.join().unwrap();
+
+        // b's resume said it had seen 1..=3, so only "four" arrives again.
+        assert_eq!(a.unacked(), 1);
+        let n = b.recv_frame(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"four");
+    }
+
+    #[test]
+    fn test_new_peer_session_is_rejected() {
+        let (mut a, _b) = pair(ReliableConfig::default());
+        let (x, y) = UnixStream::pair().unwrap();
+        let stranger = std::thread::spawn(move || {
+            let (w, r) = parts(y);
+            Reliable::connect(w, r, ReliableConfig::default())
+        });
+        let (w, r) = parts(x);
+        assert_eq!(a.resume(w, r).unwrap_err().code, AbutCode::Protocol);
+        let _ = stranger.join();
+    }
+
+    #[test]
+    fn test_bad_acks_and_config_are_rejected() {
+        let cfg = ReliableConfig { ack_every: 4, window: QueueLimits { max_frames: 2, ..Default::default() }, ..Default::default() };
+        let (x, _y) = UnixStream::pair().unwrap();
+        let (w, r) = parts(x);
+        assert_eq!(Reliable::connect(w, r, cfg).err().unwrap().code, AbutCode::Io);
+
+        // A raw peer answering the handshake with "expects seq 0".
+        let (x, y) = UnixStream::pair().unwrap();
+        let peer = std::thread::spawn(move || {
+            let (mut w, mut r) = parts(y);
+            let mut buf = [0u8; 32];
+            r.recv_frame(&mut buf).unwrap();
+            let mut resume = [0u8; 17];
+            resume[0] = TAG_RESUME;
+            w.send_frame(&resume).unwrap();
+        });
+        let (w, r) = parts(x);
+        assert_eq!(Reliable::connect(w, r, ReliableConfig::default()).err().unwrap().code, AbutCode::Protocol);
+        peer.join().unwrap();
+
+        // An ack for a frame never sent.
+        let (mut a, mut b) = pair(ReliableConfig::default());
+        a.send_frame(b"one").unwrap();
+        let mut ack = [0u8; HEADER_LEN];
+        ack[0] = TAG_ACK;
+        ack[1..].copy_from_slice(&2u64.to_le_bytes());
+        b.sink_mut().send_frame(&ack).unwrap();
+        assert_eq!(a.pump().unwrap_err().code, AbutCode::Protocol);
+    }
+
+    #[test]
+    fn test_full_byte_window_requests_an_ack() {
+        // 1 KiB frames fill the byte window after 4, long before ack_every.
+        let cfg = ReliableConfig { window: QueueLimits { max_frames: 64, max_bytes: 4096 }, ack_every: 16, ..Default::default() };
+        let (mut a, mut b) = pair(cfg);
+        let sender = std::thread::spawn(move || {
+            for i in 0..10u8 {
+                a.send_frame(&[i; 1024]).unwrap();
+            }
+            a
+        });
+        let mut buf = vec![0u8; 2048];
+        for i in 0..10u8 {
+            let n = b.recv_frame(&mut buf).unwrap();
+            assert_eq!(&buf[..n], &[i; 1024][..]);
+        }
+        sender.join().unwrap();
+    }
+
+    #[test]
+    fn test_unread_backlog_bounds_a_blocked_send() {
+        let small = ReliableConfig { window: QueueLimits { max_frames: 2, ..Default::default() }, ack_every: 1, ..Default::default() };
+        let (a, b) = UnixStream::pair().unwrap();
+        let peer = std::thread::spawn(move || {
+            let (w, r) = parts(b);
+            Reliable::connect(w, r, ReliableConfig::default()).unwrap()
+        });
+        let (w, r) = parts(a);
+        let mut a = Reliable::connect(w, r, small).unwrap();
+        let mut b = peer.join().unwrap();
+
+        // Neither side reads: a's window fills while b's frames pile up.
+        a.send_frame(b"1").unwrap();
+        a.send_frame(b"2").unwrap();
+        for _ in 0..3 {
+            b.send_frame(b"unread").unwrap();
+        }
+        assert_eq!(a.send_frame(b"3").unwrap_err().code, AbutCode::QueueFull);
+        assert_eq!(a.unacked(), 2);
+
+        let mut buf = [0u8; 16];
+        assert_eq!(a.recv_frame(&mut buf).unwrap(), 6);
+    }
+}
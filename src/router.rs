@@ -0,0 +1,360 @@
+//! Dispatch of typed messages to per-type handlers.
+//!
+//! Every routed frame starts with the message's [`Message::ID`] as a `u16`
+//! (little-endian), followed by the value encoded with the connection's
+//! [`Codec`]. A [`Router`] decodes the ID, hands the value to the handler
+//! registered for it together with the caller's connection state, and turns
+//! the handler's reply, if any, into a frame for the same connection.
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::marker::PhantomData;
+
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::frame::{FramedReader, FramedWriter};
+use crate::server::Connection;
+use crate::{AbutError, Codec, DecodeLimits};
+
+/// Length of the message ID in front of every routed frame.
+pub const ID_LEN: usize = 2;
+
+/// Reserved ID of the reply sent under [`UnknownPolicy::Reply`]; its payload
+/// is the unrecognised ID as a `u16` (little-endian), whatever the codec.
+pub const UNKNOWN_ID: u16 = u16::MAX;
+
+/// A type that travels as a routed frame.
+///
+/// With the `derive` feature, `#[derive(Message)]` and `#[message(id = ..)]`
+/// implement it, and `#[protocol]` declares a whole set of messages at once.
+pub trait Message: Serialize + DeserializeOwned {
+    /// Wire identifier; unique within a protocol. [`UNKNOWN_ID`] is reserved.
+    const ID: u16;
+
+    /// Revision of the type's shape, bumped when it changes incompatibly.
+    const VERSION: u16 = 1;
+}
+
+#[cfg(feature = "derive")]
+pub use abut_derive::{Message, protocol};
+
+/// Fails const evaluation if two IDs in `ids` are equal; used by the
+/// code generated for `#[protocol]` traits.
+#[doc(hidden)]
+pub const fn assert_unique_ids(ids: &[u16]) {
+    let mut i = 0;
+    while i < ids.len() {
+        let mut j = i + 1;
+        while j < ids.len() {
+            assert!(ids[i] != ids[j], "two messages of this protocol share an ID");
+            j += 1;
+        }
+        i += 1;
+    }
+}
+
+/// What a [`Router`] does with a frame whose ID has no handler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnknownPolicy {
+    /// Answer with an [`UNKNOWN_ID`] frame naming the ID and carry on. An
+    /// incoming [`UNKNOWN_ID`] frame is discarded instead, so two routers
+    /// that both reply cannot bounce it back and forth.
+    #[default]
+    Reply,
+    /// Discard the frame silently.
+    Ignore,
+    /// Fail with `AbutCode::Protocol`, ending the connection.
+    Disconnect,
+}
+
+/// Reads the message ID of a routed frame.
+pub fn message_id(frame: &[u8]) -> Option<u16> {
+    frame.first_chunk::<ID_LEN>().map(|id| u16::from_le_bytes(*id))
+}
+
+/// Appends `msg` to `buf` as a routed frame.
+pub fn encode<C: Codec, M: Message>(msg: &M, buf: &mut Vec<u8>) -> Result<(), AbutError> {
+    buf.extend_from_slice(&M::ID.to_le_bytes());
+    C::encode(msg, buf)
+}
+
+/// Decodes a routed frame as `M`, checking its ID first.
+pub fn decode<C: Codec, M: Message>(frame: &[u8], limits: &DecodeLimits) -> Result<M, AbutError> {
+    match message_id(frame) {
+        Some(id) if id == M::ID => C::decode(&frame[ID_LEN..], limits),
+        Some(id) => Err(AbutError::protocol(format_args!("expected message {}, got {id}", M::ID))),
+        None => Err(AbutError::protocol("routed frame shorter than its message ID")),
+    }
+}
+
+/// Decodes the payload of an [`UNKNOWN_ID`] reply: the ID the peer did not handle.
+pub fn unknown_id(frame: &[u8]) -> Option<u16> {
+    match message_id(frame)? {
+        UNKNOWN_ID => message_id(&frame[ID_LEN..]),
+        _ => None,
+    }
+}
+
+/// Decodes a payload, runs the handler and encodes its reply into the buffer,
+/// returning whether there was a reply.
+type Handler<X> = Box<dyn Fn(&mut X, &[u8], &DecodeLimits, &mut Vec<u8>) -> Result<bool, AbutError> + Send + Sync>;
+
+/// Handlers keyed by message ID, for codec `C` and per-connection state `X`.
+///
+/// A router is immutable once built and can be shared between the threads of
+/// a [`Server`](crate::server::Server) behind an `Arc`.
+pub struct Router<C, X = ()> {
+    handlers: HashMap<u16, Handler<X>>,
+    unknown: UnknownPolicy,
+    _codec: PhantomData<fn() -> C>,
+}
+
+impl<C: Codec + 'static, X: 'static> Default for Router<C, X> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: Codec + 'static, X: 'static> Router<C, X> {
+    pub fn new() -> Self {
+        Self { handlers: HashMap::new(), unknown: UnknownPolicy::default(), _codec: PhantomData }
+    }
+
+    pub fn unknown(mut self, policy: UnknownPolicy) -> Self {
+        self.unknown = policy;
+        self
+    }
+
+    fn insert(&mut self, id: u16, handler: Handler<X>) {
+        assert_ne!(id, UNKNOWN_ID, "message ID {UNKNOWN_ID:#06x} is reserved");
+        assert!(self.handlers.insert(id, handler).is_none(), "message ID {id} is routed twice");
+    }
+
+    /// Routes `M` to a handler that sends nothing back.
+    ///
+    /// # Panics
+    /// If `M::ID` already has a handler or is [`UNKNOWN_ID`].
+    pub fn on<M: Message>(mut self, handler: impl Fn(&mut X, M) -> Result<(), AbutError> + Send + Sync + 'static) -> Self {
+        self.insert(
+            M::ID,
+            Box::new(move |state, payload, limits, _| {
+                handler(state, C::decode(payload, limits)?)?;
+                Ok(false)
+            }),
+        );
+        self
+    }
+
+    /// Routes `M` to a handler whose reply, when it returns one, is sent back
+    /// on the same connection.
+    ///
+    /// # Panics
+    /// If `M::ID` already has a handler or is [`UNKNOWN_ID`].
+    pub fn on_request<M: Message, R: Message>(
+        mut self,
+        handler: impl Fn(&mut X, M) -> Result<Option<R>, AbutError> + Send + Sync + 'static,
+    ) -> Self {
+        self.insert(
+            M::ID,
+            Box::new(move |state, payload, limits, out| match handler(state, C::decode(payload, limits)?)? {
+                Some(reply) => encode::<C, R>(&reply, out).map(|()| true),
+                None => Ok(false),
+            }),
+        );
+        self
+    }
+
+    pub fn routes(&self, id: u16) -> bool {
+        self.handlers.contains_key(&id)
+    }
+
+    /// Dispatches one frame, leaving the reply frame (if any) in `reply`.
+    ///
+    /// Returns whether `reply` should be sent. Decode and handler errors are
+    /// returned as they are.
+    pub fn dispatch(&self, state: &mut X, frame: &[u8], limits: &DecodeLimits, reply: &mut Vec<u8>) -> Result<bool, AbutError> {
+        reply.clear();
+        let id = message_id(frame).ok_or_else(|| AbutError::protocol("routed frame shorter than its message ID"))?;
+        if let Some(handler) = self.handlers.get(&id) {
+            return handler(state, &frame[ID_LEN..], limits, reply);
+        }
+        match self.unknown {
+            UnknownPolicy::Reply if id == UNKNOWN_ID => Ok(false),
+            UnknownPolicy::Reply => {
+                reply.extend_from_slice(&UNKNOWN_ID.to_le_bytes());
+                reply.extend_from_slice(&id.to_le_bytes());
+                Ok(true)
+            }
+            UnknownPolicy::Ignore => Ok(false),
+            UnknownPolicy::Disconnect => Err(AbutError::protocol(format_args!("no handler for message {id}"))),
+        }
+    }
+
+    /// Dispatches frames from a server connection until the peer hangs up,
+    /// sending replies back on it.
+    pub fn serve(&self, conn: &mut Connection<C>, state: &mut X) -> Result<(), AbutError> {
+        let (mut frame, mut reply) = (Vec::new(), Vec::new());
+        while conn.reader_mut().try_recv_into(&mut frame)? {
+            let limits = conn.reader_mut().config().limits;
+            if self.dispatch(state, &frame, &limits, &mut reply)? {
+                conn.send_frame(&reply)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The calling side of a routed connection: sends messages and waits for
+/// the replies to requests.
+pub struct Caller<C, R: Read, W: Write> {
+    reader: FramedReader<R>,
+    writer: FramedWriter<W>,
+    buf: Vec<u8>,
+    _codec: PhantomData<fn() -> C>,
+}
+
+impl<C: Codec, R: Read, W: Write> Caller<C, R, W> {
+    pub fn new(reader: FramedReader<R>, writer: FramedWriter<W>) -> Self {
+        Self { reader, writer, buf: Vec::new(), _codec: PhantomData }
+    }
+
+    pub fn reader_mut(&mut self) -> &mut FramedReader<R> { &mut self.reader }
+    pub fn writer_mut(&mut self) -> &mut FramedWriter<W> { &mut self.writer }
+    pub fn into_parts(self) -> (FramedReader<R>, FramedWriter<W>) { (self.reader, self.writer) }
+
+    /// Sends `msg` and flushes, expecting no reply.
+    pub fn send<M: Message>(&mut self, msg: &M) -> Result<(), AbutError> {
+        self.buf.clear();
+        encode::<C, M>(msg, &mut self.buf)?;
+        self.writer.write_frame(&self.buf)?;
+        self.writer.flush()
+    }
+
+    /// Receives the next frame as `M`.
+    pub fn recv<M: Message>(&mut self) -> Result<M, AbutError> {
+        self.reader.recv_into(&mut self.buf)?;
+        if let Some(id) = unknown_id(&self.buf) {
+            return Err(AbutError::protocol(format_args!("peer has no handler for message {id}")));
+        }
+        decode::<C, M>(&self.buf, &self.reader.config().limits)
+    }
+
+    /// Sends a request and waits for its reply.
+    pub fn call<M: Message, Resp: Message>(&mut self, msg: &M) -> Result<Resp, AbutError> {
+        self.send(msg)?;
+        self.recv()
+    }
+}
+
+#[cfg(all(test, feature = "postcard"))]
+mod tests {
+    use super::*;
+    use crate::AbutCode;
+    use crate::frame::postcard::Postcard;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Add(u32, u32);
+    impl Message for Add {
+        const ID: u16 = 1;
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Sum(u32);
+    impl Message for Sum {
+        const ID: u16 = 2;
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Log(String);
+    impl Message for Log {
+        const ID: u16 = 3;
+    }
+
+    fn frame<M: Message>(msg: &M) -> Vec<u8> {
+        let mut buf = Vec::new();
+        encode::<Postcard, M>(msg, &mut buf).unwrap();
+        buf
+    }
+
+    fn router() -> Router<Postcard, Vec<String>> {
+        Router::new()
+            .on_request(|_, Add(a, b)| Ok(Some(Sum(a + b))))
+            .on(|log: &mut Vec<String>, Log(line)| {
+                log.push(line);
+                Ok(())
+            })
+    }
+
+    #[test]
+    fn test_dispatch_replies_and_state() {
+        let router = router();
+        let limits = DecodeLimits::default();
+        let (mut log, mut reply) = (Vec::new(), Vec::new());
+
+        assert!(router.dispatch(&mut log, &frame(&Add(2, 3)), &limits, &mut reply).unwrap());
+        assert_eq!(decode::<Postcard, Sum>(&reply, &limits).unwrap(), Sum(5));
+
+        assert!(!router.dispatch(&mut log, &frame(&Log("hi".into())), &limits, &mut reply).unwrap());
+        assert_eq!(log, ["hi"]);
+
+        let err = decode::<Postcard, Sum>(&frame(&Add(1, 1)), &limits).unwrap_err();
+        assert_eq!(err.code, AbutCode::Protocol);
+    }
+
+    #[test]
+    fn test_unknown_policies() {
+        let limits = DecodeLimits::default();
+        let unknown = frame(&Sum(1));
+        let mut reply = Vec::new();
+
+        assert!(router().dispatch(&mut Vec::new(), &unknown, &limits, &mut reply).unwrap());
+        assert_eq!(unknown_id(&reply), Some(Sum::ID));
+        // The peer's own UNKNOWN_ID reply is not answered.
+        let bounced = reply.clone();
+        assert!(!router().dispatch(&mut Vec::new(), &bounced, &limits, &mut reply).unwrap());
+        assert!(reply.is_empty());
+
+        let ignore = router().unknown(UnknownPolicy::Ignore);
+        assert!(!ignore.dispatch(&mut Vec::new(), &unknown, &limits, &mut reply).unwrap());
+
+        let strict = router().unknown(UnknownPolicy::Disconnect);
+        let err = strict.dispatch(&mut Vec::new(), &unknown, &limits, &mut reply).unwrap_err();
+        assert_eq!(err.code, AbutCode::Protocol);
+    }
+
+    #[test]
+    #[should_panic(expected = "routed twice")]
+    fn test_duplicate_route_panics() {
+        let _ = router().on(|_, _: Add| Ok(()));
+    }
+
+    #[test]
+    fn test_serve_over_server_connection() {
+        use crate::frame::{FramedReader, FramedWriter};
+        use crate::server::Server;
+        use std::os::unix::net::UnixStream;
+        use std::sync::Arc;
+
+        let path = std::env::temp_dir().join(format!("abut-router-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let server = Server::<Postcard>::bind(&path).unwrap();
+        let stopper = server.stopper().unwrap();
+        let router = Arc::new(router());
+        let running = std::thread::spawn(move || server.serve(move |conn| router.serve(conn, &mut Vec::new())));
+
+        let client = UnixStream::connect(&path).unwrap();
+        let mut w = FramedWriter::new(&client);
+        let mut r = FramedReader::new(&client);
+        let mut buf = Vec::new();
+        w.write_frame(&frame(&Add(20, 22))).unwrap();
+        r.recv_into(&mut buf).unwrap();
+        assert_eq!(decode::<Postcard, Sum>(&buf, &DecodeLimits::default()).unwrap(), Sum(42));
+
+        stopper.stop();
+        drop((w, r));
+        drop(client);
+        running.join().unwrap().unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,349 @@
+//! Schema fingerprints and the compatibility handshake.
+//!
+//! Non-self-describing formats such as postcard decode a changed struct into
+//! garbage rather than failing. Each message type therefore carries a
+//! [`Schema::FINGERPRINT`], a hash of its shape (field names and types,
+//! variant names, recursively) computed at compile time. At connection start
+//! both sides exchange the `(id, version, fingerprint)` of every message they
+//! speak with [`Schemas::handshake`], check them, and trade a one-byte
+//! verdict, so that if either side cannot decode what the peer will send
+//! both fail with `AbutCode::SchemaMismatch`.
+//!
+//! Serde attributes (`rename`, `skip`, ...) are not taken into account, and
+//! recursive types cannot have a fingerprint.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+
+use crate::router::Message;
+use crate::{AbutCode, AbutError, FrameSink, FrameSource};
+
+#[cfg(feature = "derive")]
+pub use abut_derive::Schema;
+
+/// Magic prefix of the handshake frame.
+pub const HELLO_MAGIC: &[u8; 4] = b"ABSC";
+
+/// Most entries a handshake frame may carry.
+pub const MAX_ENTRIES: usize = 1024;
+
+/// Verdict frame sent after checking the peer's entries.
+pub const VERDICT_OK: u8 = 0x00;
+/// Verdict frame of a side whose check failed.
+pub const VERDICT_MISMATCH: u8 = 0x01;
+
+const ENTRY_LEN: usize = 2 + 2 + 8;
+const HEADER_LEN: usize = HELLO_MAGIC.len() + 2;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+const fn fnv(mut hash: u64, bytes: &[u8]) -> u64 {
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+        i += 1;
+    }
+    hash
+}
+
+/// Fingerprint of a name (field, variant or primitive type).
+pub const fn name(name: &str) -> u64 {
+    fnv(FNV_OFFSET, name.as_bytes())
+}
+
+/// Fingerprint of a compound shape of kind `kind` made of `parts`, in order.
+pub const fn shape(kind: &str, parts: &[u64]) -> u64 {
+    let mut hash = name(kind);
+    let mut i = 0;
+    while i < parts.len() {
+        hash = fnv(hash, &parts[i].to_le_bytes());
+        i += 1;
+    }
+    hash
+}
+
+/// A type with a known wire shape.
+///
+/// With the `derive` feature, `#[derive(Schema)]` implements it for structs
+/// and enums whose fields implement it.
+pub trait Schema {
+    const FINGERPRINT: u64;
+}
+
+macro_rules! primitive {
+    ($($ty:ty),*) => {
+        $(impl Schema for $ty {
+            const FINGERPRINT: u64 = name(stringify!($ty));
+        })*
+    };
+}
+
+primitive!(bool, u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64, char, String, ());
+
+impl<T: Schema> Schema for Option<T> {
+    const FINGERPRINT: u64 = shape("option", &[T::FINGERPRINT]);
+}
+
+impl<T: Schema> Schema for Box<T> {
+    const FINGERPRINT: u64 = T::FINGERPRINT;
+}
+
+impl<T: Schema, const N: usize> Schema for [T; N] {
+    const FINGERPRINT: u64 = shape("array", &[T::FINGERPRINT, N as u64]);
+}
+
+macro_rules! seq {
+    ($($ty:ident),*) => {
+        $(impl<T: Schema> Schema for $ty<T> {
+            const FINGERPRINT: u64 = shape("seq", &[T::FINGERPRINT]);
+        })*
+    };
+}
+
+seq!(Vec, VecDeque, BTreeSet, HashSet);
+
+impl<K: Schema, V: Schema> Schema for BTreeMap<K, V> {
+    const FINGERPRINT: u64 = shape("map", &[K::FINGERPRINT, V::FINGERPRINT]);
+}
+
+impl<K: Schema, V: Schema> Schema for HashMap<K, V> {
+    const FINGERPRINT: u64 = shape("map", &[K::FINGERPRINT, V::FINGERPRINT]);
+}
+
+macro_rules! tuple {
+    ($($name:ident)+) => {
+        impl<$($name: Schema),+> Schema for ($($name,)+) {
+            const FINGERPRINT: u64 = shape("tuple", &[$($name::FINGERPRINT),+]);
+        }
+    };
+}
+
+tuple!(A);
+tuple!(A B);
+tuple!(A B C);
+tuple!(A B C D);
+tuple!(A B C D E);
+tuple!(A B C D E F);
+
+/// What one side says about one of its message types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SchemaEntry {
+    pub id: u16,
+    pub version: u16,
+    pub fingerprint: u64,
+}
+
+impl SchemaEntry {
+    pub fn of<M: Message + Schema>() -> Self {
+        Self { id: M::ID, version: M::VERSION, fingerprint: M::FINGERPRINT }
+    }
+}
+
+/// The message types one side speaks, and the peer versions it accepts.
+#[derive(Debug, Clone, Default)]
+pub struct Schemas {
+    local: BTreeMap<u16, SchemaEntry>,
+    compatible: BTreeMap<u16, BTreeSet<u16>>,
+}
+
+impl Schemas {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `M`, replacing any entry with the same ID.
+    pub fn with<M: Message + Schema>(mut self) -> Self {
+        self.local.insert(M::ID, SchemaEntry::of::<M>());
+        self
+    }
+
+    /// Declares that the peer's `versions` of `M` decode correctly as the
+    /// local `M`, whatever their fingerprints.
+    pub fn compatible<M: Message>(mut self, versions: impl IntoIterator<Item = u16>) -> Self {
+        self.compatible.entry(M::ID).or_default().extend(versions);
+        self
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &SchemaEntry> {
+        self.local.values()
+    }
+
+    /// Checks the peer's entries against ours.
+    ///
+    /// A message both sides speak is compatible when the fingerprints match or
+    /// the peer's version was declared [`compatible`](Self::compatible).
+    /// Messages only one side speaks are left to the router's unknown policy.
+    pub fn check(&self, peer: &[SchemaEntry]) -> Result<(), AbutError> {
+        for theirs in peer {
+            let Some(ours) = self.local.get(&theirs.id) else { continue };
+            if ours.fingerprint == theirs.fingerprint
+                || self.compatible.get(&theirs.id).is_some_and(|v| v.contains(&theirs.version))
+            {
+                continue;
+            }
+            let hint = if ours.version == theirs.version { "; the shape changed without a version bump" } else { "" };
+            return Err(AbutError::new(AbutCode::SchemaMismatch).ctx(format_args!(
+                "message {}: local v{} ({:016x}), peer v{} ({:016x}){hint}",
+                theirs.id, ours.version, ours.fingerprint, theirs.version, theirs.fingerprint
+            )));
+        }
+        Ok(())
+    }
+
+    /// Encodes the handshake frame.
+    pub fn encode(&self) -> Result<Vec<u8>, AbutError> {
+        if self.local.len() > MAX_ENTRIES {
+            return Err(AbutError::protocol(format_args!("{} schema entries, at most {MAX_ENTRIES}", self.local.len())));
+        }
+        let mut frame = Vec::with_capacity(HEADER_LEN + ENTRY_LEN * self.local.len());
+        frame.extend_from_slice(HELLO_MAGIC);
+        frame.extend_from_slice(&(self.local.len() as u16).to_le_bytes());
+        for e in self.local.values() {
+            frame.extend_from_slice(&e.id.to_le_bytes());
+            frame.extend_from_slice(&e.version.to_le_bytes());
+            frame.extend_from_slice(&e.fingerprint.to_le_bytes());
+        }
+        Ok(frame)
+    }
+
+    /// Decodes a handshake frame.
+    pub fn decode(frame: &[u8]) -> Result<Vec<SchemaEntry>, AbutError> {
+        let body = frame
+            .strip_prefix(HELLO_MAGIC)
+            .ok_or_else(|| AbutError::protocol("expected a schema handshake frame"))?;
+        let (count, body) = body.split_first_chunk::<2>().ok_or_else(|| AbutError::protocol("schema handshake truncated"))?;
+        let count = u16::from_le_bytes(*count) as usize;
+        if body.len() != count * ENTRY_LEN {
+            return Err(AbutError::protocol(format_args!("schema handshake of {} bytes for {count} entries", body.len())));
+        }
+        Ok(body
+            .chunks_exact(ENTRY_LEN)
+            .map(|e| SchemaEntry {
+                id: u16::from_le_bytes([e[0], e[1]]),
+                version: u16::from_le_bytes([e[2], e[3]]),
+                fingerprint: u64::from_le_bytes(e[4..].try_into().expect("8 bytes")),
+            })
+            .collect())
+    }
+
+    /// Sends our entries, receives the peer's and checks them, then sends our
+    /// verdict and receives the peer's.
+    ///
+    /// Both ends must call it before any other frame. It fails on both ends
+    /// if either end's check fails; on success the peer's entries are
+    /// returned.
+    pub fn handshake<S, R>(&self, sink: &mut S, source: &mut R) -> Result<Vec<SchemaEntry>, AbutError>
+    where
+        S: FrameSink<Error = AbutError>,
+        R: FrameSource<Error = AbutError>,
+    {
+        sink.send_frame(&self.encode()?)?;
+        let mut buf = vec![0u8; HEADER_LEN + ENTRY_LEN * MAX_ENTRIES];
+        let n = source.recv_frame(&mut buf)?;
+        let peer = Self::decode(&buf[..n])?;
+        if let Err(e) = self.check(&peer) {
+            // The peer may have failed too and hung up; our mismatch is the error either way.
+            let _ = sink.send_frame(&[VERDICT_MISMATCH]);
+            return Err(e);
+        }
+        // A rejecting peer may hang up before our verdict arrives; its own
+        // verdict is still waiting to be read.
+        let sent = sink.send_frame(&[VERDICT_OK]);
+        let mut verdict = [0u8; 1];
+        match source.recv_frame(&mut verdict) {
+            Ok(1) if verdict[0] == VERDICT_MISMATCH => {
+                Err(AbutError::new(AbutCode::SchemaMismatch).ctx("the peer cannot decode our messages"))
+            }
+            Ok(1) if verdict[0] == VERDICT_OK => sent.map(|()| peer),
+            Ok(_) => Err(AbutError::protocol(format_args!("unknown schema verdict {:#04x}", verdict[0]))),
+            Err(e) => Err(sent.err().unwrap_or(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::{FramedReader, FramedWriter};
+    use serde::{Deserialize, Serialize};
+    use std::os::unix::net::UnixStream;
+
+    #[derive(Serialize, Deserialize)]
+    struct PingV1 {
+        seq: u32,
+    }
+
+    impl Schema for PingV1 {
+        const FINGERPRINT: u64 = shape("struct", &[name("seq"), u32::FINGERPRINT]);
+    }
+
+    impl Message for PingV1 {
+        const ID: u16 = 1;
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct PingV2 {
+        seq: u64,
+    }
+
+    impl Schema for PingV2 {
+        const FINGERPRINT: u64 = shape("struct", &[name("seq"), u64::FINGERPRINT]);
+    }
+
+    impl Message for PingV2 {
+        const ID: u16 = 1;
+        const VERSION: u16 = 2;
+    }
+
+    #[test]
+    fn test_fingerprints_follow_shape() {
+        assert_ne!(PingV1::FINGERPRINT, PingV2::FINGERPRINT);
+        assert_ne!(<Vec<u8>>::FINGERPRINT, <Option<u8>>::FINGERPRINT);
+        assert_ne!(<(u8, u16)>::FINGERPRINT, <(u16, u8)>::FINGERPRINT);
+        assert_eq!(<Box<u32>>::FINGERPRINT, u32::FINGERPRINT);
+        assert_eq!(<VecDeque<u8>>::FINGERPRINT, <Vec<u8>>::FINGERPRINT);
+    }
+
+    #[test]
+    fn test_check_and_declared_compatibility() {
+        let v1 = Schemas::new().with::<PingV1>();
+        let v2 = Schemas::new().with::<PingV2>();
+        let peer_v1: Vec<_> = v1.entries().copied().collect();
+
+        let err = v2.check(&peer_v1).unwrap_err();
+        assert_eq!(err.code, AbutCode::SchemaMismatch);
+        assert!(v1.check(&peer_v1).is_ok());
+        assert!(v2.compatible::<PingV2>([1]).check(&peer_v1).is_ok());
+    }
+
+    #[test]
+    fn test_handshake_over_stream() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let peer = std::thread::spawn(move || {
+            Schemas::new().with::<PingV1>().handshake(&mut FramedWriter::new(&b), &mut FramedReader::new(&b))
+        });
+        let ours = Schemas::new().with::<PingV2>();
+        let err = ours.handshake(&mut FramedWriter::new(&a), &mut FramedReader::new(&a)).unwrap_err();
+        assert_eq!(err.code, AbutCode::SchemaMismatch);
+        assert_eq!(peer.join().unwrap().unwrap_err().code, AbutCode::SchemaMismatch);
+
+        // Only our side accepts the old version: the peer's rejection still fails us.
+        let (a, b) = UnixStream::pair().unwrap();
+        let peer = std::thread::spawn(move || {
+            Schemas::new().with::<PingV1>().handshake(&mut FramedWriter::new(&b), &mut FramedReader::new(&b))
+        });
+        let ours = Schemas::new().with::<PingV2>().compatible::<PingV2>([1]);
+        let err = ours.handshake(&mut FramedWriter::new(&a), &mut FramedReader::new(&a)).unwrap_err();
+        assert_eq!(err.code, AbutCode::SchemaMismatch);
+        assert_eq!(peer.join().unwrap().unwrap_err().code, AbutCode::SchemaMismatch);
+
+        let (a, b) = UnixStream::pair().unwrap();
+        let peer = std::thread::spawn(move || {
+            Schemas::new().with::<PingV1>().handshake(&mut FramedWriter::new(&b), &mut FramedReader::new(&b))
+        });
+        let ours = Schemas::new().with::<PingV1>();
+        assert_eq!(ours.handshake(&mut FramedWriter::new(&a), &mut FramedReader::new(&a)).unwrap().len(), 1);
+        assert_eq!(peer.join().unwrap().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,494 @@
+//! A multi-connection framed server over a Unix listener.
+//!
+//! [`Server`] accepts connections, checks each peer against its
+//! [`Admission`] policy and the connection limit, then runs the handler on a
+//! dedicated thread with a typed [`Connection`]. Live connections are tracked
+//! in a [`Registry`] that can enumerate, message and disconnect them.
+
+use std::collections::HashMap;
+use std::io;
+use std::marker::PhantomData;
+use std::net::Shutdown;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::frame::{FramedReader, FramedWriter, Messages};
+use crate::peer::AllowAll;
+use crate::{AbutCode, AbutError, Admission, Codec, MessageSource, PeerCred, ReaderConfig};
+
+/// Identifies a connection for the lifetime of its [`Server`].
+pub type ConnId = u64;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ServerConfig {
+    /// Connections beyond this many are closed straight after `accept`.
+    pub max_connections: usize,
+
+    /// Framing limits and deadlines for every connection's reader.
+    pub reader: ReaderConfig,
+
+    /// Send timeout for every connection's writer.
+    pub send_timeout: Option<Duration>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self { max_connections: 64, reader: ReaderConfig::default(), send_timeout: None }
+    }
+}
+
+type SharedWriter = Arc<Mutex<FramedWriter<UnixStream>>>;
+
+fn lock(writer: &SharedWriter) -> MutexGuard<'_, FramedWriter<UnixStream>> {
+    writer.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+pub struct Server<C> {
+    listener: UnixListener,
+    cfg: ServerConfig,
+    admission: Arc<dyn Admission>,
+    registry: Registry<C>,
+    stop: Arc<AtomicBool>,
+}
+
+impl<C: Codec + 'static> Server<C> {
+    /// Binds a new listener at `path`, which must not exist yet.
+    pub fn bind(path: impl AsRef<Path>) -> Result<Self, AbutError> {
+        Ok(Self::new(UnixListener::bind(path)?))
+    }
+
+    pub fn new(listener: UnixListener) -> Self {
+        Self::with_config(listener, ServerConfig::default())
+    }
+
+    pub fn with_config(listener: UnixListener, cfg: ServerConfig) -> Self {
+        Self {
+            listener,
+            cfg,
+            admission: Arc::new(AllowAll),
+            registry: Registry::new(),
+            stop: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Replaces the admission policy (by default every peer is admitted).
+    pub fn admission(mut self, admission: impl Admission + 'static) -> Self {
+        self.admission = Arc::new(admission);
+        self
+    }
+
+    pub fn config(&self) -> ServerConfig { self.cfg }
+    pub fn listener(&self) -> &UnixListener { &self.listener }
+    pub fn registry(&self) -> Registry<C> { self.registry.clone() }
+
+    /// Returns a handle that makes [`serve`](Self::serve) stop accepting.
+    pub fn stopper(&self) -> Result<Stopper, AbutError> {
+        let addr = self.listener.local_addr()?;
+        let path = addr
+            .as_pathname()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "listener has no path"))?
+            .to_owned();
+        Ok(Stopper { stop: self.stop.clone(), path })
+    }
+
+    /// Accepts connections until stopped, running `handler` for each on its own thread.
+    ///
+    /// A connection is unregistered and shut down when its handler returns.
+    /// Errors accepting or setting up one connection drop that connection
+    /// only (backing off briefly when out of fds); `serve` fails only when
+    /// the listener itself is unusable. Either way it waits for all running
+    /// handlers before returning.
+    pub fn serve<H>(&self, handler: H) -> Result<(), AbutError>
+    where
+        H: Fn(&mut Connection<C>) -> Result<(), AbutError> + Send + Sync + 'static,
+    {
+        let handler = Arc::new(handler);
+        let mut workers: Vec<JoinHandle<()>> = Vec::new();
+        let result = self.accept_loop(&handler, &mut workers);
+        for worker in workers {
+            let _ = worker.join();
+        }
+        result
+    }
+
+    fn accept_loop<H>(&self, handler: &Arc<H>, workers: &mut Vec<JoinHandle<()>>) -> Result<(), AbutError>
+    where
+        H: Fn(&mut Connection<C>) -> Result<(), AbutError> + Send + Sync + 'static,
+    {
+        for incoming in self.listener.incoming() {
+            if self.stop.load(Ordering::Acquire) {
+                break;
+            }
+            let stream = match incoming {
+                Ok(stream) => stream,
+                Err(e) if exhausted(&e) => {
+                    std::thread::sleep(ACCEPT_BACKOFF);
+                    continue;
+                }
+                Err(e) if per_connection(&e) => continue,
+                Err(e) => return Err(e.into()),
+            };
+            workers.retain(|w| !w.is_finished());
+
+            let Ok(peer) = PeerCred::of(&stream) else { continue };
+            if !self.admission.admit(&peer) || self.registry.len() >= self.cfg.max_connections {
+                continue;
+            }
+
+            let Ok(mut conn) = self.registry.register(stream, peer, &self.cfg) else { continue };
+            let handler = handler.clone();
+            let registry = self.registry.clone();
+            workers.push(std::thread::spawn(move || {
+                // Handler errors end this connection only.
+                let _ = handler(&mut conn);
+                registry.disconnect(conn.id);
+            }));
+        }
+        Ok(())
+    }
+}
+
+/// Pause after `accept` runs out of fds or memory, so that finishing
+/// handlers can free some before the next attempt.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(50);
+
+fn exhausted(e: &io::Error) -> bool {
+    matches!(e.raw_os_error(), Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM))
+}
+
+/// Whether an `accept` error concerns one incoming connection rather than the listener.
+fn per_connection(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::Interrupted | io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionReset)
+        || matches!(e.raw_os_error(), Some(libc::EPROTO | libc::EPERM))
+}
+
+/// Stops a running [`Server::serve`] loop; live connections are left alone.
+#[derive(Debug, Clone)]
+pub struct Stopper {
+    stop: Arc<AtomicBool>,
+    path: std::path::PathBuf,
+}
+
+impl Stopper {
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Release);
+        // Wake the blocked accept() so it observes the flag.
+        let _ = UnixStream::connect(&self.path);
+    }
+}
+
+/// One accepted connection, handed to the server's handler.
+pub struct Connection<C> {
+    id: ConnId,
+    peer: PeerCred,
+    reader: FramedReader<UnixStream>,
+    writer: SharedWriter,
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    _codec: PhantomData<fn() -> C>,
+}
+
+impl<C: Codec> Connection<C> {
+    pub fn id(&self) -> ConnId { self.id }
+    pub fn peer(&self) -> PeerCred { self.peer }
+    pub fn reader_mut(&mut self) -> &mut FramedReader<UnixStream> { &mut self.reader }
+
+    pub fn recv<T: DeserializeOwned>(&mut self) -> Result<T, AbutError> {
+        self.reader.recv_into(&mut self.buf)?;
+        C::decode(&self.buf, &self.reader.config().limits)
+    }
+
+    /// Like [`recv`](Self::recv), but returns `Ok(None)` once the peer hangs up cleanly.
+    pub fn try_recv<T: DeserializeOwned>(&mut self) -> Result<Option<T>, AbutError> {
+        if !self.reader.try_recv_into(&mut self.buf)? {
+            return Ok(None);
+        }
+        C::decode(&self.buf, &self.reader.config().limits).map(Some)
+    }
+
+    pub fn messages<T: DeserializeOwned>(&mut self) -> Messages<'_, Self, T> {
+        Messages::new(self)
+    }
+
+    pub fn send<T: Serialize>(&mut self, value: &T) -> Result<(), AbutError> {
+        self.out.clear();
+        C::encode(value, &mut self.out)?;
+        lock(&self.writer).write_frame(&self.out)
+    }
+
+    /// Receives one raw frame, bypassing the codec.
+    pub fn recv_frame(&mut self, dst: &mut Vec<u8>) -> Result<(), AbutError> {
+        self.reader.recv_into(dst)
+    }
+
+    /// Sends one raw frame, bypassing the codec.
+    pub fn send_frame(&mut self, bytes: &[u8]) -> Result<(), AbutError> {
+        lock(&self.writer).write_frame(bytes)
+    }
+}
+
+impl<C: Codec> MessageSource for Connection<C> {
+    fn try_recv<T: DeserializeOwned>(&mut self) -> Result<Option<T>, AbutError> {
+        Connection::try_recv(self)
+    }
+}
+
+struct Entry {
+    peer: PeerCred,
+    stream: UnixStream,
+    writer: SharedWriter,
+}
+
+/// Live connections of a [`Server`]. Cheap to clone; all clones share state.
+pub struct Registry<C> {
+    conns: Arc<Mutex<HashMap<ConnId, Entry>>>,
+    next_id: Arc<AtomicU64>,
+    _codec: PhantomData<fn() -> C>,
+}
+
+impl<C> Clone for Registry<C> {
+    fn clone(&self) -> Self {
+        Self { conns: self.conns.clone(), next_id: self.next_id.clone(), _codec: PhantomData }
+    }
+}
+
+impl<C: Codec> Registry<C> {
+    fn new() -> Self {
+        Self { conns: Arc::default(), next_id: Arc::new(AtomicU64::new(1)), _codec: PhantomData }
+    }
+
+    fn conns(&self) -> MutexGuard<'_, HashMap<ConnId, Entry>> {
+        self.conns.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn register(&self, stream: UnixStream, peer: PeerCred, cfg: &ServerConfig) -> Result<Connection<C>, AbutError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let write_half = stream.try_clone()?;
+        let writer = match cfg.send_timeout {
+            Some(timeout) => FramedWriter::with_send_timeout(write_half, timeout),
+            None => FramedWriter::new(write_half),
+        };
+        let writer = Arc::new(Mutex::new(writer));
+        let reader = FramedReader::with_deadlines(stream.try_clone()?, cfg.reader);
+
+        self.conns().insert(id, Entry { peer, stream, writer: writer.clone() });
+        Ok(Connection { id, peer, reader, writer, buf: Vec::new(), out: Vec::new(), _codec: PhantomData })
+    }
+
+    pub fn len(&self) -> usize { self.conns().len() }
+    pub fn is_empty(&self) -> bool { self.conns().is_empty() }
+
+    /// Snapshot of the live connections.
+    pub fn peers(&self) -> Vec<(ConnId, PeerCred)> {
+        let mut peers: Vec<_> = self.conns().iter().map(|(&id, e)| (id, e.peer)).collect();
+        peers.sort_by_key(|&(id, _)| id);
+        peers
+    }
+
+    fn writer(&self, id: ConnId) -> Result<SharedWriter, AbutError> {
+        self.conns()
+            .get(&id)
+            .map(|e| e.writer.clone())
+            .ok_or_else(|| AbutError::new(AbutCode::Closed).ctx(format_args!("no live connection {id}")))
+    }
+
+    /// Sends a raw frame to connection `id`.
+    pub fn send_frame(&self, id: ConnId, bytes: &[u8]) -> Result<(), AbutError> {
+        lock(&self.writer(id)?).write_frame(bytes)
+    }
+
+    pub fn send<T: Serialize>(&self, id: ConnId, value: &T) -> Result<(), AbutError> {
+        let mut out = Vec::new();
+        C::encode(value, &mut out)?;
+        self.send_frame(id, &out)
+    }
+
+    /// Sends `value` to every live connection, returning how many sends succeeded.
+    pub fn broadcast<T: Serialize>(&self, value: &T) -> Result<usize, AbutError> {
+        let mut out = Vec::new();
+        C::encode(value, &mut out)?;
+        let writers: Vec<_> = self.conns().values().map(|e| e.writer.clone()).collect();
+        Ok(writers.iter().filter(|w| lock(w).write_frame(&out).is_ok()).count())
+    }
+
+    /// Forcibly severs connection `id`. Returns false if it was not live.
+    ///
+    /// The handler's pending read fails and its thread winds down.
+    pub fn disconnect(&self, id: ConnId) -> bool {
+        match self.conns().remove(&id) {
+            Some(entry) => {
+                let _ = entry.stream.shutdown(Shutdown::Both);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn disconnect_all(&self) {
+        for (_, entry) in self.conns().drain() {
+            let _ = entry.stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::time::Instant;
+
+    /// Raw-frame codec so the server can be exercised without a serde format feature.
+    struct Raw;
+
+    impl Codec for Raw {
+        fn encode<T: Serialize>(_: &T, _: &mut Vec<u8>) -> Result<(), AbutError> {
+            Err(AbutError::protocol("raw codec"))
+        }
+        fn decode<T: DeserializeOwned>(_: &[u8], _: &crate::DecodeLimits) -> Result<T, AbutError> {
+            Err(AbutError::protocol("raw codec"))
+        }
+    }
+
+    fn socket_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("abut-server-{}-{name}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn wait_for(mut cond: impl FnMut() -> bool) {
+        let start = Instant::now();
+        while !cond() {
+            assert!(start.elapsed() < Duration::from_secs(5), "condition not reached");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn echo(conn: &mut Connection<Raw>) -> Result<(), AbutError> {
+        let mut buf = Vec::new();
+        while conn.reader_mut().try_recv_into(&mut buf)? {
+            conn.send_frame(&buf)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_echo_registry_and_disconnect() {
+        let path = socket_path("echo");
+        let server = Server::<Raw>::bind(&path).unwrap();
+        let registry = server.registry();
+        let stopper = server.stopper().unwrap();
+        let running = std::thread::spawn(move || server.serve(echo));
+
+        let client = UnixStream::connect(&path).unwrap();
+        let mut w = FramedWriter::new(&client);
+        let mut r = FramedReader::new(&client);
+        let mut buf = Vec::new();
+
+        w.write_frame(b"ping").unwrap();
+        r.recv_into(&mut buf).unwrap();
+        assert_eq!(buf, b"ping");
+
+        let peers = registry.peers();
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].1, PeerCred::current());
+
+        registry.send_frame(peers[0].0, b"from registry").unwrap();
+        r.recv_into(&mut buf).unwrap();
+        assert_eq!(buf, b"from registry");
+
+        assert!(registry.disconnect(peers[0].0));
+        assert_eq!(r.recv_into(&mut buf).unwrap_err().code, AbutCode::Closed);
+        assert!(registry.is_empty());
+
+        stopper.stop();
+        running.join().unwrap().unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_accept_errors_only_end_the_listener_when_it_is_broken() {
+        let os = io::Error::from_raw_os_error;
+        assert!(exhausted(&os(libc::EMFILE)) && exhausted(&os(libc::ENFILE)));
+        assert!(per_connection(&os(libc::ECONNABORTED)) && per_connection(&os(libc::EINTR)));
+        assert!(!exhausted(&os(libc::EBADF)) && !per_connection(&os(libc::EBADF)));
+    }
+
+    #[test]
+    fn test_connection_limit_and_admission() {
+        let path = socket_path("limit");
+        let cfg = ServerConfig { max_connections: 1, ..Default::default() };
+        let server = Server::<Raw>::with_config(UnixListener::bind(&path).unwrap(), cfg)
+            .admission(|peer: &PeerCred| peer.uid == PeerCred::current().uid);
+        let registry = server.registry();
+        let stopper = server.stopper().unwrap();
+        let running = std::thread::spawn(move || server.serve(echo));
+
+        let first = UnixStream::connect(&path).unwrap();
+        wait_for(|| registry.len() == 1);
+
+        let second = UnixStream::connect(&path).unwrap();
+        let mut buf = Vec::new();
+        let err = FramedReader::new(&second).recv_into(&mut buf).unwrap_err();
+        assert_eq!(err.code, AbutCode::Closed);
+        assert_eq!(registry.len(), 1);
+
+        drop(first);
+        wait_for(|| registry.is_empty());
+
+        stopper.stop();
+        running.join().unwrap().unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_rejected_peer_is_closed() {
+        let path = socket_path("reject");
+        let server = Server::<Raw>::bind(&path).unwrap().admission(|_: &PeerCred| false);
+        let stopper = server.stopper().unwrap();
+        let running = std::thread::spawn(move || server.serve(echo));
+
+        let client = UnixStream::connect(&path).unwrap();
+        let err = FramedReader::new(&client).recv_into(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.code, AbutCode::Closed);
+
+        stopper.stop();
+        running.join().unwrap().unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(feature = "postcard")]
+    #[test]
+    fn test_typed_postcard_connection() {
+        use crate::frame::postcard::{FramedPostcardReader, FramedPostcardWriter, Postcard};
+
+        let path = socket_path("typed");
+        let server = Server::<Postcard>::bind(&path).unwrap();
+        let stopper = server.stopper().unwrap();
+        let running = std::thread::spawn(move || {
+            server.serve(|conn| {
+                while let Some(n) = conn.try_recv::<u32>()? {
+                    conn.send(&(n * 2))?;
+                }
+                Ok(())
+            })
+        });
+
+        let client = UnixStream::connect(&path).unwrap();
+        let mut w = FramedPostcardWriter::new(&client);
+        let mut r = FramedPostcardReader::new(&client);
+        w.send(&21u32).unwrap();
+        assert_eq!(r.recv::<u32>().unwrap(), 42);
+
+        stopper.stop();
+        drop((w, r));
+        drop(client);
+        running.join().unwrap().unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,674 @@
+//! Shared-memory frame transport for high-rate, one-way traffic.
+//!
+//! A ring is a memfd holding a small header and a power-of-two data area,
+//! used by exactly one [`ShmSender`] and one [`ShmReceiver`]. Frames are
+//! stored as a 4-byte little-endian length followed by the payload, wrapping
+//! around the end of the area. Two eventfds wake a blocked side: one when data
+//! is published, one when space is freed. They are only written when the
+//! other side has announced that it is about to sleep, so a busy ring runs
+//! without syscalls.
+//!
+//! [`offer`] creates a ring and passes its fds over an existing Unix socket
+//! with `SCM_RIGHTS`; the peer picks it up with [`accept`]. That socket then
+//! doubles as a liveness signal: if it hangs up, a blocked side wakes.
+
+use std::io;
+use std::mem::size_of;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering, fence};
+use std::time::{Duration, Instant};
+
+use crate::{AbutError, FrameSink, FrameSource, ReaderConfig};
+
+const MAGIC: u64 = u64::from_le_bytes(*b"ABUTSHM1");
+const LEN_PREFIX: usize = 4;
+
+/// Smallest data area a ring is created with.
+pub const MIN_CAPACITY: usize = 64;
+
+#[repr(C, align(64))]
+struct Line<T>(T);
+
+/// Start of the mapping. Everything the peer can write is re-validated on read;
+/// the memfd is sealed against resizing, so the peer cannot pull the mapping
+/// out from under us either.
+#[repr(C)]
+struct Header {
+    magic: u64,
+    capacity: u64,
+    head: Line<AtomicU64>,
+    tail: Line<AtomicU64>,
+    producer_closed: AtomicU32,
+    consumer_closed: AtomicU32,
+    producer_waiting: AtomicU32,
+    consumer_waiting: AtomicU32,
+}
+
+const DATA_OFFSET: usize = size_of::<Header>();
+
+/// Seals every ring carries: its size is fixed and the seals themselves too.
+const SEALS: libc::c_int = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_SEAL;
+
+fn cvt(rc: libc::c_int) -> io::Result<libc::c_int> {
+    if rc < 0 { Err(io::Error::last_os_error()) } else { Ok(rc) }
+}
+
+/// A `MAP_SHARED` mapping of the whole ring.
+struct Region {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+// SAFETY: the mapping is plain memory; cross-side access goes through atomics.
+unsafe impl Send for Region {}
+
+impl Region {
+    fn map(fd: BorrowedFd<'_>, len: usize) -> io::Result<Self> {
+        // SAFETY: a fresh shared mapping of `len` bytes of `fd`; the result is checked.
+        let ptr = unsafe {
+            libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED, fd.as_raw_fd(), 0)
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { ptr: NonNull::new(ptr.cast()).expect("mmap returned null"), len })
+    }
+
+    fn header(&self) -> &Header {
+        // SAFETY: the mapping is at least DATA_OFFSET bytes and page aligned.
+        unsafe { &*self.ptr.as_ptr().cast::<Header>() }
+    }
+
+    fn data(&self) -> *mut u8 {
+        // SAFETY: DATA_OFFSET is within the mapping.
+        unsafe { self.ptr.as_ptr().add(DATA_OFFSET) }
+    }
+}
+
+impl Drop for Region {
+    fn drop(&mut self) {
+        // SAFETY: unmapping exactly what `map` mapped.
+        unsafe { libc::munmap(self.ptr.as_ptr().cast(), self.len) };
+    }
+}
+
+/// State shared by both ends' implementations.
+struct Ring {
+    region: Region,
+    cap: u64,
+    data_ready: OwnedFd,
+    space_ready: OwnedFd,
+    control: Option<UnixStream>,
+}
+
+enum Wake {
+    Signalled,
+    TimedOut,
+    HungUp,
+}
+
+impl Ring {
+    fn create(capacity: usize) -> io::Result<(Self, OwnedFd)> {
+        let cap = capacity.max(MIN_CAPACITY).next_power_of_two();
+        // SAFETY: plain syscalls; results are checked and wrapped as owned fds.
+        let (mem, data_ready, space_ready) = unsafe {
+            let flags = libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING;
+            let mem = OwnedFd::from_raw_fd(cvt(libc::memfd_create(c"abut-shm".as_ptr(), flags))?);
+            cvt(libc::ftruncate(mem.as_raw_fd(), (DATA_OFFSET + cap) as libc::off_t))?;
+            cvt(libc::fcntl(mem.as_raw_fd(), libc::F_ADD_SEALS, SEALS))?;
+            (mem, new_eventfd()?, new_eventfd()?)
+        };
+        let region = Region::map(mem.as_fd(), DATA_OFFSET + cap)?;
+        // SAFETY: nobody else has this mapping yet; a zeroed memfd is a valid Header.
+        unsafe {
+            let header = region.ptr.as_ptr().cast::<Header>();
+            (*header).magic = MAGIC;
+            (*header).capacity = cap as u64;
+        }
+        Ok((Self { region, cap: cap as u64, data_ready, space_ready, control: None }, mem))
+    }
+
+    fn open(mem: OwnedFd, data_ready: OwnedFd, space_ready: OwnedFd, capacity: u64) -> Result<Self, AbutError> {
+        if !capacity.is_power_of_two() || capacity < MIN_CAPACITY as u64 || capacity > u32::MAX as u64 {
+            return Err(AbutError::protocol(format_args!("shm ring capacity {capacity} is invalid")));
+        }
+        // A peer able to shrink the memfd could make our next access fault.
+        // SAFETY: F_GET_SEALS only reads the fd's seals.
+        let seals = cvt(unsafe { libc::fcntl(mem.as_raw_fd(), libc::F_GET_SEALS) })
+            .map_err(|e| AbutError::protocol(format_args!("shm ring is not a sealable memfd: {e}")))?;
+        if seals & SEALS != SEALS {
+            return Err(AbutError::protocol("shm ring is not sealed against resizing"));
+        }
+        // SAFETY: fstat only writes into `st`.
+        let mut st: libc::stat = unsafe { std::mem::zeroed() };
+        cvt(unsafe { libc::fstat(mem.as_raw_fd(), &mut st) })?;
+        let len = DATA_OFFSET + capacity as usize;
+        if (st.st_size as u64) < len as u64 {
+            return Err(AbutError::protocol(format_args!("shm ring is {} bytes, expected {len}", st.st_size)));
+        }
+        let region = Region::map(mem.as_fd(), len)?;
+        if region.header().magic != MAGIC || region.header().capacity != capacity {
+            return Err(AbutError::protocol("shm ring header does not match the offer"));
+        }
+        Ok(Self { region, cap: capacity, data_ready, space_ready, control: None })
+    }
+
+    fn header(&self) -> &Header { self.region.header() }
+
+    fn copy_in(&self, pos: u64, src: &[u8]) {
+        let at = (pos & (self.cap - 1)) as usize;
+        let first = src.len().min(self.cap as usize - at);
+        // SAFETY: both ranges lie within the data area because `at < cap` and
+        // the split keeps each part inside it.
+        unsafe {
+            std::ptr::copy_nonoverlapping(src.as_ptr(), self.region.data().add(at), first);
+            std::ptr::copy_nonoverlapping(src.as_ptr().add(first), self.region.data(), src.len() - first);
+        }
+    }
+
+    fn copy_out(&self, pos: u64, dst: &mut [u8]) {
+        let at = (pos & (self.cap - 1)) as usize;
+        let first = dst.len().min(self.cap as usize - at);
+        // SAFETY: as in `copy_in`.
+        unsafe {
+            std::ptr::copy_nonoverlapping(self.region.data().add(at), dst.as_mut_ptr(), first);
+            std::ptr::copy_nonoverlapping(self.region.data(), dst.as_mut_ptr().add(first), dst.len() - first);
+        }
+    }
+
+    /// Wakes the other side if it announced it is sleeping on `fd`.
+    fn notify(&self, waiting: &AtomicU32, fd: &OwnedFd) {
+        fence(Ordering::SeqCst);
+        if waiting.load(Ordering::Relaxed) != 0 {
+            signal(fd);
+        }
+    }
+
+    /// Sleeps until `fd` is signalled, the deadline passes or the control socket hangs up.
+    fn wait(&self, fd: &OwnedFd, deadline: Option<Instant>) -> Result<Wake, AbutError> {
+        let mut fds = [
+            libc::pollfd { fd: fd.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+            libc::pollfd { fd: self.control.as_ref().map_or(-1, |s| s.as_raw_fd()), events: libc::POLLRDHUP, revents: 0 },
+        ];
+        let timeout = match deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Ok(Wake::TimedOut);
+                }
+                remaining.as_millis().clamp(1, libc::c_int::MAX as u128) as libc::c_int
+            }
+            None => -1,
+        };
+        // SAFETY: `fds` is a valid array of two pollfds; a negative fd is ignored.
+        match unsafe { libc::poll(fds.as_mut_ptr(), 2, timeout) } {
+            0 => return Ok(Wake::TimedOut),
+            rc if rc < 0 => {
+                let err = io::Error::last_os_error();
+                return if err.kind() == io::ErrorKind::Interrupted { Ok(Wake::Signalled) } else { Err(err.into()) };
+            }
+            _ => {}
+        }
+        if fds[0].revents & libc::POLLIN != 0 {
+            let mut buf = [0u8; 8];
+            // SAFETY: reading 8 bytes into an 8-byte buffer; EAGAIN is fine.
+            unsafe { libc::read(fd.as_raw_fd(), buf.as_mut_ptr().cast(), 8) };
+        }
+        if fds[1].revents & (libc::POLLRDHUP | libc::POLLHUP | libc::POLLERR) != 0 {
+            return Ok(Wake::HungUp);
+        }
+        Ok(Wake::Signalled)
+    }
+}
+
+fn new_eventfd() -> io::Result<OwnedFd> {
+    // SAFETY: eventfd returns a new fd or -1.
+    let fd = cvt(unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) })?;
+    // SAFETY: `fd` was just created and is owned by nobody else.
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+fn signal(fd: &OwnedFd) {
+    let one = 1u64.to_ne_bytes();
+    // SAFETY: writing 8 bytes to an eventfd; a saturated counter (EAGAIN) still wakes.
+    unsafe { libc::write(fd.as_raw_fd(), one.as_ptr().cast(), 8) };
+}
+
+fn hung_up() -> AbutError {
+    io::Error::new(io::ErrorKind::ConnectionReset, "shm peer hung up without closing the ring").into()
+}
+
+/// Producing end of a ring.
+pub struct ShmSender {
+    ring: Ring,
+    head: u64,
+    send_timeout: Option<Duration>,
+}
+
+/// Consuming end of a ring.
+pub struct ShmReceiver {
+    ring: Ring,
+    tail: u64,
+    cfg: ReaderConfig,
+}
+
+/// Creates a ring for use within one process (e.g. between threads).
+pub fn channel(capacity: usize) -> Result<(ShmSender, ShmReceiver), AbutError> {
+    let (ring, mem) = Ring::create(capacity)?;
+    let peer = Ring::open(mem, ring.data_ready.try_clone()?, ring.space_ready.try_clone()?, ring.cap)?;
+    Ok((ShmSender::new(ring), ShmReceiver::new(peer, ReaderConfig::default())))
+}
+
+/// Creates a ring of at least `capacity` bytes and passes it to the peer on
+/// `stream`, which must call [`accept`]. This side becomes the producer.
+pub fn offer(stream: &UnixStream, capacity: usize) -> Result<ShmSender, AbutError> {
+    let (mut ring, mem) = Ring::create(capacity)?;
+    let mut msg = [0u8; 16];
+    msg[..8].copy_from_slice(&MAGIC.to_le_bytes());
+    msg[8..].copy_from_slice(&ring.cap.to_le_bytes());
+    send_fds(stream, &msg, &[mem.as_raw_fd(), ring.data_ready.as_raw_fd(), ring.space_ready.as_raw_fd()])?;
+    ring.control = Some(stream.try_clone()?);
+    Ok(ShmSender::new(ring))
+}
+
+/// Receives a ring offered by the peer on `stream`. This side becomes the consumer.
+///
+/// Frame-size limits and the idle timeout are taken from `cfg`; frames are
+/// never partially visible, so `frame_timeout` does not apply.
+pub fn accept(stream: &UnixStream, cfg: ReaderConfig) -> Result<ShmReceiver, AbutError> {
+    let mut msg = [0u8; 16];
+    let (n, fds) = recv_fds(stream, &mut msg)?;
+    if n == 0 {
+        return Err(AbutError::closed());
+    }
+    let [mem, data_ready, space_ready]: [OwnedFd; 3] = fds
+        .try_into()
+        .map_err(|fds: Vec<OwnedFd>| AbutError::protocol(format_args!("shm offer carried {} fds, expected 3", fds.len())))?;
+    if n != msg.len() || msg[..8] != MAGIC.to_le_bytes() {
+        return Err(AbutError::protocol("malformed shm offer"));
+    }
+    let capacity = u64::from_le_bytes(msg[8..].try_into().expect("8 bytes"));
+    let mut ring = Ring::open(mem, data_ready, space_ready, capacity)?;
+    ring.control = Some(stream.try_clone()?);
+    Ok(ShmReceiver::new(ring, cfg))
+}
+
+impl ShmSender {
+    fn new(ring: Ring) -> Self {
+        let head = ring.header().head.0.load(Ordering::Relaxed);
+        Self { ring, head, send_timeout: None }
+    }
+
+    /// Size of the data area. A frame needs its length plus 4 bytes of it.
+    pub fn capacity(&self) -> usize { self.ring.cap as usize }
+
+    /// Largest frame this ring can ever carry.
+    pub fn max_frame_len(&self) -> usize { self.capacity() - LEN_PREFIX }
+
+    /// Bounds how long [`send_frame`](FrameSink::send_frame) waits for space.
+    /// A timed-out send wrote nothing, so the ring stays aligned.
+    pub fn set_send_timeout(&mut self, timeout: Option<Duration>) { self.send_timeout = timeout; }
+
+    fn used(&self) -> Result<u64, AbutError> {
+        let tail = self.ring.header().tail.0.load(Ordering::Acquire);
+        let used = self.head.wrapping_sub(tail);
+        if used > self.ring.cap {
+            return Err(AbutError::protocol(format_args!("shm ring tail {tail} is ahead of head {}", self.head)));
+        }
+        Ok(used)
+    }
+
+    pub fn write_frame(&mut self, bytes: &[u8]) -> Result<(), AbutError> {
+        if bytes.len() > self.max_frame_len() {
+            return Err(AbutError::frame_too_large(bytes.len(), self.max_frame_len()));
+        }
+        let need = (LEN_PREFIX + bytes.len()) as u64;
+        let deadline = self.send_timeout.map(|t| Instant::now() + t);
+        let header = self.ring.header();
+
+        loop {
+            if header.consumer_closed.load(Ordering::Acquire) != 0 {
+                return Err(AbutError::closed());
+            }
+            if self.ring.cap - self.used()? >= need {
+                break;
+            }
+            header.producer_waiting.store(1, Ordering::Relaxed);
+            fence(Ordering::SeqCst);
+            if self.ring.cap - self.used()? >= need {
+                header.producer_waiting.store(0, Ordering::Relaxed);
+                break;
+            }
+            let wake = self.ring.wait(&self.ring.space_ready, deadline);
+            header.producer_waiting.store(0, Ordering::Relaxed);
+            match wake? {
+                Wake::Signalled => {}
+                Wake::TimedOut => return Err(AbutError::timeout(true, "send")),
+                Wake::HungUp => return Err(hung_up()),
+            }
+        }
+
+        self.ring.copy_in(self.head, &(bytes.len() as u32).to_le_bytes());
+        self.ring.copy_in(self.head + LEN_PREFIX as u64, bytes);
+        self.head = self.head.wrapping_add(need);
+        header.head.0.store(self.head, Ordering::Release);
+        self.ring.notify(&header.consumer_waiting, &self.ring.data_ready);
+        Ok(())
+    }
+}
+
+impl FrameSink for ShmSender {
+    type Error = AbutError;
+    fn send_frame(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.write_frame(bytes)
+    }
+}
+
+impl Drop for ShmSender {
+    fn drop(&mut self) {
+        self.ring.header().producer_closed.store(1, Ordering::Release);
+        signal(&self.ring.data_ready);
+    }
+}
+
+impl ShmReceiver {
+    fn new(ring: Ring, cfg: ReaderConfig) -> Self {
+        let tail = ring.header().tail.0.load(Ordering::Relaxed);
+        Self { ring, tail, cfg }
+    }
+
+    pub fn config(&self) -> ReaderConfig { self.cfg }
+    pub fn set_config(&mut self, cfg: ReaderConfig) { self.cfg = cfg; }
+
+    /// Length of the next published frame, if any.
+    fn peek(&self) -> Result<Option<usize>, AbutError> {
+        let head = self.ring.header().head.0.load(Ordering::Acquire);
+        let avail = head.wrapping_sub(self.tail);
+        if avail == 0 {
+            return Ok(None);
+        }
+        if avail > self.ring.cap || avail < LEN_PREFIX as u64 {
+            return Err(AbutError::protocol(format_args!("shm ring head {head} is inconsistent with tail {}", self.tail)));
+        }
+        let mut len = [0u8; LEN_PREFIX];
+        self.ring.copy_out(self.tail, &mut len);
+        let len = u32::from_le_bytes(len) as usize;
+        if (LEN_PREFIX + len) as u64 > avail {
+            return Err(AbutError::protocol(format_args!("shm frame of {len} bytes overruns the published data")));
+        }
+        Ok(Some(len))
+    }
+
+    /// Waits for the next frame and returns its length.
+    fn next_len(&mut self) -> Result<usize, AbutError> {
+        let deadline = self.cfg.idle_timeout.map(|t| Instant::now() + t);
+        let header = self.ring.header();
+        loop {
+            if let Some(len) = self.peek()? {
+                return Ok(len);
+            }
+            // Anything published before the close flag is still delivered.
+            if header.producer_closed.load(Ordering::Acquire) != 0 {
+                return match self.peek()? {
+                    Some(len) => Ok(len),
+                    None => Err(AbutError::closed()),
+                };
+            }
+            header.consumer_waiting.store(1, Ordering::Relaxed);
+            fence(Ordering::SeqCst);
+            if self.peek()?.is_some() || header.producer_closed.load(Ordering::Acquire) != 0 {
+                header.consumer_waiting.store(0, Ordering::Relaxed);
+                continue;
+            }
+            let wake = self.ring.wait(&self.ring.data_ready, deadline);
+            header.consumer_waiting.store(0, Ordering::Relaxed);
+            match wake? {
+                Wake::Signalled => {}
+                Wake::TimedOut => return Err(AbutError::timeout(true, "idle")),
+                Wake::HungUp => {
+                    if self.peek()?.is_none() && header.producer_closed.load(Ordering::Acquire) == 0 {
+                        return Err(hung_up());
+                    }
+                }
+            }
+        }
+    }
+
+    fn consume(&mut self, len: usize) {
+        self.tail = self.tail.wrapping_add((LEN_PREFIX + len) as u64);
+        let header = self.ring.header();
+        header.tail.0.store(self.tail, Ordering::Release);
+        self.ring.notify(&header.producer_waiting, &self.ring.space_ready);
+    }
+
+    /// Returns the length of the next frame if it may be delivered, skipping it otherwise.
+    ///
+    /// Oversized frames are always skipped: unlike a socket, dropping one costs nothing.
+    fn admit(&mut self) -> Result<usize, AbutError> {
+        let len = self.next_len()?;
+        if len > self.cfg.max_frame_len {
+            self.consume(len);
+            return Err(AbutError::frame_too_large(len, self.cfg.max_frame_len));
+        }
+        Ok(len)
+    }
+
+    /// Reads the next frame into `dst`, resizing it exactly to the frame length.
+    ///
+    /// Fails with `AbutCode::Closed` once the sender is dropped and the ring is empty.
+    pub fn recv_into(&mut self, dst: &mut Vec<u8>) -> Result<(), AbutError> {
+        let len = self.admit()?;
+        dst.clear();
+        dst.resize(len, 0);
+        self.ring.copy_out(self.tail + LEN_PREFIX as u64, dst);
+        self.consume(len);
+        Ok(())
+    }
+
+    /// Like [`recv_into`](Self::recv_into), but returns `Ok(false)` once the sender has closed.
+    pub fn try_recv_into(&mut self, dst: &mut Vec<u8>) -> Result<bool, AbutError> {
+        match self.recv_into(dst) {
+            Ok(()) => Ok(true),
+            Err(e) if e.code == crate::AbutCode::Closed => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Reads the next frame into a caller-provided slice.
+    ///
+    /// If `dst` is too small the frame stays queued (so a retry with a larger
+    /// buffer gets it) unless `drain_on_small_buffer` is set.
+    pub fn read_frame(&mut self, dst: &mut [u8]) -> Result<usize, AbutError> {
+        let len = self.admit()?;
+        if dst.len() < len {
+            if self.cfg.drain_on_small_buffer {
+                self.consume(len);
+            }
+            return Err(AbutError::buffer_too_small(len));
+        }
+        self.ring.copy_out(self.tail + LEN_PREFIX as u64, &mut dst[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl FrameSource for ShmReceiver {
+    type Error = AbutError;
+    fn recv_frame(&mut self, dst: &mut [u8]) -> Result<usize, Self::Error> {
+        self.read_frame(dst)
+    }
+}
+
+impl Drop for ShmReceiver {
+    fn drop(&mut self) {
+        self.ring.header().consumer_closed.store(1, Ordering::Release);
+        signal(&self.ring.space_ready);
+    }
+}
+
+const MAX_FDS: usize = 3;
+
+fn cmsg_space() -> usize {
+    // SAFETY: CMSG_SPACE is a pure size computation.
+    unsafe { libc::CMSG_SPACE((MAX_FDS * size_of::<RawFd>()) as u32) as usize }
+}
+
+fn send_fds(stream: &UnixStream, data: &[u8], fds: &[RawFd]) -> io::Result<()> {
+    assert!(fds.len() <= MAX_FDS);
+    let mut cbuf = vec![0u64; cmsg_space().div_ceil(8)];
+    let mut iov = libc::iovec { iov_base: data.as_ptr() as *mut _, iov_len: data.len() };
+    // SAFETY: msghdr is plain data; every pointer set below outlives the sendmsg call.
+    unsafe {
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = cbuf.as_mut_ptr().cast();
+        msg.msg_controllen = libc::CMSG_SPACE(size_of_val(fds) as u32) as _;
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(size_of_val(fds) as u32) as _;
+        std::ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg).cast::<RawFd>(), fds.len());
+        loop {
+            match cvt(libc::sendmsg(stream.as_raw_fd(), &msg, libc::MSG_NOSIGNAL) as libc::c_int) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+                Ok(n) if (n as usize) < data.len() => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(_) => return Ok(()),
+            }
+        }
+    }
+}
+
+fn recv_fds(stream: &UnixStream, data: &mut [u8]) -> io::Result<(usize, Vec<OwnedFd>)> {
+    let mut cbuf = vec![0u64; cmsg_space().div_ceil(8)];
+    let mut iov = libc::iovec { iov_base: data.as_mut_ptr().cast(), iov_len: data.len() };
+    // SAFETY: as in `send_fds`; received fds are wrapped as owned exactly once.
+    unsafe {
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = cbuf.as_mut_ptr().cast();
+        msg.msg_controllen = cmsg_space() as _;
+        let n = loop {
+            match cvt(libc::recvmsg(stream.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) as libc::c_int) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                other => break other? as usize,
+            }
+        };
+        let mut fds = Vec::new();
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let count = ((*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize) / size_of::<RawFd>();
+                let base = libc::CMSG_DATA(cmsg).cast::<RawFd>();
+                for i in 0..count {
+                    fds.push(OwnedFd::from_raw_fd(base.add(i).read_unaligned()));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+        if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "too many fds in shm offer"));
+        }
+        Ok((n, fds))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AbutCode;
+
+    #[test]
+    fn test_wraparound_across_threads() {
+        let (mut tx, mut rx) = channel(64).unwrap();
+        let producer = std::thread::spawn(move || {
+            for i in 0..500u32 {
+                let frame = vec![i as u8; (i % 37) as usize];
+                tx.send_frame(&frame).unwrap();
+            }
+        });
+        let mut buf = Vec::new();
+        for i in 0..500u32 {
+            rx.recv_into(&mut buf).unwrap();
+            assert_eq!(buf, vec![i as u8; (i % 37) as usize]);
+        }
+        producer.join().unwrap();
+        assert_eq!(rx.recv_into(&mut buf).unwrap_err().code, AbutCode::Closed);
+    }
+
+    #[test]
+    fn test_frame_limits() {
+        let (mut tx, mut rx) = channel(64).unwrap();
+        assert_eq!(tx.send_frame(&[0; 61]).unwrap_err().code, AbutCode::FrameTooLarge);
+
+        rx.set_config(ReaderConfig { max_frame_len: 8, drain_on_small_buffer: false, ..Default::default() });
+        tx.send_frame(&[1; 20]).unwrap();
+        tx.send_frame(b"ok").unwrap();
+        let mut small = [0u8; 1];
+        assert_eq!(rx.read_frame(&mut small).unwrap_err().code, AbutCode::FrameTooLarge);
+        assert_eq!(rx.read_frame(&mut small).unwrap_err().code, AbutCode::BufferTooSmall);
+        let mut buf = [0u8; 8];
+        assert_eq!(rx.read_frame(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"ok");
+    }
+
+    #[test]
+    fn test_idle_and_send_timeouts() {
+        let (mut tx, mut rx) = channel(64).unwrap();
+        rx.set_config(ReaderConfig { idle_timeout: Some(Duration::from_millis(20)), ..Default::default() });
+        let err = rx.recv_into(&mut Vec::new()).unwrap_err();
+        assert_eq!((err.code, err.stream_aligned()), (AbutCode::Timeout, Some(true)));
+
+        tx.set_send_timeout(Some(Duration::from_millis(20)));
+        tx.send_frame(&[0; 60]).unwrap();
+        assert_eq!(tx.send_frame(b"x").unwrap_err().code, AbutCode::Timeout);
+        drop(rx);
+        assert_eq!(tx.send_frame(b"x").unwrap_err().code, AbutCode::Closed);
+    }
+
+    #[test]
+    fn test_offer_accept_over_socket() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let mut tx = offer(&a, 4096).unwrap();
+        let mut rx = accept(&b, ReaderConfig::default()).unwrap();
+        assert_eq!(tx.capacity(), 4096);
+
+        tx.send_frame(b"telemetry").unwrap();
+        let mut buf = Vec::new();
+        rx.recv_into(&mut buf).unwrap();
+        assert_eq!(buf, b"telemetry");
+
+        // Simulate the producer's process dying: its socket closes, the ring is never marked closed.
+        drop(tx.ring.control.take());
+        std::mem::forget(tx);
+        drop(a);
+        assert_eq!(rx.recv_into(&mut buf).unwrap_err().code, AbutCode::Io);
+    }
+
+    #[test]
+    fn test_ring_cannot_be_resized() {
+        let (ring, mem) = Ring::create(4096).unwrap();
+        // SAFETY: plain syscalls on fds owned by this test.
+        unsafe {
+            assert!(cvt(libc::ftruncate(mem.as_raw_fd(), 0)).is_err());
+            assert!(cvt(libc::fcntl(mem.as_raw_fd(), libc::F_ADD_SEALS, 0)).is_err());
+        }
+        assert_eq!(ring.header().capacity, 4096);
+
+        // A ring the peer could still shrink is refused before it is mapped.
+        // SAFETY: as above; the memfd is wrapped as an owned fd straight away.
+        let unsealed = unsafe {
+            let mem = OwnedFd::from_raw_fd(cvt(libc::memfd_create(c"abut-test".as_ptr(), libc::MFD_CLOEXEC)).unwrap());
+            cvt(libc::ftruncate(mem.as_raw_fd(), (DATA_OFFSET + 4096) as libc::off_t)).unwrap();
+            mem
+        };
+        let err = Ring::open(unsealed, ring.data_ready.try_clone().unwrap(), ring.space_ready.try_clone().unwrap(), 4096)
+            .err()
+            .unwrap();
+        assert_eq!(err.code, AbutCode::Protocol);
+    }
+}
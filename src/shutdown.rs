@@ -0,0 +1,281 @@
+//! Graceful connection shutdown with a drain period.
+//!
+//! [`Graceful`] tags every frame with one byte so that close requests can
+//! travel in-band. Closing runs the following protocol on both sides:
+//!
+//! 1. the initiator sends `CLOSE` and keeps reading, handing any data that
+//!    was already in flight to the caller;
+//! 2. the peer sees `AbutCode::Closed`, sends whatever it still has queued,
+//!    then calls [`Graceful::close`] itself, which answers `CLOSE_ACK`;
+//! 3. each side half-closes its write direction, reads until EOF and shuts
+//!    the socket down.
+//!
+//! If the deadline passes first the socket is severed and the close fails
+//! with `AbutCode::Timeout`.
+
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
+
+use crate::frame::{FramedReader, FramedWriter};
+use crate::{AbutCode, AbutError, FrameSink, FrameSource, ReaderConfig};
+
+/// Tag of a frame carrying application data.
+pub const TAG_DATA: u8 = 0x00;
+/// Tag of a request to close the connection.
+pub const TAG_CLOSE: u8 = 0x10;
+/// Tag acknowledging a close request; nothing but EOF follows it.
+pub const TAG_CLOSE_ACK: u8 = 0x11;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseState {
+    Open,
+    /// The peer asked to close; we may still send before calling `close`.
+    PeerClosing,
+    Closed,
+}
+
+/// A framed Unix stream that can be shut down without losing in-flight frames.
+#[derive(Debug)]
+pub struct Graceful {
+    stream: UnixStream,
+    reader: FramedReader<UnixStream>,
+    writer: FramedWriter<UnixStream>,
+    scratch: Vec<u8>,
+    state: CloseState,
+}
+
+impl Graceful {
+    pub fn new(stream: UnixStream) -> Result<Self, AbutError> {
+        Self::with_config(stream, ReaderConfig::default())
+    }
+
+    pub fn with_config(stream: UnixStream, cfg: ReaderConfig) -> Result<Self, AbutError> {
+        Ok(Self {
+            reader: FramedReader::with_deadlines(stream.try_clone()?, cfg),
+            // Data frames are sent without a timeout; `close` bounds its own by the deadline.
+            writer: FramedWriter::new(stream.try_clone()?),
+            stream,
+            scratch: Vec::new(),
+            state: CloseState::Open,
+        })
+    }
+
+    pub fn state(&self) -> CloseState { self.state }
+
+    /// Receives the next data frame into `dst`.
+    ///
+    /// Fails with `AbutCode::Closed` once the peer has requested a close.
+    pub fn recv_into(&mut self, dst: &mut Vec<u8>) -> Result<(), AbutError> {
+        if self.state != CloseState::Open {
+            return Err(AbutError::closed().ctx("shutdown in progress"));
+        }
+        self.reader.recv_into(dst)?;
+        match dst.first().copied() {
+            Some(TAG_DATA) => {
+                dst.remove(0);
+                Ok(())
+            }
+            Some(TAG_CLOSE) => {
+                self.state = CloseState::PeerClosing;
+                Err(AbutError::closed().ctx("peer requested shutdown"))
+            }
+            Some(TAG_CLOSE_ACK) => Err(AbutError::protocol("close ack without a close request")),
+            Some(tag) => Err(AbutError::protocol(format_args!("unknown shutdown tag {tag:#04x}"))),
+            None => Err(AbutError::protocol("empty frame")),
+        }
+    }
+
+    fn send_tagged(&mut self, tag: u8, bytes: &[u8]) -> Result<(), AbutError> {
+        self.scratch.clear();
+        self.scratch.push(tag);
+        self.scratch.extend_from_slice(bytes);
+        self.writer.write_frame(&self.scratch)
+    }
+
+    /// Runs the shutdown protocol, giving up and severing the socket after `timeout`.
+    ///
+    /// Data frames that arrive while draining are passed to `on_frame`.
+    pub fn close(&mut self, timeout: Duration, mut on_frame: impl FnMut(&[u8])) -> Result<(), AbutError> {
+        let deadline = Instant::now() + timeout;
+        let result = self.run_close(deadline, &mut on_frame);
+        let _ = self.stream.shutdown(Shutdown::Both);
+        self.state = CloseState::Closed;
+        result
+    }
+
+    fn run_close(&mut self, deadline: Instant, on_frame: &mut dyn FnMut(&[u8])) -> Result<(), AbutError> {
+        match self.state {
+            CloseState::Closed => return Ok(()),
+            CloseState::PeerClosing => self.send_before(deadline, TAG_CLOSE_ACK)?,
+            CloseState::Open => {
+                self.send_before(deadline, TAG_CLOSE)?;
+                self.drain_until_ack(deadline, on_frame)?;
+            }
+        }
+        self.stream.shutdown(Shutdown::Write)?;
+        // Anything after CLOSE_ACK is a protocol violation; wait for the peer's EOF.
+        let mut frame = Vec::new();
+        match self.recv_before(deadline, &mut frame) {
+            Ok(()) => Err(AbutError::protocol("frame received after close ack")),
+            Err(e) if e.code == AbutCode::Closed => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn drain_until_ack(&mut self, deadline: Instant, on_frame: &mut dyn FnMut(&[u8])) -> Result<(), AbutError> {
+        let mut frame = Vec::new();
+        loop {
+            self.recv_before(deadline, &mut frame)?;
+            match frame.first().copied() {
+                Some(TAG_DATA) => on_frame(&frame[1..]),
+                Some(TAG_CLOSE_ACK) => return Ok(()),
+                // Both sides closed at once: acknowledge theirs and keep waiting for ours.
+                Some(TAG_CLOSE) => self.send_before(deadline, TAG_CLOSE_ACK)?,
+                Some(tag) => return Err(AbutError::protocol(format_args!("unknown shutdown tag {tag:#04x}"))),
+                None => return Err(AbutError::protocol("empty frame")),
+            }
+        }
+    }
+
+    /// Sends a control frame, failing if the peer does not take it by `deadline`.
+    fn send_before(&mut self, deadline: Instant, tag: u8) -> Result<(), AbutError> {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(AbutError::timeout(false, "shutdown"));
+        }
+        let timeout = self.writer.send_timeout();
+        self.writer.set_send_timeout(Some(remaining));
+        let result = self.send_tagged(tag, &[]).and_then(|()| self.writer.flush());
+        self.writer.set_send_timeout(timeout);
+        result.map_err(|e| match e.code {
+            AbutCode::Timeout => AbutError::timeout(false, "shutdown"),
+            _ => e,
+        })
+    }
+
+    fn recv_before(&mut self, deadline: Instant, frame: &mut Vec<u8>) -> Result<(), AbutError> {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(AbutError::timeout(false, "shutdown"));
+        }
+        let cfg = self.reader.config();
+        self.reader.set_config(ReaderConfig { idle_timeout: Some(remaining), frame_timeout: Some(remaining), ..cfg });
+        let result = self.reader.recv_into(frame);
+        self.reader.set_config(cfg);
+        result.map_err(|e| match e.code {
+            AbutCode::Timeout => AbutError::timeout(false, "shutdown"),
+            _ => e,
+        })
+    }
+}
+
+impl FrameSink for Graceful {
+    type Error = AbutError;
+
+    /// Sends a data frame. Allowed until `close` is called, even after the
+    /// peer asked to close, so queued replies can still go out.
+    fn send_frame(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        if self.state == CloseState::Closed {
+            return Err(AbutError::closed().ctx("connection was shut down"));
+        }
+        self.send_tagged(TAG_DATA, bytes)
+    }
+}
+
+impl FrameSource for Graceful {
+    type Error = AbutError;
+
+    fn recv_frame(&mut self, dst: &mut [u8]) -> Result<usize, Self::Error> {
+        let mut frame = std::mem::take(&mut self.scratch);
+        let result = self.recv_into(&mut frame).and_then(|()| {
+            let out = dst.get_mut(..frame.len()).ok_or_else(|| AbutError::buffer_too_small(frame.len()))?;
+            out.copy_from_slice(&frame);
+            Ok(frame.len())
+        });
+        self.scratch = frame;
+        result
+    }
+}
+
+impl From<Graceful> for UnixStream {
+    fn from(g: Graceful) -> Self {
+        g.stream
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_close_drains_in_flight_frames() {
+        let (a, b) = UnixStream::pair().unwrap();
+
+        let peer = std::thread::spawn(move || {
+            let mut b = Graceful::new(b).unwrap();
+            let mut got = Vec::new();
+            let mut frame = Vec::new();
+            let err = loop {
+                match b.recv_into(&mut frame) {
+                    Ok(()) => got.push(frame.clone()),
+                    Err(e) => break e,
+                }
+            };
+            assert_eq!(err.code, AbutCode::Closed);
+            assert_eq!(b.state(), CloseState::PeerClosing);
+
+            // Still allowed to flush replies before acknowledging.
+            b.send_frame(b"last words").unwrap();
+            b.close(Duration::from_secs(5), |_| panic!("nothing after close")).unwrap();
+            got
+        });
+
+        let mut a = Graceful::new(a).unwrap();
+        for msg in [&b"one"[..], b"two", b"three"] {
+            a.send_frame(msg).unwrap();
+        }
+        let mut drained = Vec::new();
+        a.close(Duration::from_secs(5), |f| drained.push(f.to_vec())).unwrap();
+
+        assert_eq!(drained, vec![b"last words".to_vec()]);
+        assert_eq!(peer.join().unwrap(), vec![b"one".to_vec(), b"two".to_vec(), b"three".to_vec()]);
+        assert_eq!(a.state(), CloseState::Closed);
+        assert_eq!(a.send_frame(b"late").unwrap_err().code, AbutCode::Closed);
+    }
+
+    #[test]
+    fn test_close_deadline_severs_connection() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let mut a = Graceful::new(a).unwrap();
+
+        let started = Instant::now();
+        let err = a.close(Duration::from_millis(50), |_| {}).unwrap_err();
+        assert_eq!(err.code, AbutCode::Timeout);
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        // The silent peer sees the close request and then EOF.
+        let mut r = FramedReader::new(&b);
+        let mut frame = Vec::new();
+        r.recv_into(&mut frame).unwrap();
+        assert_eq!(frame, [TAG_CLOSE]);
+        assert_eq!(r.recv_into(&mut frame).unwrap_err().code, AbutCode::Closed);
+    }
+
+    #[test]
+    fn test_close_deadline_bounds_blocked_send() {
+        use std::io::Write;
+
+        let (a, _b) = UnixStream::pair().unwrap();
+        // Fill the socket buffer so that not even CLOSE fits.
+        let mut raw = a.try_clone().unwrap();
+        raw.set_nonblocking(true).unwrap();
+        while raw.write(&[0u8; 4096]).is_ok() {}
+        raw.set_nonblocking(false).unwrap();
+
+        let mut a = Graceful::new(a).unwrap();
+        let started = Instant::now();
+        assert_eq!(a.close(Duration::from_millis(50), |_| {}).unwrap_err().code, AbutCode::Timeout);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
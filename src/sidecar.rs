@@ -0,0 +1,216 @@
+//! Launching a sidecar process already connected to its host.
+//!
+//! The host creates a `socketpair`, keeps one end and lets the child inherit
+//! the other. The child finds its end through the [`FD_ENV`] variable and
+//! recovers it with [`child_endpoint`].
+
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::frame::{FramedReader, FramedWriter};
+use crate::{AbutError, FrameSink, FrameSource, ReaderConfig};
+
+/// Environment variable carrying the child's socket fd number.
+pub const FD_ENV: &str = "ABUT_FD";
+
+#[derive(Debug, Clone, Copy)]
+pub struct SidecarConfig {
+    pub reader: ReaderConfig,
+    pub send_timeout: Option<Duration>,
+
+    /// On drop, how long the child gets to exit after seeing EOF before it is killed.
+    pub exit_grace: Duration,
+}
+
+impl Default for SidecarConfig {
+    fn default() -> Self {
+        Self { reader: ReaderConfig::default(), send_timeout: None, exit_grace: Duration::from_millis(500) }
+    }
+}
+
+/// A spawned child and the host's framed end of its socket.
+///
+/// Dropping it closes the socket, waits up to `exit_grace` for the child to
+/// exit and kills it otherwise, so no sidecar outlives its handle.
+pub struct Sidecar {
+    child: Child,
+    reader: FramedReader<UnixStream>,
+    writer: FramedWriter<UnixStream>,
+    exit_grace: Duration,
+    reaped: bool,
+}
+
+impl Sidecar {
+    pub fn spawn(cmd: Command) -> Result<Self, AbutError> {
+        Self::spawn_with(cmd, SidecarConfig::default())
+    }
+
+    pub fn spawn_with(mut cmd: Command, cfg: SidecarConfig) -> Result<Self, AbutError> {
+        // Both ends are created close-on-exec; only the child's copy of its
+        // own end has the flag cleared, after fork and before exec.
+        let (host, child_end) = UnixStream::pair()?;
+        let fd = child_end.as_raw_fd();
+        cmd.env(FD_ENV, fd.to_string());
+        // SAFETY: fcntl is async-signal-safe and touches only the child's fd table.
+        unsafe {
+            cmd.pre_exec(move || {
+                let flags = libc::fcntl(fd, libc::F_GETFD);
+                if flags < 0 || libc::fcntl(fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let child = cmd.spawn()?;
+        drop(child_end);
+
+        let reader = FramedReader::with_deadlines(host.try_clone()?, cfg.reader);
+        let writer = match cfg.send_timeout {
+            Some(timeout) => FramedWriter::with_send_timeout(host, timeout),
+            None => FramedWriter::new(host),
+        };
+        Ok(Self { child, reader, writer, exit_grace: cfg.exit_grace, reaped: false })
+    }
+
+    pub fn id(&self) -> u32 { self.child.id() }
+    pub fn child_mut(&mut self) -> &mut Child { &mut self.child }
+    pub fn reader_mut(&mut self) -> &mut FramedReader<UnixStream> { &mut self.reader }
+    pub fn writer_mut(&mut self) -> &mut FramedWriter<UnixStream> { &mut self.writer }
+
+    /// Borrows both halves at once, e.g. to hand them to a typed reader and writer.
+    pub fn split(&mut self) -> (&mut FramedReader<UnixStream>, &mut FramedWriter<UnixStream>) {
+        (&mut self.reader, &mut self.writer)
+    }
+
+    /// Closes the host end and waits for the child to exit.
+    pub fn wait(mut self) -> Result<ExitStatus, AbutError> {
+        self.hang_up();
+        self.reaped = true;
+        Ok(self.child.wait()?)
+    }
+
+    fn hang_up(&mut self) {
+        let _ = self.writer.inner_mut().shutdown(std::net::Shutdown::Both);
+    }
+}
+
+impl FrameSink for Sidecar {
+    type Error = AbutError;
+
+    fn send_frame(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.writer.write_frame(bytes)
+    }
+}
+
+impl FrameSource for Sidecar {
+    type Error = AbutError;
+
+    fn recv_frame(&mut self, dst: &mut [u8]) -> Result<usize, Self::Error> {
+        self.reader.read_frame(dst)
+    }
+}
+
+impl Drop for Sidecar {
+    fn drop(&mut self) {
+        if self.reaped {
+            return;
+        }
+        self.hang_up();
+        let deadline = Instant::now() + self.exit_grace;
+        while Instant::now() < deadline {
+            match self.child.try_wait() {
+                Ok(Some(_)) | Err(_) => return,
+                Ok(None) => std::thread::sleep(Duration::from_millis(5)),
+            }
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Recovers the socket a host passed to this process via [`Sidecar::spawn`].
+///
+/// Succeeds at most once per process, since the returned stream owns the fd.
+pub fn child_stream() -> Result<UnixStream, AbutError> {
+    let value = std::env::var(FD_ENV)
+        .map_err(|_| AbutError::from(io::Error::new(io::ErrorKind::NotFound, format!("{FD_ENV} is not set"))))?;
+    let fd: RawFd = value
+        .parse()
+        .map_err(|_| AbutError::from(io::Error::new(io::ErrorKind::InvalidInput, format!("{FD_ENV}={value:?} is not an fd"))))?;
+
+    // SAFETY: fstat only writes into `st`.
+    let mut st: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstat(fd, &mut st) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    if st.st_mode & libc::S_IFMT != libc::S_IFSOCK {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{FD_ENV}={fd} is not a socket")).into());
+    }
+    if TAKEN.swap(true, Ordering::SeqCst) {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{FD_ENV} endpoint already taken")).into());
+    }
+
+    // Don't leak the host connection into our own children.
+    // SAFETY: `fd` is an open socket per the fstat above.
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC);
+    }
+    // SAFETY: the host handed this fd to us alone and TAKEN guarantees a single owner.
+    Ok(unsafe { UnixStream::from_raw_fd(fd) })
+}
+
+/// [`child_stream`] wrapped as framed endpoints.
+pub fn child_endpoint(cfg: ReaderConfig) -> Result<(FramedReader<UnixStream>, FramedWriter<UnixStream>), AbutError> {
+    let stream = child_stream()?;
+    Ok((FramedReader::with_deadlines(stream.try_clone()?, cfg), FramedWriter::new(stream)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_echo_child_over_inherited_fd() {
+        // Raw frames are echoed byte-for-byte, so framing survives the round trip.
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", r#"exec cat <&"$ABUT_FD" >&"$ABUT_FD""#]);
+        let mut sidecar = Sidecar::spawn(cmd).unwrap();
+
+        sidecar.send_frame(b"ping").unwrap();
+        sidecar.send_frame(b"").unwrap();
+        let mut buf = [0u8; 16];
+        let n = sidecar.recv_frame(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"ping");
+        assert_eq!(sidecar.recv_frame(&mut buf).unwrap(), 0);
+
+        let status = sidecar.wait().unwrap();
+        assert!(status.success());
+    }
+
+    #[test]
+    fn test_drop_kills_stubborn_child() {
+        let mut cmd = Command::new("sleep");
+        cmd.arg("30");
+        let sidecar = Sidecar::spawn_with(cmd, SidecarConfig { exit_grace: Duration::from_millis(20), ..Default::default() }).unwrap();
+        let pid = sidecar.id() as i32;
+        drop(sidecar);
+        // SAFETY: signal 0 only probes for existence; the pid was reaped in drop.
+        assert_ne!(unsafe { libc::kill(pid, 0) }, 0);
+    }
+
+    #[test]
+    fn test_child_stream_requires_env() {
+        // The test harness is not a sidecar.
+        if std::env::var_os(FD_ENV).is_none() {
+            assert!(child_stream().is_err());
+        }
+    }
+}
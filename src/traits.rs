@@ -1,3 +1,12 @@
+use std::io;
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::{AbutError, DecodeLimits};
+
 pub trait FrameSink {
     type Error;
     fn send_frame(&mut self, bytes: &[u8]) -> Result<(), Self::Error>;
@@ -7,3 +16,88 @@ pub trait FrameSource {
     type Error;
     fn recv_frame(&mut self, dst: &mut [u8]) -> Result<usize, Self::Error>;
 }
+
+/// A reader that decodes whole typed messages from frames.
+pub trait MessageSource {
+    /// Receives the next message, or `Ok(None)` if the peer closed the
+    /// stream cleanly at a frame boundary.
+    fn try_recv<T: DeserializeOwned>(&mut self) -> Result<Option<T>, AbutError>;
+}
+
+/// A serde wire format for typed frames, implemented by the marker types
+/// in each codec module (`frame::postcard::Postcard`, ...).
+pub trait Codec {
+    /// Appends the encoding of `value` to `buf`.
+    fn encode<T: Serialize>(value: &T, buf: &mut Vec<u8>) -> Result<(), AbutError>;
+
+    /// Decodes one value from a whole frame, enforcing `limits`.
+    fn decode<T: DeserializeOwned>(bytes: &[u8], limits: &DecodeLimits) -> Result<T, AbutError>;
+}
+
+/// Streams whose blocking reads and writes can be bounded by a timeout.
+pub trait SocketTimeout {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    fn read_timeout(&self) -> io::Result<Option<Duration>>;
+    fn write_timeout(&self) -> io::Result<Option<Duration>>;
+}
+
+impl SocketTimeout for UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_write_timeout(self, timeout)
+    }
+    fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        UnixStream::read_timeout(self)
+    }
+    fn write_timeout(&self) -> io::Result<Option<Duration>> {
+        UnixStream::write_timeout(self)
+    }
+}
+
+impl SocketTimeout for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+    fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        TcpStream::read_timeout(self)
+    }
+    fn write_timeout(&self) -> io::Result<Option<Duration>> {
+        TcpStream::write_timeout(self)
+    }
+}
+
+impl<T: SocketTimeout + ?Sized> SocketTimeout for &T {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_read_timeout(timeout)
+    }
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_write_timeout(timeout)
+    }
+    fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        (**self).read_timeout()
+    }
+    fn write_timeout(&self) -> io::Result<Option<Duration>> {
+        (**self).write_timeout()
+    }
+}
+
+impl<T: SocketTimeout + ?Sized> SocketTimeout for &mut T {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_read_timeout(timeout)
+    }
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_write_timeout(timeout)
+    }
+    fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        (**self).read_timeout()
+    }
+    fn write_timeout(&self) -> io::Result<Option<Duration>> {
+        (**self).write_timeout()
+    }
+}
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 /// Returned by sources that need a larger destination buffer to receive a frame.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct BufferTooSmall {
@@ -12,6 +14,26 @@ impl core::fmt::Display for BufferTooSmall {
 
 impl std::error::Error for BufferTooSmall {}
 
+/// Source of an `AbutCode::Timeout` error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimedOut {
+    /// True if no bytes of the interrupted frame were transferred, so the
+    /// stream is still at a frame boundary and may be used again.
+    pub aligned: bool,
+}
+
+impl core::fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.aligned {
+            write!(f, "timed out at a frame boundary")
+        } else {
+            write!(f, "timed out mid-frame; stream is no longer aligned")
+        }
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
 
 #[derive(Debug, Clone, Copy)]
 pub struct ReaderConfig {
@@ -23,6 +45,22 @@ pub struct ReaderConfig {
     /// If the peer claims an oversize frame, only drain it if len <= drain_oversize_up_to.
     /// 0 = never drain oversize (recommended default).
     pub drain_oversize_up_to: usize,
+
+    /// Longest wait for the first byte of the next frame.
+    /// Only enforced by readers built with `FramedReader::with_deadlines`.
+    pub idle_timeout: Option<Duration>,
+
+    /// Longest time one frame may take from its first byte to its last, so a
+    /// peer trickling bytes cannot hold the reader indefinitely.
+    /// Only enforced by readers built with `FramedReader::with_deadlines`.
+    pub frame_timeout: Option<Duration>,
+
+    /// Limits enforced by the typed (postcard/cbor/...) readers when decoding a frame.
+    pub limits: DecodeLimits,
+
+    /// Frames carry a compression flag byte, as written by a `FramedWriter`
+    /// with compression set. `max_frame_len` then bounds the decompressed size.
+    pub compressed: bool,
 }
 
 impl Default for ReaderConfig {
@@ -31,6 +69,73 @@ impl Default for ReaderConfig {
             max_frame_len: 64 * 1024,
             drain_on_small_buffer: true,
             drain_oversize_up_to: 0,
+            idle_timeout: None,
+            frame_timeout: None,
+            limits: DecodeLimits::default(),
+            compressed: false,
+        }
+    }
+}
+/// Bounds applied while decoding a typed frame, on top of `max_frame_len`.
+///
+/// A small frame can still declare huge collections or nest deeply enough to
+/// exhaust memory or stack inside the decoder; these limits are checked as
+/// the value is built and fail with `AbutCode::DecodeLimit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Maximum nesting of sequences, maps, structs and enum payloads.
+    pub max_depth: usize,
+
+    /// Maximum number of elements (or map entries) in any single collection.
+    pub max_collection_len: usize,
+
+    /// Approximate upper bound, in bytes, on memory the decoded value may own:
+    /// string and byte buffers plus `size_of` of every collection element.
+    pub max_alloc: usize,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: 64,
+            max_collection_len: 64 * 1024,
+            max_alloc: 16 * 1024 * 1024,
         }
     }
-}
\ No newline at end of file
+}
+
+/// What a bounded outbound queue does with a frame that does not fit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Wait until there is room.
+    #[default]
+    Block,
+    /// Evict the oldest queued frames to make room.
+    DropOldest,
+    /// Discard the frame being sent.
+    DropNewest,
+    /// Fail the send with `AbutCode::QueueFull`.
+    Error,
+}
+
+/// Capacity of a bounded outbound queue; a frame is admitted only if both limits hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueLimits {
+    pub max_frames: usize,
+    pub max_bytes: usize,
+}
+
+impl Default for QueueLimits {
+    fn default() -> Self {
+        Self { max_frames: 1024, max_bytes: 4 * 1024 * 1024 }
+    }
+}
+
+/// Which way a frame crosses the connection, seen from this process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Sent by the peer, received by us.
+    Inbound,
+    /// Sent by us to the peer.
+    Outbound,
+}
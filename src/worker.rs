@@ -0,0 +1,285 @@
+//! The bounded queue and sending thread behind
+//! [`QueuedWriter`](crate::frame::queued::QueuedWriter) and
+//! [`PriorityWriter`](crate::priority::PriorityWriter).
+//!
+//! Both writers let producers queue frames that one thread drains into a
+//! sink. They differ only in the order the queue hands out work, which is
+//! what a [`Backlog`] decides; limits, overflow, flushing, failure and
+//! shutdown live here.
+
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+
+use crate::frame::queued::QueueDepth;
+use crate::{AbutCode, AbutError, OverflowPolicy, QueueLimits};
+
+/// Frames waiting for the worker, in the order it should send them.
+pub(crate) trait Backlog: Send + 'static {
+    /// What a producer attaches to each frame besides its bytes.
+    type Tag;
+
+    /// Frames queued, including one that is partly sent.
+    fn frames(&self) -> usize;
+    /// Bytes of the frames counted by [`frames`](Self::frames).
+    fn bytes(&self) -> usize;
+    fn push(&mut self, tag: Self::Tag, data: Vec<u8>);
+    /// Discards the oldest frame not yet started; false if there is none.
+    fn evict(&mut self) -> bool;
+    /// Moves the next unit to send into `wire`; false if nothing is queued.
+    fn next(&mut self, wire: &mut Vec<u8>) -> bool;
+    fn clear(&mut self);
+}
+
+/// Where the worker sends what it takes from the backlog.
+pub(crate) trait Outlet: Send + 'static {
+    fn send(&mut self, wire: &[u8]) -> Result<(), AbutError>;
+
+    /// Called whenever a send leaves the backlog empty.
+    fn idle(&mut self) -> Result<(), AbutError> {
+        Ok(())
+    }
+}
+
+struct State<Q> {
+    backlog: Q,
+    dropped: u64,
+    busy: bool,
+    closed: bool,
+    /// First send error; the worker stops once it fails.
+    failed: Option<(AbutCode, String)>,
+}
+
+pub(crate) struct Shared<Q> {
+    state: Mutex<State<Q>>,
+    /// Signalled when a frame is queued or the writer is closed.
+    queued: Condvar,
+    /// Signalled when a send finishes or the worker stops.
+    drained: Condvar,
+    limits: QueueLimits,
+    policy: OverflowPolicy,
+    /// Names the writer in errors.
+    what: &'static str,
+}
+
+impl<Q> Shared<Q> {
+    fn lock(&self) -> MutexGuard<'_, State<Q>> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn wait<'a>(&self, cond: &Condvar, state: MutexGuard<'a, State<Q>>) -> MutexGuard<'a, State<Q>> {
+        cond.wait(state).unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn failure(&self, state: &State<Q>) -> Option<AbutError> {
+        let (code, msg) = state.failed.as_ref()?;
+        Some(AbutError::new(*code).ctx(format_args!("{} failed: {msg}", self.what)))
+    }
+
+    fn check(&self, state: &State<Q>) -> Result<(), AbutError> {
+        if let Some(e) = self.failure(state) {
+            return Err(e);
+        }
+        if state.closed {
+            return Err(AbutError::closed());
+        }
+        Ok(())
+    }
+}
+
+impl<Q: Backlog> Shared<Q> {
+    fn fits(&self, state: &State<Q>, len: usize) -> bool {
+        state.backlog.frames() < self.limits.max_frames && state.backlog.bytes() + len <= self.limits.max_bytes
+    }
+
+    /// Queues a frame, applying the overflow policy if the queue is full.
+    pub(crate) fn enqueue(&self, tag: Q::Tag, bytes: &[u8]) -> Result<(), AbutError> {
+        if bytes.len() > self.limits.max_bytes {
+            return Err(AbutError::new(AbutCode::QueueFull)
+                .ctx(format_args!("frame of {} bytes exceeds queue capacity {}", bytes.len(), self.limits.max_bytes)));
+        }
+        let mut state = self.lock();
+        self.check(&state)?;
+        while !self.fits(&state, bytes.len()) {
+            match self.policy {
+                OverflowPolicy::Block => {
+                    state = self.wait(&self.drained, state);
+                    self.check(&state)?;
+                }
+                OverflowPolicy::DropOldest if state.backlog.evict() => state.dropped += 1,
+                // With nothing older left to drop, the new frame goes instead.
+                OverflowPolicy::DropOldest | OverflowPolicy::DropNewest => {
+                    state.dropped += 1;
+                    return Ok(());
+                }
+                OverflowPolicy::Error => {
+                    return Err(AbutError::new(AbutCode::QueueFull).ctx(format_args!(
+                        "{} frames, {} bytes queued",
+                        state.backlog.frames(),
+                        state.backlog.bytes()
+                    )));
+                }
+            }
+        }
+        state.backlog.push(tag, bytes.to_vec());
+        self.queued.notify_one();
+        Ok(())
+    }
+
+    pub(crate) fn depth(&self) -> QueueDepth {
+        let state = self.lock();
+        QueueDepth { frames: state.backlog.frames(), bytes: state.backlog.bytes(), dropped: state.dropped }
+    }
+
+    /// Looks at the backlog under the lock.
+    pub(crate) fn backlog<T>(&self, f: impl FnOnce(&Q) -> T) -> T {
+        f(&self.lock().backlog)
+    }
+
+    fn run<O: Outlet>(&self, outlet: &mut O) {
+        let mut wire = Vec::new();
+        loop {
+            let mut state = self.lock();
+            while state.backlog.frames() == 0 && !state.closed {
+                state = self.wait(&self.queued, state);
+            }
+            if !state.backlog.next(&mut wire) {
+                break;
+            }
+            state.busy = true;
+            drop(state);
+
+            let mut result = outlet.send(&wire);
+            let mut state = self.lock();
+            if result.is_ok() && state.backlog.frames() == 0 {
+                drop(state);
+                result = outlet.idle();
+                state = self.lock();
+            }
+            state.busy = false;
+            if let Err(e) = result {
+                // Nothing queued can be sent any more.
+                state.failed = Some((e.code, e.to_string()));
+                state.backlog.clear();
+            }
+            let stop = state.failed.is_some();
+            self.drained.notify_all();
+            if stop {
+                break;
+            }
+        }
+    }
+}
+
+/// Owns the sending thread and the backlog feeding it.
+///
+/// Dropping it sends whatever is still queued, then joins the thread.
+pub(crate) struct Worker<Q, O> {
+    shared: Arc<Shared<Q>>,
+    thread: Option<JoinHandle<O>>,
+}
+
+impl<Q: Backlog, O: Outlet> Worker<Q, O> {
+    pub(crate) fn spawn(what: &'static str, backlog: Q, outlet: O, limits: QueueLimits, policy: OverflowPolicy) -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State { backlog, dropped: 0, busy: false, closed: false, failed: None }),
+            queued: Condvar::new(),
+            drained: Condvar::new(),
+            limits,
+            policy,
+            what,
+        });
+        let worker_shared = shared.clone();
+        let thread = std::thread::spawn(move || {
+            let mut outlet = outlet;
+            worker_shared.run(&mut outlet);
+            outlet
+        });
+        Self { shared, thread: Some(thread) }
+    }
+
+    pub(crate) fn shared(&self) -> &Arc<Shared<Q>> {
+        &self.shared
+    }
+
+    /// Blocks until everything queued has been sent.
+    pub(crate) fn flush(&self) -> Result<(), AbutError> {
+        let mut state = self.shared.lock();
+        while (state.backlog.frames() > 0 || state.busy) && state.failed.is_none() {
+            state = self.shared.wait(&self.shared.drained, state);
+        }
+        self.shared.failure(&state).map_or(Ok(()), Err)
+    }
+
+    /// Stops accepting frames, sends the rest and returns the outlet.
+    pub(crate) fn close(mut self) -> Result<O, AbutError> {
+        let outlet = self.stop();
+        if let Some(e) = self.shared.failure(&self.shared.lock()) {
+            return Err(e);
+        }
+        outlet.ok_or_else(|| AbutError::protocol(format_args!("{} thread panicked", self.shared.what)))
+    }
+}
+
+impl<Q, O> Worker<Q, O> {
+    fn stop(&mut self) -> Option<O> {
+        self.shared.lock().closed = true;
+        self.shared.queued.notify_all();
+        self.shared.drained.notify_all();
+        self.thread.take().and_then(|thread| thread.join().ok())
+    }
+}
+
+impl<Q, O> Drop for Worker<Q, O> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use std::io::{self, Write};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use crate::{AbutError, FrameSink};
+
+    /// A writer and sink whose every write waits until the test drops the
+    /// returned sender, so frames pile up in the queue in front of it.
+    pub(crate) struct Gate {
+        release: mpsc::Receiver<()>,
+        pub(crate) written: Vec<Vec<u8>>,
+    }
+
+    impl Gate {
+        pub(crate) fn new() -> (Self, mpsc::Sender<()>) {
+            let (tx, rx) = mpsc::channel();
+            (Self { release: rx, written: Vec::new() }, tx)
+        }
+    }
+
+    impl Write for Gate {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let _ = self.release.recv();
+            self.written.push(buf.to_vec());
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    impl FrameSink for Gate {
+        type Error = AbutError;
+        fn send_frame(&mut self, bytes: &[u8]) -> Result<(), AbutError> {
+            let _ = self.release.recv();
+            self.written.push(bytes.to_vec());
+            Ok(())
+        }
+    }
+
+    /// Waits until the worker has taken the frame queued in front of a
+    /// [`Gate`] and is parked on it.
+    pub(crate) fn parked(queued: impl Fn() -> usize) {
+        while queued() != 0 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
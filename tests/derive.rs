@@ -0,0 +1,130 @@
+#![cfg(all(feature = "derive", feature = "postcard"))]
+
+use std::os::unix::net::UnixStream;
+
+use abut::frame::postcard::Postcard;
+use abut::frame::{FramedReader, FramedWriter};
+use abut::router::{Message, UnknownPolicy, protocol};
+use abut::{AbutCode, AbutError};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Message)]
+#[message(id = 1)]
+struct Add(u32, u32);
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Message)]
+#[message(id = 2, version = 3)]
+struct Sum(u32);
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Message)]
+#[message(id = 7)]
+struct Note(String);
+
+#[protocol]
+trait Calc {
+    fn add(&mut self, req: Add) -> Result<Sum, AbutError>;
+    fn note(&mut self, ev: Note) -> Result<(), AbutError>;
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Message)]
+#[message(id = 20)]
+struct Ping;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Message)]
+#[message(id = 20)]
+struct Pong;
+
+// A reply may share its request's ID and answer more than one request.
+#[protocol]
+#[allow(dead_code)]
+trait Echo {
+    fn ping(&mut self, req: Ping) -> Result<Pong, AbutError>;
+    fn add(&mut self, req: Add) -> Result<Pong, AbutError>;
+}
+
+#[derive(Default)]
+struct Service {
+    notes: Vec<String>,
+}
+
+impl Calc for Service {
+    fn add(&mut self, Add(a, b): Add) -> Result<Sum, AbutError> {
+        Ok(Sum(a + b))
+    }
+
+    fn note(&mut self, Note(text): Note) -> Result<(), AbutError> {
+        self.notes.push(text);
+        Ok(())
+    }
+}
+
+#[test]
+fn derived_ids_and_versions() {
+    assert_eq!((Add::ID, Add::VERSION), (1, 1));
+    assert_eq!((Sum::ID, Sum::VERSION), (2, 3));
+    assert_eq!(<Service as Calc>::MESSAGES, &[(1, 1), (2, 3), (7, 1)]);
+}
+
+#[test]
+fn generated_client_talks_to_generated_router() {
+    let (client, server) = UnixStream::pair().unwrap();
+    let serving = std::thread::spawn(move || {
+        let router = Service::router::<Postcard>().unknown(UnknownPolicy::Reply);
+        let mut service = Service::default();
+        let mut r = FramedReader::new(&server);
+        let mut w = FramedWriter::new(&server);
+        let (mut frame, mut reply) = (Vec::new(), Vec::new());
+        while r.try_recv_into(&mut frame).unwrap() {
+            if router.dispatch(&mut service, &frame, &r.config().limits, &mut reply).unwrap() {
+                w.write_frame(&reply).unwrap();
+            }
+        }
+        service.notes
+    });
+
+    let mut calc = CalcClient::<Postcard, _, _>::new(FramedReader::new(&client), FramedWriter::new(&client));
+    calc.note(&Note("first".into())).unwrap();
+    assert_eq!(calc.add(&Add(40, 2)).unwrap(), Sum(42));
+
+    // A request the server does not route comes back as an unknown-ID reply.
+    let err = calc.0.call::<Sum, Sum>(&Sum(0)).unwrap_err();
+    assert_eq!(err.code, AbutCode::Protocol);
+
+    drop(calc);
+    client.shutdown(std::net::Shutdown::Both).unwrap();
+    assert_eq!(serving.join().unwrap(), ["first"]);
+}
+
+mod schema {
+    use abut::schema::{Schema, Schemas, shape};
+    use serde::{Deserialize, Serialize};
+
+    use super::Message;
+
+    #[derive(Serialize, Deserialize, Message, Schema)]
+    #[message(id = 10)]
+    struct Reading {
+        sensor: String,
+        value: Option<f64>,
+    }
+
+    #[derive(Serialize, Deserialize, Schema)]
+    enum Unit {
+        Celsius,
+        Scaled(u8),
+        Range { low: i32, high: i32 },
+    }
+
+    #[derive(Serialize, Deserialize, Schema)]
+    struct Wrapper<T>(Vec<T>);
+
+    #[test]
+    fn derived_fingerprints_match_hand_written() {
+        use abut::schema::name;
+        let expected = shape("struct", &[name("sensor"), String::FINGERPRINT, name("value"), <Option<f64>>::FINGERPRINT]);
+        assert_eq!(Reading::FINGERPRINT, expected);
+        assert_ne!(<Wrapper<u8>>::FINGERPRINT, <Wrapper<u16>>::FINGERPRINT);
+        assert_ne!(Unit::FINGERPRINT, <()>::FINGERPRINT);
+        assert!(Schemas::new().with::<Reading>().entries().all(|e| e.id == 10 && e.fingerprint == expected));
+    }
+}
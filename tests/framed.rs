@@ -24,7 +24,7 @@
 // 
 use std::io::Cursor;
 
-use abut::{ReaderConfig, frame::{FramedReader, FramedWriter}};
+use abut::{AbutCode, ReaderConfig, frame::{FramedReader, FramedWriter}};
 
 #[test]
 fn roundtrip_one_frame() {
@@ -84,3 +84,106 @@ fn oversize_does_not_drain_by_default() {
     let e = r.read_frame(&mut buf).unwrap_err();
     assert!(format!("{e}").contains("Frame too large"));
 }
+
+#[test]
+fn socket_close_between_and_within_frames() {
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+
+    let (a, b) = UnixStream::pair().unwrap();
+    {
+        let mut w = FramedWriter::new(&a);
+        w.write_frame(b"bye").unwrap();
+    }
+    drop(a);
+
+    let mut r = FramedReader::new(&b);
+    let mut buf = Vec::new();
+    r.recv_into(&mut buf).unwrap();
+    assert_eq!(r.recv_into(&mut buf).unwrap_err().code, AbutCode::Closed);
+
+    let (mut a, b) = UnixStream::pair().unwrap();
+    a.write_all(&[0x10, 0x00, 0x00, 0x00, b'x']).unwrap();
+    drop(a);
+
+    let mut r = FramedReader::new(&b);
+    assert_eq!(r.recv_into(&mut buf).unwrap_err().code, AbutCode::TruncatedFrame);
+}
+
+#[test]
+fn idle_and_frame_deadlines() {
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+    use std::time::{Duration, Instant};
+
+    let (mut a, b) = UnixStream::pair().unwrap();
+    let cfg = ReaderConfig {
+        idle_timeout: Some(Duration::from_millis(50)),
+        frame_timeout: Some(Duration::from_millis(80)),
+        ..Default::default()
+    };
+    let mut r = FramedReader::with_deadlines(&b, cfg);
+    let mut buf = Vec::new();
+
+    // Nothing arrives: idle deadline, stream still aligned.
+    let e = r.recv_into(&mut buf).unwrap_err();
+    assert_eq!(e.code, AbutCode::Timeout);
+    assert_eq!(e.stream_aligned(), Some(true));
+
+    // A whole frame still reads fine afterwards.
+    FramedWriter::new(&a).write_frame(b"ok").unwrap();
+    r.recv_into(&mut buf).unwrap();
+    assert_eq!(buf, b"ok");
+
+    // The peer sends a prefix and trickles the body: frame deadline, stream misaligned.
+    a.write_all(&[0x08, 0x00, 0x00, 0x00, b'x']).unwrap();
+    let started = Instant::now();
+    let e = r.recv_into(&mut buf).unwrap_err();
+    assert_eq!(e.code, AbutCode::Timeout);
+    assert_eq!(e.stream_aligned(), Some(false));
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn deadlines_restore_socket_read_timeout() {
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    let (a, b) = UnixStream::pair().unwrap();
+    let own = Some(Duration::from_secs(30));
+    b.set_read_timeout(own).unwrap();
+    let cfg = ReaderConfig { idle_timeout: Some(Duration::from_millis(50)), ..Default::default() };
+    let mut r = FramedReader::with_deadlines(&b, cfg);
+    let mut buf = Vec::new();
+
+    assert_eq!(r.recv_into(&mut buf).unwrap_err().code, AbutCode::Timeout);
+    assert_eq!(b.read_timeout().unwrap(), own);
+    FramedWriter::new(&a).write_frame(b"ok").unwrap();
+    r.recv_into(&mut buf).unwrap();
+    assert_eq!(b.read_timeout().unwrap(), own);
+
+    // Without deadlines, the socket's own timeout firing is a timeout too.
+    b.set_read_timeout(Some(Duration::from_millis(20))).unwrap();
+    let e = FramedReader::new(&b).recv_into(&mut buf).unwrap_err();
+    assert_eq!((e.code, e.stream_aligned()), (AbutCode::Timeout, Some(true)));
+}
+
+#[test]
+fn send_timeout_on_stalled_peer() {
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    let (a, _b) = UnixStream::pair().unwrap();
+    let own = Some(Duration::from_secs(30));
+    a.set_write_timeout(own).unwrap();
+    let mut w = FramedWriter::with_send_timeout(&a, Duration::from_millis(50));
+    w.write_frame(b"fits").unwrap();
+    assert_eq!(a.write_timeout().unwrap(), own);
+
+    // Nobody reads `_b`, so a large frame fills the socket buffer part-way.
+    let e = w.write_frame(&vec![0u8; 8 * 1024 * 1024]).unwrap_err();
+    assert_eq!(e.code, AbutCode::Timeout);
+    assert_eq!(e.stream_aligned(), Some(false));
+    // The socket's own timeout is back even though the frame failed.
+    assert_eq!(a.write_timeout().unwrap(), own);
+}